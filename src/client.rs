@@ -1,7 +1,12 @@
+use std::fs;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
 use std::thread;
 use std::time;
+use structopt::StructOpt;
 use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufReader;
 use tokio::net::TcpListener;
@@ -12,18 +17,188 @@ mod models;
 
 use crate::configs::client::*;
 use crate::configs::server::START_PORT;
+use crate::models::export::{self, Format, CSV_HEADER};
+use crate::models::fault::*;
 use crate::models::kv::*;
 use crate::models::msg::*;
 use crate::models::package::*;
 
+//without a subcommand the client asks for commands interactively
+#[derive(StructOpt)]
+struct Args {
+    #[structopt(subcommand)]
+    tool: Option<Tool>,
+}
+
+#[derive(StructOpt)]
+enum Tool {
+    //write every key of a node with its revisions to a file, or to stdout
+    Export {
+        #[structopt(long)]
+        pid: u64,
+        #[structopt(long, default_value = "jsonl")]
+        format: Format,
+        #[structopt(long, parse(from_os_str))]
+        out: Option<PathBuf>,
+    },
+    //put the keys of a file in batches, from the `offset`th key of the file on
+    Import {
+        #[structopt(long)]
+        pid: u64,
+        #[structopt(long, default_value = "jsonl")]
+        format: Format,
+        #[structopt(long, default_value = "0")]
+        offset: usize,
+        #[structopt(long)]
+        batch: Option<usize>,
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
 #[tokio::main]
 async fn main() {
-    //create client tasks
-    let listener_task = listen_thread();
-    let commander_task = command_thread();
+    match Args::from_args().tool {
+        Some(Tool::Export { pid, format, out }) => process::exit(export(pid, format, out).await),
+        Some(Tool::Import {
+            pid,
+            format,
+            offset,
+            batch,
+            file,
+        }) => {
+            let batch = batch.unwrap_or(IMPORT_BATCH).max(1);
+            process::exit(import(pid, format, offset, batch, file).await)
+        }
+        None => {
+            //create client tasks
+            let listener_task = listen_thread();
+            let commander_task = command_thread();
+
+            //execute all tasks in parallel.
+            tokio::join!(listener_task, commander_task);
+        }
+    }
+}
+
+//export every key of a node from a single read, so the keys are consistent at the
+//index the node applied
+async fn export(pid: u64, format: Format, out: Option<PathBuf>) -> i32 {
+    let listener = TcpListener::bind(CLIENT_ADDR).await.unwrap();
+    let reply = match request(&listener, pid, admin(Operation::Export)).await {
+        Some(reply) => reply,
+        None => {
+            eprintln!("No reply from node {}", pid);
+            return 1;
+        }
+    };
+    let mut lines = reply.lines();
+    let status = lines.next().unwrap_or_default();
+    if !status.starts_with("Successfully") {
+        eprintln!("{}", status);
+        return 1;
+    }
+    let mut dump = String::new();
+    if format == Format::Csv {
+        dump += CSV_HEADER;
+        dump.push('\n');
+    }
+    for line in lines {
+        let row = export::read(Format::Jsonl, line).unwrap();
+        dump += &export::write(format, &row);
+        dump.push('\n');
+    }
+    match out {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, dump) {
+                eprintln!("Failed to write {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+        None => print!("{}", dump),
+    }
+    eprintln!("{}", status);
+    0
+}
+
+//import the keys of a file with one MultiPut per batch, a batch that fails is put
+//again when the import is resumed from the offset it reports
+async fn import(pid: u64, format: Format, offset: usize, batch: usize, file: PathBuf) -> i32 {
+    let text = match fs::read_to_string(&file) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", file.display(), e);
+            return 1;
+        }
+    };
+    let mut kvs = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let header = format == Format::Csv && n == 0 && line.starts_with("key,value");
+        if header || line.trim().is_empty() {
+            continue;
+        }
+        match export::read(format, line) {
+            Ok(row) => kvs.push(row.kv()),
+            Err(e) => {
+                eprintln!("Line {} of {}: {}", n + 1, file.display(), e);
+                return 1;
+            }
+        }
+    }
+
+    let listener = TcpListener::bind(CLIENT_ADDR).await.unwrap();
+    let total = kvs.len();
+    let mut done = offset.min(total);
+    for kvs in kvs[done..].chunks(batch) {
+        let msg = admin(Operation::MultiPut { kvs: kvs.to_vec() });
+        let reply = request(&listener, pid, msg).await;
+        if reply.as_deref() != Some("Successfully to put values") {
+            let reply = reply.unwrap_or_else(|| "no reply".to_string());
+            eprintln!("Failed to import the keys from offset {}: {}", done, reply);
+            eprintln!("Resume with --offset {}", done);
+            return 1;
+        }
+        done += kvs.len();
+        eprintln!("Imported {}/{} keys, offset {}", done, total, done);
+    }
+    0
+}
+
+//send a command to a node and wait for its whole reply
+async fn request(listener: &TcpListener, pid: u64, msg: CMDMessage) -> Option<String> {
+    let pkg = Package {
+        types: Types::CMD,
+        msg: Msg::CMD(msg),
+    };
+    let bytes = serde_json::to_string(&pkg).unwrap();
+    let mut tcp_stream = TcpStream::connect(node_addr(pid)).await.ok()?;
+    tcp_stream.write_all(bytes.as_bytes()).await.ok()?;
+    drop(tcp_stream);
+
+    let timeout = time::Duration::from_millis(REPLY_TIMEOUT);
+    let (mut socket, _) = tokio::time::timeout(timeout, listener.accept())
+        .await
+        .ok()?
+        .ok()?;
+    let mut reply = String::new();
+    let read = socket.read_to_string(&mut reply);
+    tokio::time::timeout(timeout, read).await.ok()?.ok()?;
+    Some(reply)
+}
+
+//a command that only carries its operation
+fn admin(operation: Operation) -> CMDMessage {
+    CMDMessage {
+        operation,
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
 
-    //execute all tasks in parallel.
-    tokio::join!(listener_task, commander_task);
+fn node_addr(pid: u64) -> SocketAddr {
+    format!("127.0.0.1:{}", START_PORT + pid).parse().unwrap()
 }
 
 // the listener thread, receive message from server and print it
@@ -32,16 +207,20 @@ async fn listen_thread() {
         let tcp_listener = TcpListener::bind(CLIENT_ADDR).await.unwrap();
         loop {
             let (mut socket, _) = tcp_listener.accept().await.unwrap();
-            let (read, _) = socket.split();
-            let mut reader = BufReader::new(read);
-            let mut buffer = String::new();
-            loop {
-                let line = reader.read_line(&mut buffer).await.unwrap();
-                if line == 0 {
-                    break;
+            //a watch keeps its connection open, so every connection is read on its own
+            tokio::spawn(async move {
+                let (read, _) = socket.split();
+                let mut reader = BufReader::new(read);
+                let mut buffer = String::new();
+                loop {
+                    let line = reader.read_line(&mut buffer).await.unwrap();
+                    if line == 0 {
+                        break;
+                    }
+                    println!("Server: {}", buffer.trim_end());
+                    buffer.clear();
                 }
-                println!("Server: {}", &&buffer);
-            }
+            });
         }
     });
 }
@@ -59,18 +238,33 @@ async fn command_thread() {
 
         //create node's address
         let p: u64 = input.trim().parse().ok().expect("Parse Error");
-        let mut addr = "127.0.0.1:".to_string();
-        let port = START_PORT + p;
-        addr += &port.to_string();
-        let addr: SocketAddr = addr.parse().unwrap();
+        let addr = node_addr(p);
 
         //choose function
         loop {
             println!("---------------------------");
-            println!("Please choose your command [input number 1/2/3]:");
+            println!("Please choose your command [input number 1-21]:");
             println!("1.Get");
             println!("2.Put");
             println!("3.Snap");
+            println!("4.Leader");
+            println!("5.Fault");
+            println!("6.TransferLeader");
+            println!("7.Cas");
+            println!("8.Incr");
+            println!("9.Decr");
+            println!("10.Txn");
+            println!("11.MultiGet");
+            println!("12.MultiPut");
+            println!("13.Scan");
+            println!("14.Prefix");
+            println!("15.Watch");
+            println!("16.Lease");
+            println!("17.Lock");
+            println!("18.Unlock");
+            println!("19.GetAt");
+            println!("20.SnapshotAt");
+            println!("21.Backup");
 
             let mut input = String::new();
             std::io::stdin().read_line(&mut input).expect("msg");
@@ -81,6 +275,24 @@ async fn command_thread() {
                 "1" => get(),
                 "2" => put(),
                 "3" => snap(),
+                "4" => leader(),
+                "5" => fault(),
+                "6" => transfer_leader(),
+                "7" => cas(),
+                "8" => count(Operation::Incr),
+                "9" => count(Operation::Decr),
+                "10" => txn(),
+                "11" => multi_get(),
+                "12" => multi_put(),
+                "13" => scan(),
+                "14" => prefix(),
+                "15" => watch(),
+                "16" => lease(),
+                "17" => lock(false),
+                "18" => lock(true),
+                "19" => get_at(),
+                "20" => snapshot_at(),
+                "21" => backup(),
                 _ => {
                     println!("Invalid command");
                     continue;
@@ -127,15 +339,56 @@ fn get() -> CMDMessage {
     }
 }
 
+//get at index function, the value as it was once the entry at the index was applied
+fn get_at() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the key and the log index [eg. A 7]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("GetAt Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    CMDMessage {
+        operation: Operation::GetAt {
+            at_index: args[1].parse::<u64>().ok().expect("Error"),
+        },
+        kv: KeyValue {
+            key: args[0].to_string(),
+            value: 0,
+        },
+    }
+}
+
+//snapshot at index function, every key as it was once the entry at the index was applied
+fn snapshot_at() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the log index [eg. 7]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("SnapshotAt Error");
+    CMDMessage {
+        operation: Operation::SnapshotAt {
+            at_index: input.trim().parse::<u64>().ok().expect("Error"),
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
 //put function
 fn put() -> CMDMessage {
     println!("---------------------------");
-    println!("Please enter the key and value [eg. A 10]:");
+    println!("Please enter the key, value and an optional ttl in ms [eg. A 10 or A 10 5000]:");
     let mut input = String::new();
     std::io::stdin().read_line(&mut input).expect("Put Error");
     let kv: Vec<&str> = input.trim().split(" ").collect();
+    let operation = match kv.get(2) {
+        Some(ttl) => Operation::PutTtl {
+            ttl: ttl.parse::<u64>().ok().expect("Error"),
+        },
+        None => Operation::Put,
+    };
     CMDMessage {
-        operation: Operation::Put,
+        operation,
         kv: KeyValue {
             key: kv[0].to_string(),
             value: kv[1].parse::<u64>().ok().expect("Error"),
@@ -143,6 +396,264 @@ fn put() -> CMDMessage {
     }
 }
 
+//multi get function
+fn multi_get() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the keys [eg. A B C]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("MultiGet Error");
+    CMDMessage {
+        operation: Operation::MultiGet {
+            keys: input.split_whitespace().map(|k| k.to_string()).collect(),
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
+//multi put function
+fn multi_put() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the keys and values [eg. A 10 B 20]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("MultiPut Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    CMDMessage {
+        operation: Operation::MultiPut {
+            kvs: args
+                .chunks(2)
+                .map(|kv| KeyValue {
+                    key: kv[0].to_string(),
+                    value: kv[1].parse::<u64>().ok().expect("Error"),
+                })
+                .collect(),
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
+//scan function
+fn scan() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the start, end, limit and the token of the next page [eg. A C 10]:");
+    println!("Enter - as end to scan until the last key, 0 as limit for no limit");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Scan Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    CMDMessage {
+        operation: Operation::Scan {
+            start: args[0].to_string(),
+            end: match args[1] {
+                "-" => None,
+                end => Some(end.to_string()),
+            },
+            limit: args[2].parse::<usize>().ok().expect("Error"),
+            token: args.get(3).map(|t| t.to_string()),
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
+//prefix function
+fn prefix() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the prefix, limit and the token of the next page [eg. user/ 10]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Prefix Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    CMDMessage {
+        operation: Operation::Prefix {
+            prefix: args[0].to_string(),
+            limit: args[1].parse::<usize>().ok().expect("Error"),
+            token: args.get(2).map(|t| t.to_string()),
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
+//watch function, the changes are printed by the listener thread as they arrive
+fn watch() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the key, or a prefix ending with *, and the revision to start from [eg. A 0 or user/* 0]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Watch Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    let (key, prefix) = match args[0].strip_suffix('*') {
+        Some(prefix) => (prefix, true),
+        None => (args[0], false),
+    };
+    CMDMessage {
+        operation: Operation::Watch {
+            prefix,
+            from_revision: args[1].parse::<u64>().ok().expect("Error"),
+        },
+        kv: KeyValue {
+            key: key.to_string(),
+            value: 0,
+        },
+    }
+}
+
+//lease function, a lease has to be kept alive within its ttl
+fn lease() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter grant ttl(ms), keepalive lease, revoke lease or put key value lease");
+    println!("[eg. grant 5000, keepalive 7, revoke 7 or put A 10 7]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Lease Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    let number = |i: usize| args[i].parse::<u64>().ok().expect("Error");
+    let (operation, key, value) = match args[0] {
+        "grant" => (Operation::Grant { ttl: number(1) }, "_", 0),
+        "keepalive" => (Operation::KeepAlive { lease: number(1) }, "_", 0),
+        "revoke" => (Operation::Revoke { lease: number(1) }, "_", 0),
+        _ => (Operation::PutLease { lease: number(3) }, args[1], number(2)),
+    };
+    CMDMessage {
+        operation,
+        kv: KeyValue {
+            key: key.to_string(),
+            value,
+        },
+    }
+}
+
+//lock and unlock function, the lock is held by a lease
+fn lock(unlock: bool) -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the name of the lock and the lease [eg. job 7]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Lock Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    let lease = args[1].parse::<u64>().ok().expect("Error");
+    CMDMessage {
+        operation: match unlock {
+            true => Operation::Unlock { lease },
+            false => Operation::Lock { lease },
+        },
+        kv: KeyValue {
+            key: args[0].to_string(),
+            value: 0,
+        },
+    }
+}
+
+//compare and swap function
+fn cas() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the key, expected value and new value [eg. A 10 11]:");
+    println!("Enter - as expected value if the key should not exist [eg. A - 1]");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Cas Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    let expected = match args[1] {
+        "-" => None,
+        v => Some(v.parse::<u64>().ok().expect("Error")),
+    };
+    CMDMessage {
+        operation: Operation::Cas { expected },
+        kv: KeyValue {
+            key: args[0].to_string(),
+            value: args[2].parse::<u64>().ok().expect("Error"),
+        },
+    }
+}
+
+//increment and decrement function
+fn count(operation: Operation) -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the key and delta [eg. A 1]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Count Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    CMDMessage {
+        operation,
+        kv: KeyValue {
+            key: args[0].to_string(),
+            value: args[1].parse::<u64>().ok().expect("Error"),
+        },
+    }
+}
+
+//transaction function
+fn txn() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the conditions [eg. A=10 B? !C D#2 E@7]:");
+    println!("A=10: A is 10, B?: B exists, !C: C does not exist, D#2: D is at version 2,");
+    println!("E@7: E was last changed at revision 7");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Txn Error");
+    let conditions = input
+        .split_whitespace()
+        .map(|c| {
+            if let Some((key, value)) = c.split_once('=') {
+                Condition::Equals {
+                    key: key.to_string(),
+                    value: value.parse::<u64>().ok().expect("Error"),
+                }
+            } else if let Some((key, version)) = c.split_once('#') {
+                Condition::Version {
+                    key: key.to_string(),
+                    version: version.parse::<u64>().ok().expect("Error"),
+                }
+            } else if let Some((key, revision)) = c.split_once('@') {
+                Condition::ModRevision {
+                    key: key.to_string(),
+                    revision: revision.parse::<u64>().ok().expect("Error"),
+                }
+            } else if let Some(key) = c.strip_suffix('?') {
+                Condition::Exists {
+                    key: key.to_string(),
+                    exists: true,
+                }
+            } else {
+                Condition::Exists {
+                    key: c.trim_start_matches('!').to_string(),
+                    exists: false,
+                }
+            }
+        })
+        .collect();
+
+    println!("Please enter the puts and deletes [eg. A=11 -B]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Txn Error");
+    let mutations = input
+        .split_whitespace()
+        .map(|m| match m.split_once('=') {
+            Some((key, value)) => Mutation::Put(KeyValue {
+                key: key.to_string(),
+                value: value.parse::<u64>().ok().expect("Error"),
+            }),
+            None => Mutation::Delete {
+                key: m.trim_start_matches('-').to_string(),
+            },
+        })
+        .collect();
+
+    CMDMessage {
+        operation: Operation::Txn {
+            conditions,
+            mutations,
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
 //snap function
 fn snap() -> CMDMessage {
     CMDMessage {
@@ -154,6 +665,86 @@ fn snap() -> CMDMessage {
     }
 }
 
+//backup function, the file is written on the node
+fn backup() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the path of the backup file [eg. /tmp/kv.backup]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Backup Error");
+    CMDMessage {
+        operation: Operation::Backup {
+            path: input.trim().to_string(),
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
+//leader function
+fn leader() -> CMDMessage {
+    CMDMessage {
+        operation: Operation::Leader,
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
+//fault function
+fn fault() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter drop% delay(ms) duplicate% reorder% and the partition [eg. 10 0 0 0 1,2|3]:");
+    println!("Enter nothing to heal all faults of the node");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Fault Error");
+    let args: Vec<&str> = input.split_whitespace().collect();
+    let mut config = FaultConfig::default();
+    if !args.is_empty() {
+        config.drop = args[0].parse::<u64>().ok().expect("Error");
+        config.delay = args[1].parse::<u64>().ok().expect("Error");
+        config.duplicate = args[2].parse::<u64>().ok().expect("Error");
+        config.reorder = args[3].parse::<u64>().ok().expect("Error");
+        if let Some(partition) = args.get(4) {
+            config.partition = partition
+                .split('|')
+                .map(|group| {
+                    group
+                        .split(',')
+                        .map(|pid| pid.parse::<u64>().ok().expect("Error"))
+                        .collect()
+                })
+                .collect();
+        }
+    }
+    CMDMessage {
+        operation: Operation::Fault(config),
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
+//transfer leader function
+fn transfer_leader() -> CMDMessage {
+    println!("---------------------------");
+    println!("Please enter the pid of the new leader [eg. 2]:");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).expect("Transfer Error");
+    CMDMessage {
+        operation: Operation::TransferLeader {
+            to: input.trim().parse::<u64>().ok().expect("Error"),
+        },
+        kv: KeyValue {
+            key: String::from("_"),
+            value: 0,
+        },
+    }
+}
+
 //log printer
 fn print_log(log: String) {
     if DEBUG_OUTPUT {
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use omnipaxos_runtime::omnipaxos::OmniPaxosNode;
+use tokio::time;
+
+use crate::configs::server::{APPLY_INTERVAL, SNAPSHOT_BYTES, SNAPSHOT_ENTRIES, SNAPSHOT_INTERVAL};
+use crate::models::kv::{KVSnapshot, LogEntry};
+use crate::models::node::Node;
+use crate::models::storage::StateStore;
+use crate::print_log;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+
+//the log is compacted into a snapshot once `entries` entries or `bytes` bytes were
+//applied since the last snapshot, or `interval` ms passed, a threshold of 0 is off
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SnapshotPolicy {
+    pub entries: u64,
+    pub bytes: u64,
+    pub interval: u64,
+}
+
+impl SnapshotPolicy {
+    //the thresholds given to the node, the configured ones otherwise
+    pub fn new(node: &Node) -> Self {
+        SnapshotPolicy {
+            entries: node.snapshot_entries.unwrap_or(SNAPSHOT_ENTRIES),
+            bytes: node.snapshot_bytes.unwrap_or(SNAPSHOT_BYTES),
+            interval: node.snapshot_interval.unwrap_or(SNAPSHOT_INTERVAL),
+        }
+    }
+
+    //a snapshot is only due if there is something to compact
+    fn due(&self, entries: u64, bytes: u64, elapsed: Duration) -> bool {
+        let reached = |threshold: u64, value: u64| threshold > 0 && value >= threshold;
+        entries > 0
+            && (reached(self.entries, entries)
+                || reached(self.bytes, bytes)
+                || reached(self.interval, elapsed.as_millis() as u64))
+    }
+}
+
+//the leader compacts the log of every replica once the policy says so, a round that
+//fails, e.g. because a follower did not decide the index yet, is tried again later;
+//every node also saves its state machine, with a data directory to disk, so it restarts
+//from there instead of replaying the whole log
+pub(crate) async fn snapshot_thread(
+    store: &Store,
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    pid: u64,
+    policy: SnapshotPolicy,
+    state: &dyn StateStore,
+    mut stopped: Shutdown,
+) {
+    loop {
+        tokio::select! {
+            _ = time::sleep(Duration::from_millis(APPLY_INTERVAL)) => {}
+            _ = stopped.wait() => break,
+        }
+        let (entries, bytes, elapsed) = store.uncompacted();
+        if !policy.due(entries, bytes, elapsed) {
+            continue;
+        }
+        if op.get_current_leader().await == pid {
+            match store.compact(op).await {
+                Some(index) => print_log(format!("Log is compacted up to index {}", index)),
+                None => {
+                    print_log(format!("Failed to compact the log"));
+                    continue;
+                }
+            }
+        }
+        //the saved state is the checkpoint of the round on every node, so a follower
+        //counts again from here and only asks for the leader once the next one is due
+        save(store, state, pid);
+    }
+}
+
+//save the state of the node
+pub(crate) fn save(store: &Store, state: &dyn StateStore, pid: u64) {
+    let snapshot = store.checkpoint();
+    match state.save(&snapshot) {
+        Ok(_) => print_log(format!("Snapshot up to index {} is saved", snapshot.len)),
+        Err(e) => println!("Node {} failed to save its snapshot: {}", pid, e),
+    }
+}
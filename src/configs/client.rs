@@ -4,3 +4,9 @@ pub(crate) const CLIENT_ADDR: &str = "127.0.0.1:12345";
 
 //enable debug mode or not
 pub(crate) const DEBUG_OUTPUT: bool = false;
+
+//the number of keys an import puts in one batch
+pub(crate) const IMPORT_BATCH: usize = 500;
+
+//the time an export or an import waits for the reply of the node, in ms
+pub(crate) const REPLY_TIMEOUT: u64 = 5000;
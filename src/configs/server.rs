@@ -4,3 +4,75 @@ pub(crate) const START_PORT: u64 = 11000;
 
 //enable debug mode or not
 pub(crate) const DEBUG_OUTPUT: bool = false;
+
+//latency of the simulated network is SIM_LATENCY plus up to SIM_JITTER ms
+pub(crate) const SIM_LATENCY: u64 = 2;
+pub(crate) const SIM_JITTER: u64 = 20;
+
+//the simulated workload: number of operations, number of distinct keys,
+//and the virtual time (ms) to wait before, during and after it
+pub(crate) const SIM_OPS: u64 = 50;
+pub(crate) const SIM_KEYS: u64 = 5;
+pub(crate) const SIM_WARMUP: u64 = 2000;
+pub(crate) const SIM_REPLY_TIMEOUT: u64 = 5000;
+pub(crate) const SIM_SETTLE: u64 = 2000;
+
+//packages reordered by the fault layer are held back up to REORDER_WINDOW ms
+pub(crate) const REORDER_WINDOW: u64 = 50;
+
+//the history scenario: number of concurrent clients, and the longest time (ms)
+//a node stays up or down between restarts
+pub(crate) const SIM_CLIENTS: u64 = 4;
+pub(crate) const SIM_RESTART: u64 = 3000;
+
+//time (ms) a node has to drain its in-flight commands after SIGINT or SIGTERM
+pub(crate) const SHUTDOWN_TIMEOUT: u64 = 5000;
+
+//a leader transfer steers the election for TRANSFER_WINDOW ms and
+//fails if the target is not leader after TRANSFER_TIMEOUT ms
+pub(crate) const TRANSFER_WINDOW: u64 = 1000;
+pub(crate) const TRANSFER_TIMEOUT: u64 = 3000;
+pub(crate) const TRANSFER_POLL: u64 = 50;
+
+//the decided log is applied to the key-value store every APPLY_INTERVAL ms, a proposal
+//fails if it is not applied after PROPOSAL_TIMEOUT ms
+pub(crate) const APPLY_INTERVAL: u64 = 10;
+pub(crate) const PROPOSAL_TIMEOUT: u64 = 3000;
+
+//the number of changes a node keeps, so a watch can resume from a revision up to
+//WATCH_HISTORY changes back
+pub(crate) const WATCH_HISTORY: usize = 1000;
+
+//the leader compacts the log into a snapshot once SNAPSHOT_ENTRIES entries or
+//SNAPSHOT_BYTES bytes were applied since the last one, or SNAPSHOT_INTERVAL ms passed,
+//0 turns a threshold off
+pub(crate) const SNAPSHOT_ENTRIES: u64 = 1000;
+pub(crate) const SNAPSHOT_BYTES: u64 = 1 << 20;
+pub(crate) const SNAPSHOT_INTERVAL: u64 = 60000;
+
+//a segment of the write-ahead log of a node with a data directory is closed once it
+//holds WAL_SEGMENT_BYTES bytes
+pub(crate) const WAL_SEGMENT_BYTES: u64 = 16 << 20;
+
+//the lsm storage writes its entries in memory to a sorted run once it holds
+//LSM_MEMTABLE_ENTRIES of them, and merges its runs once it has more than LSM_MAX_RUNS
+pub(crate) const LSM_MEMTABLE_ENTRIES: usize = 4096;
+pub(crate) const LSM_MAX_RUNS: usize = 4;
+
+//the compaction scenario snapshots every SIM_SNAPSHOT_ENTRIES entries
+pub(crate) const SIM_SNAPSHOT_ENTRIES: u64 = 5;
+
+//the chunks scenario writes batches of SIM_BULK_KEYS keys, too large for one package
+pub(crate) const SIM_BULK_KEYS: u64 = 4000;
+
+//an SP message longer than TRANSFER_CHUNK bytes is sent in chunks of that size, up to
+//TRANSFER_WINDOW_CHUNKS at a time; the window is sent again from the first chunk the
+//receiver misses if it is not acknowledged within TRANSFER_ACK_TIMEOUT ms, and the
+//transfer is given up after TRANSFER_RETRIES rounds without progress
+pub(crate) const TRANSFER_CHUNK: usize = 64 * 1024;
+pub(crate) const TRANSFER_WINDOW_CHUNKS: u64 = 8;
+pub(crate) const TRANSFER_ACK_TIMEOUT: u64 = 500;
+pub(crate) const TRANSFER_RETRIES: u64 = 20;
+//a transfer no chunk came in for since TRANSFER_EXPIRY ms is dropped by the receiver,
+//the sender has given up on it by then
+pub(crate) const TRANSFER_EXPIRY: u64 = TRANSFER_ACK_TIMEOUT * TRANSFER_RETRIES;
@@ -0,0 +1,91 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::configs::server::{LSM_MAX_RUNS, LSM_MEMTABLE_ENTRIES, WAL_SEGMENT_BYTES};
+use crate::models::kv::KVSnapshot;
+use crate::models::node::Node;
+use crate::models::persist::{self, Backup};
+use crate::models::storage::{
+    invalid, write_atomic, Engine, SnapshotFile, StateStore, StorageOptions,
+};
+
+//where and in which engine the log storage of a node is kept, the snapshots are kept in
+//the data directory whatever the engine
+#[derive(Clone, Debug)]
+pub(crate) struct StorageConfig {
+    pub engine: Engine,
+    pub dir: Option<PathBuf>,
+}
+
+impl StorageConfig {
+    pub fn new(node: &Node) -> Self {
+        let engine = match (node.storage, &node.data_dir) {
+            (Some(engine), _) => engine,
+            (None, Some(_)) => Engine::File,
+            (None, None) => Engine::Memory,
+        };
+        StorageConfig {
+            engine,
+            dir: node.data_dir.clone(),
+        }
+    }
+}
+
+pub(crate) fn storage_options() -> StorageOptions {
+    StorageOptions {
+        segment_bytes: WAL_SEGMENT_BYTES,
+        memtable_entries: LSM_MEMTABLE_ENTRIES,
+        max_runs: LSM_MAX_RUNS,
+    }
+}
+
+//the file the last snapshot of a node is kept in
+pub(crate) fn snapshot_path(dir: &Path, pid: u64) -> PathBuf {
+    dir.join(format!("node{}.snap", pid))
+}
+
+//the directory of the log storage of a node in an engine, the memory storage has none
+pub(crate) fn storage_path(dir: &Path, pid: u64, engine: Engine) -> Option<PathBuf> {
+    match engine {
+        Engine::Memory => None,
+        Engine::File => Some(dir.join(format!("node{}.wal", pid))),
+        Engine::Lsm => Some(dir.join(format!("node{}.lsm", pid))),
+    }
+}
+
+//write the snapshot of a node to its snapshot file
+pub(crate) fn save_snapshot(dir: &Path, pid: u64, snapshot: &KVSnapshot) -> io::Result<()> {
+    SnapshotFile::new(&snapshot_path(dir, pid)).save(snapshot)
+}
+
+//move a corrupted snapshot out of the way, so the node starts without it and the next
+//snapshot is written in its place
+pub(crate) fn discard_snapshot(dir: &Path, pid: u64) -> io::Result<PathBuf> {
+    let path = dir.join(format!("node{}.snap.corrupt", pid));
+    fs::rename(snapshot_path(dir, pid), &path)?;
+    Ok(path)
+}
+
+//write a backup to `path` the same way, the directory has to exist
+pub(crate) fn save_backup(path: &Path, backup: &Backup) -> io::Result<()> {
+    write_atomic(path, &persist::encode_backup(backup))
+}
+
+pub(crate) fn load_backup(path: &Path) -> io::Result<Backup> {
+    persist::decode_backup(&fs::read(path)?).map_err(|e| invalid("backup", e))
+}
+
+//seed the data directory of a node with the snapshot of a backup, so the node starts
+//from it, an existing snapshot is never overwritten
+pub(crate) fn restore_backup(path: &Path, dir: &Path, pid: u64) -> io::Result<Backup> {
+    let backup = load_backup(path)?;
+    let engines = [Engine::File, Engine::Lsm];
+    let mut logs = engines.iter().filter_map(|e| storage_path(dir, pid, *e));
+    if snapshot_path(dir, pid).exists() || logs.any(|path| path.exists()) {
+        let msg = format!("{} already holds the state of node {}", dir.display(), pid);
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+    }
+    save_snapshot(dir, pid, &backup.snapshot)?;
+    Ok(backup)
+}
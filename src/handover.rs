@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use omnipaxos_core::ballot_leader_election::messages::{BLEMessage, HeartbeatMsg};
+use omnipaxos_runtime::omnipaxos::OmniPaxosNode;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{self, Instant};
+
+use crate::configs::server::{TRANSFER_POLL, TRANSFER_TIMEOUT, TRANSFER_WINDOW};
+use crate::models::kv::{KVSnapshot, LogEntry};
+use crate::models::msg::{Elect, Msg};
+use crate::models::package::{Package, Types};
+use crate::network::{ClientStream, Network};
+use crate::print_log;
+use crate::store::Store;
+
+//the ballot leader election of the target of a transfer is steered for a while: it does
+//not hear the heartbeat replies of the old leader, so it sees it gone and takes a ballot
+//above the one of the old leader, which every other node then follows as the highest one
+#[derive(Clone, Default)]
+pub(crate) struct Steering {
+    //the old leader and until when its replies are ignored
+    takeover: Arc<Mutex<Option<(u64, Instant)>>>,
+}
+
+impl Steering {
+    //ignore the heartbeat replies of `old` for the transfer window
+    pub fn take_over(&self, old: u64) {
+        let until = Instant::now() + Duration::from_millis(TRANSFER_WINDOW);
+        *self.takeover.lock().unwrap() = Some((old, until));
+    }
+
+    //whether an incoming BLE message is kept from the ballot leader election
+    pub fn ignores(&self, msg: &BLEMessage) -> bool {
+        match *self.takeover.lock().unwrap() {
+            Some((old, until)) => {
+                msg.from == old
+                    && matches!(msg.msg, HeartbeatMsg::Reply(_))
+                    && Instant::now() < until
+            }
+            None => false,
+        }
+    }
+}
+
+//a transfer the command thread hands over, so it goes on with the other commands
+pub(crate) enum Transfer {
+    //move the leadership to `to` and answer the client on the stream
+    Leader { to: u64, stream: ClientStream },
+    //take the leadership over from `old` once the log is decided up to `decided`
+    Elect { old: u64, decided: u64 },
+}
+
+//run the transfers one after the other until the command thread is gone
+pub(crate) async fn transfer_thread(
+    transfers: &mut Receiver<Transfer>,
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    store: &Store,
+    pid: u64,
+    peers: &[u64],
+    network: &Network,
+    steering: &Steering,
+) {
+    while let Some(transfer) = transfers.recv().await {
+        match transfer {
+            Transfer::Leader { to, stream } => {
+                let moved = transfer_leader(op, store, pid, peers, network, steering, to).await;
+                let reply = if moved {
+                    format!("Successfully to transfer leader to {}", to)
+                } else {
+                    "Failed to transfer leader".to_string()
+                };
+                stream.send(&reply).await;
+            }
+            Transfer::Elect { old, decided } => {
+                elect(op, store, pid, old, decided, steering).await;
+            }
+        }
+    }
+}
+
+//move the leadership to the node `to` and wait until this node sees it as the leader
+async fn transfer_leader(
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    store: &Store,
+    pid: u64,
+    peers: &[u64],
+    network: &Network,
+    steering: &Steering,
+    to: u64,
+) -> bool {
+    if to != pid && !peers.contains(&to) {
+        return false;
+    }
+    let old = op.get_current_leader().await;
+    if old != to {
+        let decided = store.applied();
+        if to == pid {
+            elect(op, store, pid, old, decided, steering).await;
+        } else {
+            send(network, pid, to, old, decided).await;
+        }
+    }
+    let deadline = Instant::now() + Duration::from_millis(TRANSFER_TIMEOUT);
+    while Instant::now() < deadline {
+        if op.get_current_leader().await == to {
+            return true;
+        }
+        time::sleep(Duration::from_millis(TRANSFER_POLL)).await;
+    }
+    false
+}
+
+//run on the target of a transfer: once its log is decided up to the index the transfer
+//was requested at, it stops hearing the old leader and takes the leadership over
+async fn elect(
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    store: &Store,
+    pid: u64,
+    old: u64,
+    decided: u64,
+    steering: &Steering,
+) {
+    if op.get_current_leader().await != old {
+        return;
+    }
+    let deadline = Instant::now() + Duration::from_millis(TRANSFER_TIMEOUT);
+    while store.applied() < decided {
+        if Instant::now() >= deadline {
+            print_log(format!("Node {} did not catch up, leader stays {}", pid, old));
+            return;
+        }
+        time::sleep(Duration::from_millis(TRANSFER_POLL)).await;
+    }
+    print_log(format!("Node {} takes over the leadership from {}", pid, old));
+    steering.take_over(old);
+}
+
+//hand the leadership to a peer before this node goes down
+pub(crate) async fn step_down(
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    store: &Store,
+    pid: u64,
+    peers: &[u64],
+    network: &Network,
+) {
+    if peers.is_empty() || op.get_current_leader().await != pid {
+        return;
+    }
+    let to = peers[0];
+    print_log(format!("Node {} hands the leadership to {}", pid, to));
+    let decided = store.applied();
+    send(network, pid, to, pid, decided).await;
+}
+
+//hand a transfer to its target `to`, it does not answer to the client
+async fn send(network: &Network, from: u64, to: u64, old: u64, decided: u64) {
+    let elect = Elect {
+        from,
+        to,
+        old,
+        decided,
+    };
+    let pkg = Package {
+        types: Types::Elect,
+        msg: Msg::Elect(elect),
+    };
+    network.send(from, to, pkg).await;
+}
@@ -0,0 +1,364 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use structopt::StructOpt;
+
+#[allow(dead_code)]
+mod models;
+use crate::models::kv::{KVSnapshot, Store};
+use crate::models::lsm::{self, Meta};
+use crate::models::persist::{self, BACKUP_MAGIC, SNAPSHOT_MAGIC};
+use crate::models::wal::{self, WalRecord};
+
+//offline tools for the files of a node, the node does not have to run
+#[derive(StructOpt)]
+enum Kvctl {
+    //print the logs and snapshots in a data directory, or a log directory, an lsm
+    //directory, a snapshot or a backup file: the ballots and entries of a log or a run,
+    //the header, the commands not resolved yet and the reconstructed key space of a
+    //snapshot
+    Inspect {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        //only the log and snapshot of this node in a data directory
+        #[structopt(long)]
+        pid: Option<u64>,
+        #[structopt(flatten)]
+        filter: Filter,
+    },
+}
+
+//what to print: the key, or the keys with a prefix, and the log indexes in [from, to],
+//a key of a snapshot is printed if the entry that last changed it is in the range
+#[derive(StructOpt)]
+struct Filter {
+    #[structopt(long)]
+    key: Option<String>,
+    #[structopt(long)]
+    prefix: Option<String>,
+    #[structopt(long)]
+    from: Option<u64>,
+    #[structopt(long)]
+    to: Option<u64>,
+}
+
+impl Filter {
+    fn key(&self, key: &str) -> bool {
+        (self.key.is_none() || self.key.as_deref() == Some(key))
+            && (self.prefix.is_none() || key.starts_with(self.prefix.as_deref().unwrap()))
+    }
+
+    fn index(&self, index: u64) -> bool {
+        (self.from.is_none() || Some(index) >= self.from)
+            && (self.to.is_none() || Some(index) <= self.to)
+    }
+}
+
+fn main() {
+    match Kvctl::from_args() {
+        Kvctl::Inspect { path, pid, filter } => {
+            let files = match files(&path, pid) {
+                Ok(files) => files,
+                Err(e) => {
+                    println!("Failed to read {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            };
+            if files.is_empty() {
+                println!("No log or snapshot in {}", path.display());
+            }
+            //every file is printed, the exit code says if one of them is corrupted
+            let mut code = 0;
+            for file in files {
+                if let Err(e) = inspect(&file, &filter) {
+                    println!("{}: {}", file.display(), e);
+                    code = 1;
+                }
+            }
+            process::exit(code);
+        }
+    }
+}
+
+//the file, log or lsm directory itself, or the logs, lsm directories and snapshots of
+//a data directory in order
+fn files(path: &Path, pid: Option<u64>) -> std::io::Result<Vec<PathBuf>> {
+    if !path.is_dir() || !wal::segments(path)?.is_empty() || is_lsm(path)? {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = vec![];
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+        let name = file.file_name().unwrap().to_string_lossy().to_string();
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some(split) => split,
+            None => continue,
+        };
+        let node = match pid {
+            Some(pid) => stem == format!("node{}", pid),
+            None => stem.starts_with("node"),
+        };
+        if node && ["wal", "lsm", "snap"].contains(&extension) {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+//an lsm directory holds runs or the write-ahead log of the lsm storage
+fn is_lsm(dir: &Path) -> std::io::Result<bool> {
+    Ok(!lsm::runs(dir)?.is_empty() || dir.join("wal").is_dir())
+}
+
+fn inspect(file: &Path, filter: &Filter) -> Result<(), String> {
+    if file.is_dir() {
+        return match is_lsm(file) {
+            Ok(true) => inspect_lsm(file, filter),
+            Ok(false) => inspect_log(file, filter),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+    let bytes = fs::read(file).map_err(|e| e.to_string())?;
+    println!("== {}", file.display());
+    let snapshot = if bytes.starts_with(format!("{} ", SNAPSHOT_MAGIC).as_bytes()) {
+        let (header, _) = persist::header(&bytes).map_err(|e| format!("{:?}", e))?;
+        println!(
+            "snapshot: version {}, index {}, {} bytes, crc {:08x}",
+            header.version, header.index, header.length, header.crc
+        );
+        persist::decode(&bytes).map_err(|e| format!("{:?}", e))?
+    } else if bytes.starts_with(format!("{} ", BACKUP_MAGIC).as_bytes()) {
+        let (header, _) = persist::backup_header(&bytes).map_err(|e| format!("{:?}", e))?;
+        let backup = persist::decode_backup(&bytes).map_err(|e| format!("{:?}", e))?;
+        println!(
+            "backup: version {}, index {}, {} bytes, crc {:08x}",
+            header.version, header.index, header.length, header.crc
+        );
+        println!(
+            "taken on node {} of cluster {:?} at {} ms since the unix epoch",
+            backup.pid, backup.cluster, backup.created
+        );
+        backup.snapshot
+    } else {
+        return Err("not a snapshot or backup file".to_string());
+    };
+    print_snapshot(&snapshot, filter);
+    Ok(())
+}
+
+//the write-ahead log of a node: its ballots, the indexes it decided and compacted, and
+//the entries after the compaction
+fn inspect_log(dir: &Path, filter: &Filter) -> Result<(), String> {
+    let replay = wal::replay(dir).map_err(|e| e.to_string())?;
+    println!("== {}", dir.display());
+    println!(
+        "log: {} records in segments {:?}",
+        replay.records, replay.segments
+    );
+    if let Some((segment, offset)) = replay.torn {
+        println!("torn record at offset {} of segment {}", offset, segment);
+    }
+    if let Some((segment, offset)) = replay.corrupt {
+        println!(
+            "corrupted record at offset {} of segment {}, {} bytes are not replayed",
+            offset, segment, replay.discarded
+        );
+    }
+    let state = replay.state;
+    println!("promise: {:?}", state.promise);
+    println!("accepted round: {:?}", state.accepted);
+    println!(
+        "decided up to index {}, compacted up to index {}",
+        state.decided, state.compacted
+    );
+    let any_key = filter.key.is_none() && filter.prefix.is_none();
+    let store = Store::default();
+    println!("entries: {}", state.log.len());
+    for (i, entry) in state.log.iter().enumerate() {
+        let index = state.compacted + i as u64;
+        let keys = entry.command.keys(&store);
+        if filter.index(index) && (any_key || keys.iter().any(|k| filter.key(k))) {
+            let decided = match index < state.decided {
+                true => "decided",
+                false => "accepted",
+            };
+            println!("  {} ({}): {:?}", index, decided, entry.command);
+        }
+    }
+    if let Some(snapshot) = &state.snapshot {
+        print_snapshot(snapshot, filter);
+    }
+    match replay.corrupt {
+        Some((segment, offset)) => Err(format!(
+            "segment {} is corrupted at offset {}",
+            segment, offset
+        )),
+        None => Ok(()),
+    }
+}
+
+//the lsm storage of a node: every run with the state it was written with and its
+//entries, the snapshot of the newest run, then the changes of the write-ahead log after
+//the runs
+fn inspect_lsm(dir: &Path, filter: &Filter) -> Result<(), String> {
+    let runs = lsm::runs(dir).map_err(|e| e.to_string())?;
+    println!("== {}", dir.display());
+    println!("runs: {}", runs.len());
+    let any_key = filter.key.is_none() && filter.prefix.is_none();
+    let store = Store::default();
+    let mut corrupt = None;
+    let mut newest = Meta::default();
+    for (number, path) in runs {
+        let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+        let run = match lsm::decode_run(&bytes) {
+            Ok(run) => run,
+            Err(e) => {
+                println!("run {}: {:?}", number, e);
+                corrupt = corrupt.or(Some(format!("run {} is corrupted", number)));
+                continue;
+            }
+        };
+        let meta = run.meta;
+        println!(
+            "run {}: {} entries, log [{}, {}), write-ahead log from segment {}",
+            number,
+            run.entries.len(),
+            meta.offset,
+            meta.offset + meta.len,
+            meta.wal_from
+        );
+        println!("  promise: {:?}", meta.promise);
+        println!("  accepted round: {:?}", meta.accepted);
+        println!(
+            "  decided up to index {}, compacted up to index {}",
+            meta.decided, meta.compacted
+        );
+        for (index, entry) in &run.entries {
+            let keys = entry.command.keys(&store);
+            if filter.index(*index) && (any_key || keys.iter().any(|k| filter.key(k))) {
+                println!("  {}: {:?}", index, entry.command);
+            }
+        }
+        if let Some(index) = run.corrupt {
+            println!("  corrupted entry at index {}", index);
+            corrupt = corrupt.or(Some(format!(
+                "run {} is corrupted at index {}",
+                number, index
+            )));
+        }
+        newest = meta;
+    }
+    if let Some(snapshot) = &newest.snapshot {
+        print_snapshot(snapshot, filter);
+    }
+    let wal = dir.join("wal");
+    if wal.is_dir() {
+        print_changes(&wal, &newest, filter)?;
+    }
+    match corrupt {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+//the records of the write-ahead log of an lsm storage, the entries with their absolute
+//index: the log starts at the offset of the newest run and moves with the trims
+fn print_changes(dir: &Path, newest: &Meta, filter: &Filter) -> Result<(), String> {
+    let read = wal::read(dir).map_err(|e| e.to_string())?;
+    let records: usize = read.segments.iter().map(|(_, records)| records.len()).sum();
+    let segments: Vec<u64> = read.segments.iter().map(|(number, _)| *number).collect();
+    println!("== {}", dir.display());
+    println!("log: {} records in segments {:?}", records, segments);
+    if let Some((segment, offset)) = read.torn {
+        println!("torn record at offset {} of segment {}", offset, segment);
+    }
+    if let Some((segment, offset)) = read.corrupt {
+        println!(
+            "corrupted record at offset {} of segment {}, {} bytes are not replayed",
+            offset, segment, read.discarded
+        );
+    }
+    let any_key = filter.key.is_none() && filter.prefix.is_none();
+    let store = Store::default();
+    let mut offset = newest.offset;
+    for (segment, records) in read.segments {
+        //the segments before the newest run are in the runs already
+        if segment < newest.wal_from {
+            continue;
+        }
+        for record in records {
+            match record {
+                WalRecord::Append { at, entries } => {
+                    for (i, entry) in entries.iter().enumerate() {
+                        let index = offset + at + i as u64;
+                        let keys = entry.command.keys(&store);
+                        if filter.index(index) && (any_key || keys.iter().any(|k| filter.key(k))) {
+                            println!("  {}: {:?}", index, entry.command);
+                        }
+                    }
+                }
+                WalRecord::Promise(ballot) => println!("  promise: {:?}", ballot),
+                WalRecord::Accepted(ballot) => println!("  accepted round: {:?}", ballot),
+                WalRecord::Decided(index) => println!("  decided up to index {}", index),
+                WalRecord::Trim(index) => {
+                    offset += index;
+                    println!("  trimmed up to index {}", offset);
+                }
+                WalRecord::Compacted(index) => println!("  compacted up to index {}", index),
+                WalRecord::Snapshot(snapshot) => {
+                    println!("  snapshot up to index {}", snapshot.len)
+                }
+                WalRecord::Checkpoint(state) => {
+                    println!("  checkpoint of {} entries", state.log.len())
+                }
+            }
+        }
+    }
+    match read.corrupt {
+        Some((segment, offset)) => Err(format!(
+            "segment {} is corrupted at offset {}",
+            segment, offset
+        )),
+        None => Ok(()),
+    }
+}
+
+fn print_snapshot(snapshot: &KVSnapshot, filter: &Filter) {
+    println!("log: entries [0, {}) compacted", snapshot.len);
+    let start = snapshot.len - snapshot.delta.len() as u64;
+    let mut before = snapshot.snapshotted.clone();
+    println!("unresolved commands: {}", snapshot.delta.len());
+    for (i, command) in snapshot.delta.iter().enumerate() {
+        let index = start + i as u64;
+        let any_key = filter.key.is_none() && filter.prefix.is_none();
+        let keys = command.keys(&before);
+        if filter.index(index) && (any_key || keys.iter().any(|k| filter.key(k))) {
+            println!("  {}: {:?}", index, command);
+        }
+        command.apply(&mut before, index + 1);
+    }
+    print_store(&snapshot.state(), snapshot.len, filter);
+}
+
+fn print_store(store: &Store, len: u64, filter: &Filter) {
+    println!("leases: {}", store.leases.len());
+    for (id, lease) in &store.leases {
+        if filter.index(lease.revision) {
+            println!(
+                "  lease {} (ttl {} ms, kept alive at revision {})",
+                id, lease.ttl, lease.revision
+            );
+        }
+    }
+    println!("keys at index {}: {}", len, store.data.len());
+    for (key, record) in &store.data {
+        if filter.key(key) && filter.index(record.mod_revision) {
+            println!(
+                "  {} = {} (version {}, create revision {}, mod revision {})",
+                key, record.value, record.version, record.create_revision, record.mod_revision
+            );
+        }
+    }
+}
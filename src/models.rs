@@ -1,4 +1,10 @@
+pub mod export;
+pub mod fault;
 pub mod kv;
+pub mod lsm;
 pub mod msg;
 pub mod package;
+pub mod persist;
+pub mod storage;
+pub mod wal;
 pub mod node;
\ No newline at end of file
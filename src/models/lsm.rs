@@ -0,0 +1,493 @@
+#![allow(dead_code)]
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use omnipaxos_core::ballot_leader_election::Ballot;
+use omnipaxos_core::storage::{StopSignEntry, Storage};
+use serde::{Deserialize, Serialize};
+
+use super::kv::{KVSnapshot, LogEntry};
+use super::persist::FileErr;
+use super::storage::{Recovery, Repair, StorageOptions};
+use super::wal::{frame, records, sync_dir, unframe, Wal, WalRecord};
+
+//the log storage of a node as a log-structured merge tree: the changes go to a
+//write-ahead log and the entries to a memtable, once the memtable is full it is written
+//to a new sorted run and the segments of the write-ahead log it covers are deleted.
+//Once there are too many runs, or a compaction trimmed the log, the runs are merged
+//into one without the entries that are no longer in the log. A run is a file
+//`<number>.run` of frames like the records of the write-ahead log, each with its length
+//and checksum: a header with `KVRUN` as magic and the state of the storage when it was
+//written, then its entries in order of their absolute index. The newest run has the
+//newest state, and an entry in a newer run replaces an older one. A corrupted entry cuts
+//the log at its index, a corrupted header cuts the run and the runs after it; the
+//promise is kept across a cut and the accepted round is reset, the entries accepted in
+//it may be cut. A run of another version is never
+//repaired, the storage does not open.
+pub(crate) const RUN_MAGIC: &str = "KVRUN";
+pub(crate) const RUN_VERSION: u32 = 2;
+
+//what the storage keeps besides the entries, the same as the memory storage: the
+//indexes the storage is called with are relative to the first entry of the log
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Meta {
+    //the absolute index of the first entry of the log and the length of the log
+    pub offset: u64,
+    pub len: u64,
+    pub promise: Ballot,
+    pub accepted: Ballot,
+    pub decided: u64,
+    pub compacted: u64,
+    pub snapshot: Option<KVSnapshot>,
+    //the first segment of the write-ahead log that is not in the runs yet
+    pub wal_from: u64,
+}
+
+struct Run {
+    number: u64,
+    entries: BTreeMap<u64, LogEntry>,
+}
+
+fn run_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:08}.run", number))
+}
+
+fn wal_dir(dir: &Path) -> PathBuf {
+    dir.join("wal")
+}
+
+//the runs of a storage directory in order, by number
+pub(crate) fn runs(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut runs = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".run"))
+            .and_then(|number| number.parse().ok());
+        if let Some(number) = number {
+            runs.push((number, path));
+        }
+    }
+    runs.sort();
+    Ok(runs)
+}
+
+//the first frame of a run, the entries of a run are in a row from `first` on
+#[derive(Serialize, Deserialize)]
+struct RunHeader {
+    magic: String,
+    version: u32,
+    number: u64,
+    first: u64,
+    count: u64,
+    meta: Meta,
+}
+
+//a run read from its file, with its entries up to the first one that is not whole
+pub(crate) struct RunFile {
+    pub number: u64,
+    pub meta: Meta,
+    pub entries: BTreeMap<u64, LogEntry>,
+    //the index of the first entry that is corrupted or missing
+    pub corrupt: Option<u64>,
+}
+
+pub(crate) fn encode_run(number: u64, meta: &Meta, entries: &BTreeMap<u64, LogEntry>) -> Vec<u8> {
+    let header = RunHeader {
+        magic: RUN_MAGIC.to_string(),
+        version: RUN_VERSION,
+        number,
+        first: entries.keys().next().copied().unwrap_or(meta.offset),
+        count: entries.len() as u64,
+        meta: meta.clone(),
+    };
+    let mut bytes = frame(&header);
+    for entry in entries {
+        bytes.extend(frame(&entry));
+    }
+    bytes
+}
+
+pub(crate) fn decode_run(bytes: &[u8]) -> Result<RunFile, FileErr> {
+    //a run of version 1 starts with the text header of a snapshot file
+    if let Some(line) = bytes.strip_prefix(format!("{} ", RUN_MAGIC).as_bytes()) {
+        let version = line
+            .split(|b| *b == b' ')
+            .next()
+            .and_then(|version| std::str::from_utf8(version).ok()?.parse().ok());
+        return Err(version.map_or(FileErr::Format, FileErr::Version));
+    }
+    let (header, start): (RunHeader, usize) = unframe(bytes).ok_or(FileErr::Checksum)?;
+    if header.magic != RUN_MAGIC {
+        return Err(FileErr::Format);
+    }
+    if header.version != RUN_VERSION {
+        return Err(FileErr::Version(header.version));
+    }
+    let (entries, _): (Vec<(u64, LogEntry)>, _) = records(&bytes[start..]);
+    let read = entries.len() as u64;
+    Ok(RunFile {
+        number: header.number,
+        meta: header.meta,
+        entries: entries.into_iter().collect(),
+        corrupt: (read < header.count).then_some(header.first + read),
+    })
+}
+
+pub(crate) struct LsmStorage {
+    dir: PathBuf,
+    options: StorageOptions,
+    wal: Arc<Wal>,
+    meta: Meta,
+    memtable: BTreeMap<u64, LogEntry>,
+    //the runs from the oldest to the newest
+    runs: Vec<Run>,
+    next_run: u64,
+    //the log is never reconfigured, so the stop sign is not kept on disk
+    stopsign: Option<StopSignEntry>,
+}
+
+impl LsmStorage {
+    //open the storage in `dir`, created if it does not exist: the newest run gives the
+    //state, and the write-ahead log after it is replayed. What is found corrupted is
+    //discarded and the runs are written again without it, a run of another version is
+    //an error and nothing is changed
+    pub fn open(dir: &Path, options: &StorageOptions) -> io::Result<(Self, Recovery)> {
+        fs::create_dir_all(dir)?;
+        let mut files = vec![];
+        for (number, path) in runs(dir)? {
+            match decode_run(&fs::read(&path)?) {
+                Err(FileErr::Version(version)) => {
+                    let msg = format!("run {} has the unknown version {}", number, version);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+                Err(FileErr::Format) => {
+                    let msg = format!("run {} is not a run of the lsm storage", number);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+                run => files.push((number, path, run.ok())),
+            }
+        }
+        let mut meta = Meta::default();
+        let mut loaded = vec![];
+        let mut repairs = vec![];
+        let mut cut: Option<u64> = None;
+        //a run with a corrupted header and the runs after it are cut, the state of a run
+        //is needed for the ones after it; the ballots of the newest run are kept, and the
+        //write-ahead log after it
+        let mut cut_runs = false;
+        let (mut promise, mut accepted, mut ballots_from) =
+            (Ballot::default(), Ballot::default(), 0);
+        for (number, path, run) in files {
+            if let Some(run) = &run {
+                (promise, accepted) = (run.meta.promise, run.meta.accepted);
+                ballots_from = run.meta.wal_from;
+            }
+            let run = match run {
+                Some(run) if !cut_runs => run,
+                _ => {
+                    if !cut_runs {
+                        repairs.push(Repair::Run { run: number });
+                    }
+                    cut_runs = true;
+                    fs::remove_file(path)?;
+                    continue;
+                }
+            };
+            if let Some(index) = run.corrupt {
+                repairs.push(Repair::Entry { run: number, index });
+                cut = Some(cut.map_or(index, |cut| cut.min(index)));
+            }
+            meta = run.meta;
+            loaded.push(Run {
+                number,
+                entries: run.entries,
+            });
+        }
+        if cut_runs {
+            sync_dir(dir)?;
+        }
+        if let Some(index) = cut {
+            if index >= meta.offset && index < meta.offset + meta.len {
+                meta.len = index - meta.offset;
+            }
+        }
+        let (wal, read) = Wal::recover(&wal_dir(dir), options.segment_bytes)?;
+        if let Some((segment, offset)) = read.corrupt {
+            let bytes = read.discarded;
+            repairs.push(Repair::Segment {
+                segment,
+                offset,
+                bytes,
+            });
+        }
+        let next_run = loaded.last().map_or(1, |run| run.number + 1);
+        let mut storage = LsmStorage {
+            dir: dir.to_path_buf(),
+            options: *options,
+            wal: Arc::new(wal),
+            meta,
+            memtable: BTreeMap::new(),
+            runs: loaded,
+            next_run,
+            stopsign: None,
+        };
+        let mut records = 0;
+        for (segment, segment_records) in read.segments {
+            for record in segment_records {
+                records += 1;
+                match record {
+                    WalRecord::Promise(ballot) if segment >= ballots_from => promise = ballot,
+                    WalRecord::Accepted(ballot) if segment >= ballots_from => accepted = ballot,
+                    //the segments the runs cover, and once runs were cut the records
+                    //that continue them and not the runs left
+                    _ if cut_runs || segment < storage.meta.wal_from => continue,
+                    record => storage.apply(record),
+                }
+            }
+        }
+        //a node that reported the accepted round of the entries it cut could have them
+        //overwritten, it reports none until it accepts the log of a leader again
+        if !repairs.is_empty() {
+            accepted = Ballot::default();
+        }
+        (storage.meta.promise, storage.meta.accepted) = (promise, accepted);
+        //a crash after a flush may leave the segments the run covers behind
+        storage.wal.remove_before(storage.meta.wal_from)?;
+        let end = storage.meta.offset + storage.meta.len;
+        if !repairs.is_empty() {
+            storage.meta.decided = storage.meta.decided.min(end);
+            storage.flush()?;
+            storage.merge()?;
+        }
+        let recovery = Recovery {
+            records,
+            runs: storage.runs.len() as u64,
+            decided: storage.meta.decided,
+            compacted: storage.meta.compacted,
+            snapshot: storage.meta.snapshot.is_some(),
+            torn: read.torn,
+            repairs,
+            end,
+        };
+        Ok((storage, recovery))
+    }
+
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    //the numbers of the runs on disk
+    pub fn runs(&self) -> Vec<u64> {
+        self.runs.iter().map(|run| run.number).collect()
+    }
+
+    pub fn wal(&self) -> &Arc<Wal> {
+        &self.wal
+    }
+
+    fn apply(&mut self, record: WalRecord) {
+        let meta = &mut self.meta;
+        match record {
+            //the log was cut by a repair, what was appended after the cut is gone
+            WalRecord::Append { at, .. } if at > meta.len => {}
+            WalRecord::Append { at, entries } => {
+                let start = meta.offset + at;
+                self.memtable.split_off(&start);
+                meta.len = at + entries.len() as u64;
+                for (i, entry) in entries.into_iter().enumerate() {
+                    self.memtable.insert(start + i as u64, entry);
+                }
+            }
+            WalRecord::Promise(ballot) => meta.promise = ballot,
+            WalRecord::Accepted(ballot) => meta.accepted = ballot,
+            WalRecord::Decided(index) => meta.decided = index,
+            WalRecord::Trim(index) => {
+                let trimmed = index.min(meta.len);
+                meta.offset += trimmed;
+                meta.len -= trimmed;
+                self.memtable = self.memtable.split_off(&meta.offset);
+            }
+            WalRecord::Compacted(index) => meta.compacted = index,
+            WalRecord::Snapshot(snapshot) => meta.snapshot = Some(snapshot),
+            //the whole storage of the file engine, the log starts at the current offset
+            WalRecord::Checkpoint(state) => {
+                meta.len = state.log.len() as u64;
+                meta.promise = state.promise;
+                meta.accepted = state.accepted;
+                meta.decided = state.decided;
+                meta.compacted = state.compacted;
+                meta.snapshot = state.snapshot;
+                let offset = meta.offset;
+                self.memtable = (offset..).zip(state.log).collect();
+            }
+        }
+    }
+
+    //a storage that can not write its log must not answer, so the node stops
+    fn write(&mut self, record: WalRecord) {
+        self.wal
+            .queue(&record)
+            .expect("Failed to write the write-ahead log");
+        self.apply(record);
+        if self.memtable.len() >= self.options.memtable_entries {
+            self.flush().expect("Failed to flush the log storage");
+        }
+    }
+
+    //write the memtable to a new run, the write-ahead log then starts after it with the
+    //ballots, so they are kept if the run is found corrupted
+    fn flush(&mut self) -> io::Result<()> {
+        let ballots = [
+            WalRecord::Promise(self.meta.promise),
+            WalRecord::Accepted(self.meta.accepted),
+        ];
+        self.meta.wal_from = self.wal.rotate(&ballots)?;
+        let entries = std::mem::take(&mut self.memtable);
+        let run = self.write_run(entries)?;
+        self.runs.push(run);
+        self.wal.remove_before(self.meta.wal_from)?;
+        let offset = self.meta.offset;
+        let trimmed = self
+            .runs
+            .iter()
+            .any(|run| run.entries.range(..offset).next().is_some());
+        if self.runs.len() > self.options.max_runs || trimmed {
+            self.merge()?;
+        }
+        Ok(())
+    }
+
+    //merge every run into a new one with the entries in the log, the newest entry of
+    //an index wins
+    fn merge(&mut self) -> io::Result<()> {
+        let mut entries = BTreeMap::new();
+        for run in &self.runs {
+            entries.extend(run.entries.iter().map(|(i, e)| (*i, e.clone())));
+        }
+        let log = self.meta.offset..self.meta.offset + self.meta.len;
+        entries.retain(|index, _| log.contains(index));
+        let merged = self.write_run(entries)?;
+        for run in std::mem::replace(&mut self.runs, vec![merged]) {
+            fs::remove_file(run_path(&self.dir, run.number))?;
+        }
+        sync_dir(&self.dir)
+    }
+
+    //write the run next to its place and move it there, so a crash while writing leaves
+    //no half run behind
+    fn write_run(&mut self, entries: BTreeMap<u64, LogEntry>) -> io::Result<Run> {
+        let number = self.next_run;
+        let path = run_path(&self.dir, number);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, encode_run(number, &self.meta, &entries))?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        sync_dir(&self.dir)?;
+        self.next_run += 1;
+        Ok(Run { number, entries })
+    }
+
+    //the entry at an absolute index, from the memtable or the newest run that has it
+    fn entry(&self, index: u64) -> Option<&LogEntry> {
+        self.memtable.get(&index).or_else(|| {
+            self.runs
+                .iter()
+                .rev()
+                .find_map(|run| run.entries.get(&index))
+        })
+    }
+}
+
+impl Storage<LogEntry, KVSnapshot> for LsmStorage {
+    fn append_entry(&mut self, entry: LogEntry) -> u64 {
+        self.append_entries(vec![entry])
+    }
+
+    fn append_entries(&mut self, entries: Vec<LogEntry>) -> u64 {
+        let at = self.meta.len;
+        self.append_on_prefix(at, entries)
+    }
+
+    fn append_on_prefix(&mut self, from_idx: u64, entries: Vec<LogEntry>) -> u64 {
+        self.write(WalRecord::Append {
+            at: from_idx,
+            entries,
+        });
+        self.get_log_len()
+    }
+
+    fn set_promise(&mut self, n_prom: Ballot) {
+        self.write(WalRecord::Promise(n_prom));
+    }
+
+    fn set_decided_idx(&mut self, ld: u64) {
+        self.write(WalRecord::Decided(ld));
+    }
+
+    fn get_decided_idx(&self) -> u64 {
+        self.meta.decided
+    }
+
+    fn set_accepted_round(&mut self, na: Ballot) {
+        self.write(WalRecord::Accepted(na));
+    }
+
+    fn get_accepted_round(&self) -> Ballot {
+        self.meta.accepted
+    }
+
+    fn get_entries(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        let to = to.min(self.meta.len);
+        (from..to)
+            .filter_map(|i| self.entry(self.meta.offset + i).cloned())
+            .collect()
+    }
+
+    fn get_log_len(&self) -> u64 {
+        self.meta.len
+    }
+
+    fn get_suffix(&self, from: u64) -> Vec<LogEntry> {
+        self.get_entries(from, self.meta.len)
+    }
+
+    fn get_promise(&self) -> Ballot {
+        self.meta.promise
+    }
+
+    fn set_stopsign(&mut self, s: StopSignEntry) {
+        self.stopsign = Some(s);
+    }
+
+    fn get_stopsign(&self) -> Option<StopSignEntry> {
+        self.stopsign.clone()
+    }
+
+    fn trim(&mut self, idx: u64) {
+        self.write(WalRecord::Trim(idx));
+    }
+
+    //the compaction is complete, the runs are merged without the trimmed entries
+    fn set_compacted_idx(&mut self, idx: u64) {
+        self.write(WalRecord::Compacted(idx));
+        self.flush().expect("Failed to flush the log storage");
+    }
+
+    fn get_compacted_idx(&self) -> u64 {
+        self.meta.compacted
+    }
+
+    fn set_snapshot(&mut self, snapshot: KVSnapshot) {
+        self.write(WalRecord::Snapshot(snapshot));
+    }
+
+    fn get_snapshot(&self) -> Option<KVSnapshot> {
+        self.meta.snapshot.clone()
+    }
+}
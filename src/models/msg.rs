@@ -1,13 +1,65 @@
 use omnipaxos_core::{ballot_leader_election::messages::BLEMessage, messages::Message};
 use serde::{Deserialize, Serialize};
 
-use super::kv::{KVSnapshot, KeyValue};
+use super::fault::FaultConfig;
+use super::kv::{Condition, KVSnapshot, KeyValue, LogEntry, Mutation};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) enum Operation {
     Get,
+    //the key, or every key, as it was right after the entry at `at_index` was applied
+    GetAt { at_index: u64 },
+    SnapshotAt { at_index: u64 },
     Put,
+    //a put that expires `ttl` ms after the last change of the key
+    PutTtl { ttl: u64 },
+    //many keys in one request, the key-value of the message is not used
+    MultiGet { keys: Vec<String> },
+    MultiPut { kvs: Vec<KeyValue> },
+    //list the keys in [start, end) or with a prefix in order, a page holds up to
+    //`limit` keys (0 for all) and the token of the next page resumes the listing
+    Scan {
+        start: String,
+        end: Option<String>,
+        limit: usize,
+        token: Option<String>,
+    },
+    Prefix {
+        prefix: String,
+        limit: usize,
+        token: Option<String>,
+    },
+    //stream the changes of the key, or of every key with the key as prefix,
+    //from the revision `from_revision` on
+    Watch { prefix: bool, from_revision: u64 },
+    //compare and swap, the new value is the value of the key-value,
+    //None expects the key to be absent
+    Cas { expected: Option<u64> },
+    //add or subtract the value of the key-value
+    Incr,
+    Decr,
+    //apply the mutations atomically if every condition holds
+    Txn {
+        conditions: Vec<Condition>,
+        mutations: Vec<Mutation>,
+    },
+    //leases, a key put with a lease or a lock on the key is deleted when the lease
+    //is revoked or not kept alive within its ttl
+    Grant { ttl: u64 },
+    KeepAlive { lease: u64 },
+    Revoke { lease: u64 },
+    PutLease { lease: u64 },
+    Lock { lease: u64 },
+    Unlock { lease: u64 },
+    //every key with its revisions, read at the index the node applied
+    Export,
     Snap,
+    //admin operations
+    Leader,
+    //write the state at the index the node applied to a backup file on the node
+    Backup { path: String },
+    Fault(FaultConfig),
+    TransferLeader { to: u64 },
 }
 
 #[allow(missing_docs)]
@@ -17,11 +69,67 @@ pub(crate) struct CMDMessage {
     pub kv: KeyValue,
 }
 
+//a piece of an SP message too large to be sent in one package, e.g. a snapshot for a
+//lagging follower, `crc` is the checksum of the whole message
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Chunk {
+    pub from: u64,
+    pub to: u64,
+    pub transfer: u64,
+    pub index: u64,
+    pub total: u64,
+    pub crc: u32,
+    pub data: String,
+}
+
+//the receiver of a transfer needs the chunk `next` next, `total` once it is complete
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ChunkAck {
+    pub from: u64,
+    pub to: u64,
+    pub transfer: u64,
+    pub next: u64,
+}
+
+//a node asks a peer for the end of its log before a linearizable read, `read` tells
+//the reads of the node apart
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ReadIndex {
+    pub from: u64,
+    pub to: u64,
+    pub read: u64,
+}
+
+//the index the log of the peer reaches, accepted entries included
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ReadIndexAck {
+    pub from: u64,
+    pub to: u64,
+    pub read: u64,
+    pub index: u64,
+}
+
+//the node that handles a leader transfer hands it to its target, which takes the
+//leadership over from `old` once its log is decided up to `decided`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Elect {
+    pub from: u64,
+    pub to: u64,
+    pub old: u64,
+    pub decided: u64,
+}
+
 #[allow(missing_docs)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub(crate) enum Msg {
     BLE(BLEMessage),
-    SP(Message<KeyValue, KVSnapshot>),
+    SP(Message<LogEntry, KVSnapshot>),
     CMD(CMDMessage),
+    Chunk(Chunk),
+    ChunkAck(ChunkAck),
+    //an ack has every field of its request, so it is tried first
+    ReadIndexAck(ReadIndexAck),
+    ReadIndex(ReadIndex),
+    Elect(Elect),
 }
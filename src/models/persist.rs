@@ -0,0 +1,139 @@
+#![allow(dead_code)]
+use serde::{Deserialize, Serialize};
+
+use super::kv::KVSnapshot;
+
+//a snapshot file is a header line `KVSNAP <version> <index> <length> <crc32>` followed by
+//the snapshot as json, the header says how long the body is and its checksum, so a torn
+//or corrupted file is detected instead of being restored
+pub(crate) const SNAPSHOT_MAGIC: &str = "KVSNAP";
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+//a backup file has the same layout with `KVBACKUP` as magic, its body is the backup
+//with the snapshot and where it was taken
+pub(crate) const BACKUP_MAGIC: &str = "KVBACKUP";
+pub(crate) const BACKUP_VERSION: u32 = 1;
+
+//why a file can not be read
+#[derive(Debug, PartialEq)]
+pub(crate) enum FileErr {
+    //the header is missing or malformed
+    Format,
+    //the file was written by another version of the format
+    Version(u32),
+    //the body is shorter or longer than the header says
+    Length,
+    Checksum,
+}
+
+//the header of a snapshot or backup file
+#[derive(Debug, PartialEq)]
+pub(crate) struct Header {
+    pub version: u32,
+    //the snapshot covers the log up to this index
+    pub index: u64,
+    pub length: u64,
+    pub crc: u32,
+}
+
+//a point-in-time dump of a cluster, the state at a decided index and where it was taken
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Backup {
+    //the cluster id, the pids of its nodes in order
+    pub cluster: Vec<u64>,
+    //the node that took it
+    pub pid: u64,
+    //the log index the snapshot goes up to
+    pub index: u64,
+    //when it was taken, in ms since the unix epoch
+    pub created: u64,
+    pub snapshot: KVSnapshot,
+}
+
+pub(crate) fn encode(snapshot: &KVSnapshot) -> Vec<u8> {
+    let body = serde_json::to_vec(snapshot).unwrap();
+    frame(SNAPSHOT_MAGIC, SNAPSHOT_VERSION, snapshot.len, body)
+}
+
+pub(crate) fn encode_backup(backup: &Backup) -> Vec<u8> {
+    let body = serde_json::to_vec(backup).unwrap();
+    frame(BACKUP_MAGIC, BACKUP_VERSION, backup.index, body)
+}
+
+fn frame(magic: &str, version: u32, index: u64, body: Vec<u8>) -> Vec<u8> {
+    let header = format!(
+        "{} {} {} {} {:08x}\n",
+        magic,
+        version,
+        index,
+        body.len(),
+        crc32(&body)
+    );
+    [header.into_bytes(), body].concat()
+}
+
+//read the header of a snapshot file and check the body against it
+pub(crate) fn header(bytes: &[u8]) -> Result<(Header, &[u8]), FileErr> {
+    read(bytes, SNAPSHOT_MAGIC, SNAPSHOT_VERSION)
+}
+
+pub(crate) fn backup_header(bytes: &[u8]) -> Result<(Header, &[u8]), FileErr> {
+    read(bytes, BACKUP_MAGIC, BACKUP_VERSION)
+}
+
+fn read<'a>(bytes: &'a [u8], magic: &str, current: u32) -> Result<(Header, &'a [u8]), FileErr> {
+    let end = bytes
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or(FileErr::Format)?;
+    let line = std::str::from_utf8(&bytes[..end]).map_err(|_| FileErr::Format)?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    if fields.len() != 5 || fields[0] != magic {
+        return Err(FileErr::Format);
+    }
+    let version = fields[1].parse().map_err(|_| FileErr::Format)?;
+    if version != current {
+        return Err(FileErr::Version(version));
+    }
+    let header = Header {
+        version,
+        index: fields[2].parse().map_err(|_| FileErr::Format)?,
+        length: fields[3].parse().map_err(|_| FileErr::Format)?,
+        crc: u32::from_str_radix(fields[4], 16).map_err(|_| FileErr::Format)?,
+    };
+    let body = &bytes[end + 1..];
+    if body.len() as u64 != header.length {
+        return Err(FileErr::Length);
+    }
+    if crc32(body) != header.crc {
+        return Err(FileErr::Checksum);
+    }
+    Ok((header, body))
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Result<KVSnapshot, FileErr> {
+    let (_, body) = header(bytes)?;
+    serde_json::from_slice(body).map_err(|_| FileErr::Format)
+}
+
+//a backup whose body does not match the index of its header is rejected as well
+pub(crate) fn decode_backup(bytes: &[u8]) -> Result<Backup, FileErr> {
+    let (header, body) = backup_header(bytes)?;
+    let backup: Backup = serde_json::from_slice(body).map_err(|_| FileErr::Format)?;
+    if backup.index != header.index || backup.snapshot.len != backup.index {
+        return Err(FileErr::Format);
+    }
+    Ok(backup)
+}
+
+//crc-32 (IEEE), the checksum of zip and ethernet
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
@@ -0,0 +1,270 @@
+#![allow(dead_code)]
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use omnipaxos_core::storage::{memory_storage::MemoryStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+use super::kv::{KVSnapshot, LogEntry};
+use super::lsm::LsmStorage;
+use super::persist::{self, FileErr};
+use super::wal::{Wal, WalStorage};
+
+//the engines the log storage of a node can be kept in: memory keeps nothing on disk,
+//file is the write-ahead log, lsm flushes the log into sorted runs
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Engine {
+    Memory,
+    File,
+    Lsm,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Engine::Memory),
+            "file" => Ok(Engine::File),
+            "lsm" => Ok(Engine::Lsm),
+            _ => Err(format!("unknown storage {}, memory, file or lsm", s)),
+        }
+    }
+}
+
+impl Engine {
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Memory => "memory",
+            Engine::File => "file",
+            Engine::Lsm => "lsm",
+        }
+    }
+}
+
+//the sizes of the files of the engines on disk
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StorageOptions {
+    //a segment of a write-ahead log is closed once it holds this many bytes
+    pub segment_bytes: u64,
+    //the lsm engine writes its entries in memory to a run once it holds this many
+    pub memtable_entries: usize,
+    //and merges its runs into one once it has more than this many
+    pub max_runs: usize,
+}
+
+//what opening a storage found on disk, nothing for a new storage
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Recovery {
+    //the records of the write-ahead log read back, counted alike by every engine
+    pub records: u64,
+    //the sorted runs loaded, only the lsm engine has them
+    pub runs: u64,
+    pub decided: u64,
+    pub compacted: u64,
+    pub snapshot: bool,
+    //the segment and offset of a torn record dropped from the end of the log
+    pub torn: Option<(u64, u64)>,
+    //what was found corrupted and discarded, and the index the log ends at after it
+    pub repairs: Vec<Repair>,
+    pub end: u64,
+}
+
+impl Recovery {
+    //whether nothing was found on disk, neither a record nor a run
+    pub fn is_new(&self) -> bool {
+        self.records == 0 && self.runs == 0
+    }
+}
+
+//a part of a storage found corrupted on start: the log is cut before it and the peers
+//send the entries after the cut again, like to a node that was down
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Repair {
+    //a record of a segment of a write-ahead log, the bytes from it on are discarded
+    Segment {
+        segment: u64,
+        offset: u64,
+        bytes: u64,
+    },
+    //an entry of a run of the lsm storage, the log is cut at its index if it is still in
+    //the log
+    Entry {
+        run: u64,
+        index: u64,
+    },
+    //the header of a run of the lsm storage, the run and the runs after it are discarded
+    Run {
+        run: u64,
+    },
+}
+
+//the log storage of a node: Omni-Paxos calls the storage, the backend says how it is
+//opened, every engine gives the same answers to the same calls
+pub(crate) trait Backend: Storage<LogEntry, KVSnapshot> + Send + Sized + 'static {
+    const ENGINE: Engine;
+
+    //open the storage kept in `path`, created if it does not exist; only the memory
+    //storage has no path
+    fn open(path: Option<&Path>, options: &StorageOptions) -> io::Result<(Self, Recovery)>;
+
+    //the write-ahead log the changes are queued in, the node syncs it before it sends
+    //the messages that answer them; None if nothing is kept on disk
+    fn sync_handle(&self) -> Option<Arc<Wal>> {
+        None
+    }
+
+    //where the state machine of the node is kept, the state it applied from the decided
+    //log: in the snapshot file `path` with a data directory, so the node restarts from
+    //it, and only in memory without one
+    fn state(path: Option<&Path>) -> Box<dyn StateStore> {
+        match path {
+            Some(path) => Box::new(SnapshotFile::new(path)),
+            None => Box::new(MemoryState::default()),
+        }
+    }
+}
+
+//the state machine of a node as a snapshot of the key-value store up to the index it
+//applied, every engine gives back the last state it saved
+pub(crate) trait StateStore: Send + Sync {
+    //replace the saved state, a failed save leaves the one before
+    fn save(&self, snapshot: &KVSnapshot) -> io::Result<()>;
+
+    //the last state saved, None if there is none yet
+    fn load(&self) -> io::Result<Option<KVSnapshot>>;
+}
+
+//the state kept for as long as the node runs
+#[derive(Default)]
+pub(crate) struct MemoryState {
+    snapshot: Mutex<Option<KVSnapshot>>,
+}
+
+impl StateStore for MemoryState {
+    fn save(&self, snapshot: &KVSnapshot) -> io::Result<()> {
+        *self.snapshot.lock().unwrap() = Some(snapshot.clone());
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<Option<KVSnapshot>> {
+        Ok(self.snapshot.lock().unwrap().clone())
+    }
+}
+
+//the state kept in a snapshot file, written next to the old one and moved over it, so a
+//crash while writing leaves the old state in place
+pub(crate) struct SnapshotFile {
+    path: PathBuf,
+}
+
+impl SnapshotFile {
+    pub fn new(path: &Path) -> Self {
+        SnapshotFile {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl StateStore for SnapshotFile {
+    fn save(&self, snapshot: &KVSnapshot) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, &persist::encode(snapshot))
+    }
+
+    fn load(&self) -> io::Result<Option<KVSnapshot>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        persist::decode(&bytes)
+            .map(Some)
+            .map_err(|e| invalid("snapshot", e))
+    }
+}
+
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, bytes)?;
+    fs::File::open(&tmp)?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+//a file of another version is unsupported and not corrupted, it is never repaired
+pub(crate) fn invalid(what: &str, e: FileErr) -> io::Error {
+    match e {
+        FileErr::Version(v) => {
+            let msg = format!("unknown {} version {}", what, v);
+            io::Error::new(io::ErrorKind::Unsupported, msg)
+        }
+        e => {
+            let msg = format!("corrupted {}: {:?}", what, e);
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        }
+    }
+}
+
+fn needs_path(engine: Engine, path: Option<&Path>) -> io::Result<&Path> {
+    path.ok_or_else(|| {
+        let msg = format!("the {} storage needs a data directory", engine.name());
+        io::Error::new(io::ErrorKind::InvalidInput, msg)
+    })
+}
+
+impl Backend for MemoryStorage<LogEntry, KVSnapshot> {
+    const ENGINE: Engine = Engine::Memory;
+
+    fn open(_: Option<&Path>, _: &StorageOptions) -> io::Result<(Self, Recovery)> {
+        Ok((MemoryStorage::default(), Recovery::default()))
+    }
+}
+
+impl Backend for WalStorage {
+    const ENGINE: Engine = Engine::File;
+
+    fn open(path: Option<&Path>, options: &StorageOptions) -> io::Result<(Self, Recovery)> {
+        let path = needs_path(Self::ENGINE, path)?;
+        let (storage, replay) = WalStorage::open(path, options.segment_bytes)?;
+        let state = &replay.state;
+        let repairs = replay.corrupt.map(|(segment, offset)| Repair::Segment {
+            segment,
+            offset,
+            bytes: replay.discarded,
+        });
+        let recovery = Recovery {
+            records: replay.records,
+            runs: 0,
+            decided: state.decided,
+            compacted: state.compacted,
+            snapshot: state.snapshot.is_some(),
+            torn: replay.torn,
+            repairs: repairs.into_iter().collect(),
+            end: state.compacted + state.log.len() as u64,
+        };
+        Ok((storage, recovery))
+    }
+
+    fn sync_handle(&self) -> Option<Arc<Wal>> {
+        Some(self.wal().clone())
+    }
+}
+
+impl Backend for LsmStorage {
+    const ENGINE: Engine = Engine::Lsm;
+
+    fn open(path: Option<&Path>, options: &StorageOptions) -> io::Result<(Self, Recovery)> {
+        let path = needs_path(Self::ENGINE, path)?;
+        LsmStorage::open(path, options)
+    }
+
+    fn sync_handle(&self) -> Option<Arc<Wal>> {
+        Some(self.wal().clone())
+    }
+}
@@ -0,0 +1,600 @@
+#![allow(dead_code)]
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+
+use omnipaxos_core::ballot_leader_election::Ballot;
+use omnipaxos_core::storage::{StopSignEntry, Storage};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::kv::{KVSnapshot, LogEntry};
+use super::persist::crc32;
+
+//the log storage of a node on disk: every change of the storage is a record appended to
+//a write-ahead log before it is applied, and the storage is rebuilt by replaying the log.
+//The log is a directory of numbered segments, a segment is a sequence of records
+//`<length u32> <crc32 u32> <json>` (little endian), so a record torn by a crash is
+//detected and dropped, and a record corrupted on disk is detected and the log is cut
+//before it, the peers send what was cut off again. A new segment starts once a segment
+//is full, and once the log was compacted a new segment starts with the whole storage and
+//the older ones are deleted.
+
+//a change of the storage
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum WalRecord {
+    //the log from `at` on is replaced by the entries
+    Append { at: u64, entries: Vec<LogEntry> },
+    Promise(Ballot),
+    Accepted(Ballot),
+    Decided(u64),
+    Trim(u64),
+    Compacted(u64),
+    Snapshot(KVSnapshot),
+    //the whole storage, the first record of a segment
+    Checkpoint(LogState),
+}
+
+//what the storage keeps, the same as the memory storage of Omni-Paxos: the log starts
+//at the compacted index and the indexes the storage is called with are relative to it
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct LogState {
+    pub log: Vec<LogEntry>,
+    pub promise: Ballot,
+    pub accepted: Ballot,
+    pub decided: u64,
+    pub compacted: u64,
+    pub snapshot: Option<KVSnapshot>,
+}
+
+impl LogState {
+    pub fn apply(&mut self, record: WalRecord) {
+        match record {
+            WalRecord::Append { at, entries } => {
+                self.log.truncate(at as usize);
+                self.log.extend(entries);
+            }
+            WalRecord::Promise(ballot) => self.promise = ballot,
+            WalRecord::Accepted(ballot) => self.accepted = ballot,
+            WalRecord::Decided(index) => self.decided = index,
+            WalRecord::Trim(index) => {
+                let end = (index as usize).min(self.log.len());
+                self.log.drain(0..end);
+            }
+            WalRecord::Compacted(index) => self.compacted = index,
+            WalRecord::Snapshot(snapshot) => self.snapshot = Some(snapshot),
+            WalRecord::Checkpoint(state) => *self = state,
+        }
+    }
+}
+
+//a record, or any other value framed the same way with its length and checksum
+pub(crate) fn frame<T: Serialize>(value: &T) -> Vec<u8> {
+    let body = serde_json::to_vec(value).unwrap();
+    let mut bytes = Vec::with_capacity(body.len() + 8);
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+//the value framed at the start of `bytes` and the length of its frame, None if the frame
+//is not whole or its checksum does not match
+pub(crate) fn unframe<T: DeserializeOwned>(bytes: &[u8]) -> Option<(T, usize)> {
+    let length = u32::from_le_bytes(bytes.get(0..4)?.try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(bytes.get(4..8)?.try_into().unwrap());
+    let body = bytes.get(8..8 + length).filter(|body| crc32(body) == crc)?;
+    let value = serde_json::from_slice(body).ok()?;
+    Some((value, 8 + length))
+}
+
+//the records of a segment and the offset of the first byte that is not a whole record
+pub(crate) fn records<T: DeserializeOwned>(bytes: &[u8]) -> (Vec<T>, usize) {
+    let mut records = vec![];
+    let mut offset = 0;
+    while let Some((record, length)) = unframe(&bytes[offset..]) {
+        records.push(record);
+        offset += length;
+    }
+    (records, offset)
+}
+
+//the segments of a log directory in order, by number
+pub(crate) fn segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".seg"))
+            .and_then(|number| number.parse().ok());
+        if let Some(number) = number {
+            segments.push((number, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:08}.seg", number))
+}
+
+//the records of the segments of a log directory in order up to the first one that is
+//not whole: with nothing whole after it, it is a record torn by a crash, else the log is
+//corrupted and everything from that record on is discarded
+#[derive(Debug, Default)]
+pub(crate) struct Records {
+    pub segments: Vec<(u64, Vec<WalRecord>)>,
+    //the segment and offset of a torn record at the end of the log
+    pub torn: Option<(u64, u64)>,
+    //the segment and offset of a corrupted record, and the bytes from it on
+    pub corrupt: Option<(u64, u64)>,
+    pub discarded: u64,
+    //the newest promise in the whole segments after a corrupted record: the entries are
+    //cut, but a node must never forget a ballot it promised
+    pub promise: Option<Ballot>,
+}
+
+impl Records {
+    fn discard(&mut self, records: Vec<WalRecord>) {
+        for record in records {
+            match record {
+                WalRecord::Promise(ballot) => self.promise = Some(ballot),
+                WalRecord::Checkpoint(state) => self.promise = Some(state.promise),
+                _ => {}
+            }
+        }
+    }
+
+    //the records written after a cut: the promise of the discarded records is kept, the
+    //accepted round is reset as the entries accepted in it may be cut, a node that
+    //reported it with a shorter log could have its accepted entries overwritten
+    pub fn kept(&self) -> Vec<WalRecord> {
+        if self.corrupt.is_none() {
+            return vec![];
+        }
+        let promise = self.promise.map(WalRecord::Promise);
+        let accepted = WalRecord::Accepted(Ballot::default());
+        promise.into_iter().chain([accepted]).collect()
+    }
+}
+
+//the whole records of a segment from `offset` on, the bytes that are not a whole record
+//are skipped: what is left of a log after a corrupted record
+fn salvage(bytes: &[u8], mut offset: usize) -> Vec<WalRecord> {
+    let mut salvaged = vec![];
+    while offset < bytes.len() {
+        match unframe(&bytes[offset..]) {
+            Some((record, length)) => {
+                salvaged.push(record);
+                offset += length;
+            }
+            None => offset += 1,
+        }
+    }
+    salvaged
+}
+
+//read a log directory without changing it; a record that is not whole is torn by a crash
+//only if nothing whole follows it, in its segment or in a later one
+pub(crate) fn read(dir: &Path) -> io::Result<Records> {
+    let mut read = Records::default();
+    let segments = segments(dir)?;
+    for (i, (number, path)) in segments.iter().enumerate() {
+        let bytes = fs::read(path)?;
+        if read.corrupt.is_some() {
+            read.discarded += bytes.len() as u64;
+            read.discard(salvage(&bytes, 0));
+            continue;
+        }
+        let (records, end) = records(&bytes);
+        read.segments.push((*number, records));
+        if end < bytes.len() {
+            let after = salvage(&bytes, end);
+            match i + 1 < segments.len() || !after.is_empty() {
+                true => read.corrupt = Some((*number, end as u64)),
+                false => read.torn = Some((*number, end as u64)),
+            }
+            read.discard(after);
+            read.discarded += (bytes.len() - end) as u64;
+        }
+    }
+    Ok(read)
+}
+
+//what replaying a log directory found
+#[derive(Debug, Default)]
+pub(crate) struct Replay {
+    pub state: LogState,
+    pub records: u64,
+    pub segments: Vec<u64>,
+    //the segment and offset of a torn record at the end of the log
+    pub torn: Option<(u64, u64)>,
+    //the segment and offset of a corrupted record, the log is replayed up to it
+    pub corrupt: Option<(u64, u64)>,
+    pub discarded: u64,
+}
+
+impl Replay {
+    fn new(read: Records) -> Self {
+        let mut replay = Replay {
+            torn: read.torn,
+            corrupt: read.corrupt,
+            discarded: read.discarded,
+            ..Default::default()
+        };
+        for (number, records) in read.segments {
+            replay.records += records.len() as u64;
+            for record in records {
+                replay.state.apply(record);
+            }
+            replay.segments.push(number);
+        }
+        replay
+    }
+}
+
+//rebuild the storage from a log directory without changing it
+pub(crate) fn replay(dir: &Path) -> io::Result<Replay> {
+    read(dir).map(Replay::new)
+}
+
+struct Writer {
+    file: Arc<File>,
+    segment: u64,
+    size: u64,
+    //the records waiting for the next fsync, and how many were queued and synced so far
+    pending: Vec<u8>,
+    queued: u64,
+    synced: u64,
+    syncing: bool,
+    syncs: u64,
+    failed: Option<io::ErrorKind>,
+}
+
+//the write-ahead log, appends from many threads are written together with one fsync:
+//an append that finds no write in progress writes every queued record, the appends
+//queued meanwhile wait for it and the next write takes all of them at once. A record can
+//also be queued without waiting, it is on disk after the next sync
+pub(crate) struct Wal {
+    dir: PathBuf,
+    segment_bytes: u64,
+    writer: Mutex<Writer>,
+    synced: Condvar,
+}
+
+impl Wal {
+    //open the log in `dir`, created if it does not exist, and replay it
+    pub fn open(dir: &Path, segment_bytes: u64) -> io::Result<(Self, Replay)> {
+        let (wal, read) = Wal::recover(dir, segment_bytes)?;
+        Ok((wal, Replay::new(read)))
+    }
+
+    //open the log in `dir` with the records it holds; a torn record at its end is cut
+    //off so the next records follow the last whole one, and a corrupted log is cut at the
+    //corrupted record, the segments after it are deleted, the promise they held is written
+    //again after the cut and the accepted round is reset
+    pub fn recover(dir: &Path, segment_bytes: u64) -> io::Result<(Self, Records)> {
+        fs::create_dir_all(dir)?;
+        let mut read = read(dir)?;
+        let segment = read.segments.last().map_or(1, |(number, _)| *number);
+        for (number, path) in segments(dir)? {
+            if number > segment {
+                fs::remove_file(path)?;
+            }
+        }
+        let path = segment_path(dir, segment);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if let Some((_, offset)) = read.torn.or(read.corrupt) {
+            file.set_len(offset)?;
+            let kept = read.kept();
+            for record in &kept {
+                (&file).write_all(&frame(record))?;
+            }
+            file.sync_all()?;
+            if let Some((_, records)) = read.segments.last_mut() {
+                records.extend(kept);
+            }
+        }
+        let size = file.metadata()?.len();
+        sync_dir(dir)?;
+        let writer = Writer {
+            file: Arc::new(file),
+            segment,
+            size,
+            pending: vec![],
+            queued: 0,
+            synced: 0,
+            syncing: false,
+            syncs: 0,
+            failed: None,
+        };
+        let wal = Wal {
+            dir: dir.to_path_buf(),
+            segment_bytes,
+            writer: Mutex::new(writer),
+            synced: Condvar::new(),
+        };
+        Ok((wal, read))
+    }
+
+    //append a record and return once it is on disk
+    pub fn append(&self, record: &WalRecord) -> io::Result<()> {
+        let seq = self.queue(record)?;
+        self.sync_to(seq)
+    }
+
+    //queue a record for the next write and return its sequence number without waiting
+    pub fn queue(&self, record: &WalRecord) -> io::Result<u64> {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(kind) = writer.failed {
+            return Err(io::Error::new(kind, "the write-ahead log failed"));
+        }
+        writer.pending.extend(frame(record));
+        writer.queued += 1;
+        Ok(writer.queued)
+    }
+
+    //write every queued record with one fsync and return once they are on disk
+    pub fn sync(&self) -> io::Result<()> {
+        let seq = self.writer.lock().unwrap().queued;
+        self.sync_to(seq)
+    }
+
+    //return once the records up to `seq` are on disk, writing them if no write is in
+    //progress
+    fn sync_to(&self, seq: u64) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        loop {
+            if writer.synced >= seq {
+                return Ok(());
+            }
+            if let Some(kind) = writer.failed {
+                return Err(io::Error::new(kind, "the write-ahead log failed"));
+            }
+            if writer.syncing {
+                writer = self.synced.wait(writer).unwrap();
+                continue;
+            }
+            //no write in progress, this one writes every queued record
+            writer.syncing = true;
+            let batch = std::mem::take(&mut writer.pending);
+            let queued = writer.queued;
+            let file = writer.file.clone();
+            drop(writer);
+            let result = (&*file).write_all(&batch).and_then(|_| file.sync_data());
+            writer = self.writer.lock().unwrap();
+            writer.syncing = false;
+            let result = result.and_then(|_| {
+                writer.synced = queued;
+                writer.size += batch.len() as u64;
+                writer.syncs += 1;
+                match writer.size >= self.segment_bytes {
+                    true => self.next_segment(&mut writer, &[]).map(|_| ()),
+                    false => Ok(()),
+                }
+            });
+            if let Err(e) = result {
+                writer.failed = Some(e.kind());
+            }
+            self.synced.notify_all();
+        }
+    }
+
+    //start a new segment with the whole storage and delete the older segments, e.g.
+    //once the log was compacted into a snapshot
+    pub fn checkpoint(&self, state: &LogState) -> io::Result<()> {
+        let first = frame(&WalRecord::Checkpoint(state.clone()));
+        let segment = self.locked(|writer| self.next_segment(writer, &first))?;
+        self.remove_before(segment)
+    }
+
+    //write the queued records and continue in a new segment that starts with `first`,
+    //the records appended from now on are in the segment it returns
+    pub fn rotate(&self, first: &[WalRecord]) -> io::Result<u64> {
+        let first: Vec<u8> = first.iter().flat_map(frame).collect();
+        self.locked(|writer| self.next_segment(writer, &first))
+    }
+
+    //delete the segments before `segment`
+    pub fn remove_before(&self, segment: u64) -> io::Result<()> {
+        for (number, path) in segments(&self.dir)? {
+            if number < segment {
+                fs::remove_file(path)?;
+            }
+        }
+        sync_dir(&self.dir)
+    }
+
+    //run `f` once no write is in progress and every queued record is on disk
+    fn locked<R>(&self, f: impl FnOnce(&mut Writer) -> io::Result<R>) -> io::Result<R> {
+        let mut writer = self.writer.lock().unwrap();
+        while writer.syncing {
+            writer = self.synced.wait(writer).unwrap();
+        }
+        let result = self.flush(&mut writer).and_then(|_| f(&mut writer));
+        if let Err(e) = &result {
+            writer.failed = Some(e.kind());
+        }
+        self.synced.notify_all();
+        result
+    }
+
+    fn flush(&self, writer: &mut Writer) -> io::Result<()> {
+        if !writer.pending.is_empty() {
+            let batch = std::mem::take(&mut writer.pending);
+            (&*writer.file).write_all(&batch)?;
+            writer.file.sync_data()?;
+            writer.synced = writer.queued;
+            writer.syncs += 1;
+        }
+        Ok(())
+    }
+
+    //continue in the next segment, which starts with `first`
+    fn next_segment(&self, writer: &mut Writer, first: &[u8]) -> io::Result<u64> {
+        let segment = writer.segment + 1;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&self.dir, segment))?;
+        file.write_all(first)?;
+        file.sync_all()?;
+        sync_dir(&self.dir)?;
+        writer.file = Arc::new(file);
+        writer.segment = segment;
+        writer.size = first.len() as u64;
+        Ok(segment)
+    }
+
+    //the number of fsyncs of appended records so far
+    pub fn syncs(&self) -> u64 {
+        self.writer.lock().unwrap().syncs
+    }
+}
+
+//the records still queued are written when the log is closed
+impl Drop for Wal {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+//make the creation and deletion of segments durable
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+//the Omni-Paxos storage on the write-ahead log: a change is queued in the log and
+//applied, and the node syncs the log before it sends the messages that answer the
+//changes, so the changes of a batch share one fsync; the reads are served from memory
+pub(crate) struct WalStorage {
+    wal: Arc<Wal>,
+    state: LogState,
+    //the log is never reconfigured, so the stop sign is not kept on disk
+    stopsign: Option<StopSignEntry>,
+}
+
+impl WalStorage {
+    pub fn open(dir: &Path, segment_bytes: u64) -> io::Result<(Self, Replay)> {
+        let (wal, replay) = Wal::open(dir, segment_bytes)?;
+        let storage = WalStorage {
+            wal: Arc::new(wal),
+            state: replay.state.clone(),
+            stopsign: None,
+        };
+        Ok((storage, replay))
+    }
+
+    pub fn state(&self) -> &LogState {
+        &self.state
+    }
+
+    pub fn wal(&self) -> &Arc<Wal> {
+        &self.wal
+    }
+
+    //a storage that can not write its log must not answer, so the node stops
+    fn write(&mut self, record: WalRecord) {
+        self.wal
+            .queue(&record)
+            .expect("Failed to write the write-ahead log");
+        self.state.apply(record);
+    }
+}
+
+impl Storage<LogEntry, KVSnapshot> for WalStorage {
+    fn append_entry(&mut self, entry: LogEntry) -> u64 {
+        self.append_entries(vec![entry])
+    }
+
+    fn append_entries(&mut self, entries: Vec<LogEntry>) -> u64 {
+        let at = self.state.log.len() as u64;
+        self.append_on_prefix(at, entries)
+    }
+
+    fn append_on_prefix(&mut self, from_idx: u64, entries: Vec<LogEntry>) -> u64 {
+        self.write(WalRecord::Append {
+            at: from_idx,
+            entries,
+        });
+        self.get_log_len()
+    }
+
+    fn set_promise(&mut self, n_prom: Ballot) {
+        self.write(WalRecord::Promise(n_prom));
+    }
+
+    fn set_decided_idx(&mut self, ld: u64) {
+        self.write(WalRecord::Decided(ld));
+    }
+
+    fn get_decided_idx(&self) -> u64 {
+        self.state.decided
+    }
+
+    fn set_accepted_round(&mut self, na: Ballot) {
+        self.write(WalRecord::Accepted(na));
+    }
+
+    fn get_accepted_round(&self) -> Ballot {
+        self.state.accepted
+    }
+
+    fn get_entries(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        let log = &self.state.log;
+        let to = (to as usize).min(log.len());
+        log.get(from as usize..to).unwrap_or(&[]).to_vec()
+    }
+
+    fn get_log_len(&self) -> u64 {
+        self.state.log.len() as u64
+    }
+
+    fn get_suffix(&self, from: u64) -> Vec<LogEntry> {
+        self.state.log.get(from as usize..).unwrap_or(&[]).to_vec()
+    }
+
+    fn get_promise(&self) -> Ballot {
+        self.state.promise
+    }
+
+    fn set_stopsign(&mut self, s: StopSignEntry) {
+        self.stopsign = Some(s);
+    }
+
+    fn get_stopsign(&self) -> Option<StopSignEntry> {
+        self.stopsign.clone()
+    }
+
+    fn trim(&mut self, idx: u64) {
+        self.write(WalRecord::Trim(idx));
+    }
+
+    //the compaction is complete, the segments before it are covered by the new one
+    fn set_compacted_idx(&mut self, idx: u64) {
+        self.write(WalRecord::Compacted(idx));
+        self.wal
+            .checkpoint(&self.state)
+            .expect("Failed to write the write-ahead log");
+    }
+
+    fn get_compacted_idx(&self) -> u64 {
+        self.state.compacted
+    }
+
+    fn set_snapshot(&mut self, snapshot: KVSnapshot) {
+        self.write(WalRecord::Snapshot(snapshot));
+    }
+
+    fn get_snapshot(&self) -> Option<KVSnapshot> {
+        self.state.snapshot.clone()
+    }
+}
@@ -0,0 +1,165 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time;
+
+use crate::configs::client::CLIENT_ADDR;
+use crate::configs::server::START_PORT;
+use crate::models::package::Package;
+
+pub mod fault;
+pub mod read;
+pub mod sim;
+pub mod transfer;
+
+use fault::FaultLayer;
+use read::Reads;
+use transfer::Transfers;
+
+//the transport used between the nodes and towards the client
+#[derive(Clone)]
+pub(crate) enum Transport {
+    //real sockets, one node per process
+    Tcp,
+    //seeded in-memory network, the whole cluster in one process
+    Sim(sim::SimNetwork),
+}
+
+//a transport with the fault injection layer in front of it
+#[derive(Clone)]
+pub(crate) struct Network {
+    pub transport: Transport,
+    pub faults: FaultLayer,
+    pub transfers: Transfers,
+    pub reads: Reads,
+}
+
+impl Network {
+    pub fn tcp(pid: u64) -> Self {
+        Network {
+            transport: Transport::Tcp,
+            faults: FaultLayer::new(pid),
+            transfers: Transfers::default(),
+            reads: Reads::default(),
+        }
+    }
+
+    pub fn sim(sim: sim::SimNetwork, faults: FaultLayer) -> Self {
+        Network {
+            transport: Transport::Sim(sim),
+            faults,
+            transfers: Transfers::default(),
+            reads: Reads::default(),
+        }
+    }
+
+    //send a package from node `from` to node `to` through the fault layer
+    pub async fn send(&self, from: u64, to: u64, pkg: Package) {
+        let copies = self.faults.plan(from, to);
+        if copies.is_empty() {
+            if let Transport::Sim(sim) = &self.transport {
+                sim.lost(from, to, &pkg);
+            }
+            return;
+        }
+        for delay in copies {
+            let pkg = pkg.clone();
+            match &self.transport {
+                Transport::Tcp if delay == 0 => tcp_send(to, pkg).await,
+                Transport::Tcp => {
+                    tokio::spawn(async move {
+                        time::sleep(Duration::from_millis(delay)).await;
+                        tcp_send(to, pkg).await;
+                    });
+                }
+                Transport::Sim(sim) => sim.send(from, to, pkg, delay).await,
+            }
+        }
+    }
+
+    //open a stream to the client of the command `from` handles now, instead of replying
+    pub fn stream(&self, from: u64) -> ClientStream {
+        let client = match &self.transport {
+            Transport::Tcp => None,
+            Transport::Sim(sim) => Some(sim.requester(from)),
+        };
+        ClientStream {
+            network: self.clone(),
+            from,
+            client,
+            connection: Mutex::new(None),
+        }
+    }
+
+    //send a reply from node `from` to the client, return false if it can not be reached
+    pub async fn reply(&self, from: u64, str: &str) -> bool {
+        match &self.transport {
+            Transport::Tcp => {
+                if let Ok(mut tcp_stream) = TcpStream::connect(CLIENT_ADDR).await {
+                    let (_, mut write) = tcp_stream.split();
+                    write.write_all(str.as_bytes()).await.unwrap();
+                    true
+                } else {
+                    false
+                }
+            }
+            Transport::Sim(sim) => {
+                sim.reply(from, str).await;
+                true
+            }
+        }
+    }
+}
+
+//a stream of messages to the client whose command the node handles now
+pub(crate) struct ClientStream {
+    network: Network,
+    from: u64,
+    //the simulated client, over tcp every message goes to the client address
+    client: Option<u64>,
+    //over tcp the stream keeps one connection to the client, a message per line
+    connection: Mutex<Option<TcpStream>>,
+}
+
+impl ClientStream {
+    //false once the client is gone
+    pub async fn send(&self, str: &str) -> bool {
+        match (&self.network.transport, self.client) {
+            (Transport::Sim(sim), Some(client)) => sim.push(self.from, client, str),
+            _ => {
+                let mut connection = self.connection.lock().await;
+                if connection.is_none() {
+                    *connection = TcpStream::connect(CLIENT_ADDR).await.ok();
+                }
+                let line = format!("{}\n", str);
+                let sent = match connection.as_mut() {
+                    Some(tcp_stream) => tcp_stream.write_all(line.as_bytes()).await.is_ok(),
+                    None => false,
+                };
+                //the client closed the connection, it is gone
+                if !sent {
+                    *connection = None;
+                }
+                sent
+            }
+        }
+    }
+}
+
+async fn tcp_send(to: u64, pkg: Package) {
+    let serialized = serde_json::to_string(&pkg).unwrap();
+    if let Ok(mut tcp_stream) = TcpStream::connect(node_addr(to)).await {
+        let (_, mut write) = tcp_stream.split();
+        write.write_all(serialized.as_bytes()).await.unwrap();
+    }
+}
+
+//the address of the node with the given pid
+pub(crate) fn node_addr(pid: u64) -> SocketAddr {
+    let port = START_PORT + pid;
+    let addr = "127.0.0.1:".to_string() + &port.to_string();
+    addr.parse().unwrap()
+}
@@ -0,0 +1,58 @@
+use std::sync::{Arc, Mutex};
+
+use crate::configs::server::REORDER_WINDOW;
+use crate::models::fault::FaultConfig;
+
+use super::sim::SimRng;
+
+struct FaultState {
+    config: FaultConfig,
+    rng: SimRng,
+}
+
+//middleware between the outgoing threads and the transport that drops, delays,
+//duplicates and reorders packages and cuts the links between partitioned nodes
+#[derive(Clone)]
+pub(crate) struct FaultLayer {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultLayer {
+    pub fn new(seed: u64) -> Self {
+        FaultLayer {
+            state: Arc::new(Mutex::new(FaultState {
+                config: FaultConfig::default(),
+                rng: SimRng::new(seed),
+            })),
+        }
+    }
+
+    //replace the injected faults, the default config heals everything
+    pub fn set(&self, config: FaultConfig) {
+        self.state.lock().unwrap().config = config;
+    }
+
+    pub fn connected(&self, a: u64, b: u64) -> bool {
+        self.state.lock().unwrap().config.connected(a, b)
+    }
+
+    //decide the fate of one package from `from` to `to`:
+    //the delay in ms of every copy to send, empty if it is lost
+    pub fn plan(&self, from: u64, to: u64) -> Vec<u64> {
+        let mut state = self.state.lock().unwrap();
+        let FaultState { config, rng } = &mut *state;
+        if !config.connected(from, to) || rng.below(100) < config.drop {
+            return vec![];
+        }
+        let mut copies = vec![config.delay];
+        if rng.below(100) < config.duplicate {
+            copies.push(config.delay);
+        }
+        for delay in copies.iter_mut() {
+            if rng.below(100) < config.reorder {
+                *delay += 1 + rng.below(REORDER_WINDOW);
+            }
+        }
+        copies
+    }
+}
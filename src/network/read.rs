@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+
+use crate::configs::server::PROPOSAL_TIMEOUT;
+use crate::models::msg::{Msg, ReadIndex, ReadIndexAck};
+use crate::models::package::{Package, Types};
+
+use super::Network;
+
+#[derive(Default)]
+struct ReadState {
+    next_id: u64,
+    //the acks of the reads this node makes, by read id, with the peer they came from
+    pending: HashMap<u64, mpsc::UnboundedSender<(u64, u64)>>,
+}
+
+//the read index of a linearizable read: a write that was decided before the read began is
+//in the log of a majority, so the longest log of any majority reaches past it, and a
+//node that applied up to there answers with every such write, without a log entry
+#[derive(Clone, Default)]
+pub(crate) struct Reads {
+    state: Arc<Mutex<ReadState>>,
+}
+
+impl Reads {
+    //the longest log of a majority of the cluster, `own` is the end of the log of this
+    //node, None if not enough peers answered in time
+    pub async fn index(&self, network: &Network, pid: u64, peers: &[u64], own: u64) -> Option<u64> {
+        let (sender, mut acks) = mpsc::unbounded_channel();
+        let read = {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+            let read = state.next_id;
+            state.pending.insert(read, sender);
+            read
+        };
+        for to in peers {
+            let request = ReadIndex {
+                from: pid,
+                to: *to,
+                read,
+            };
+            let pkg = Package {
+                types: Types::Read,
+                msg: Msg::ReadIndex(request),
+            };
+            network.send(pid, *to, pkg).await;
+        }
+
+        //this node is part of the majority, a duplicated ack is counted once
+        let cluster = peers.len() + 1;
+        let majority = cluster / 2 + 1;
+        let mut answered = HashMap::from([(pid, own)]);
+        let deadline = Instant::now() + Duration::from_millis(PROPOSAL_TIMEOUT);
+        while answered.len() < majority {
+            match time::timeout_at(deadline, acks.recv()).await {
+                Ok(Some((from, index))) => {
+                    answered.insert(from, index);
+                }
+                _ => break,
+            }
+        }
+        self.state.lock().unwrap().pending.remove(&read);
+        match answered.len() >= majority {
+            true => answered.into_values().max(),
+            false => None,
+        }
+    }
+
+    //hand an ack to the read it belongs to
+    pub fn acked(&self, ack: ReadIndexAck) {
+        if let Some(sender) = self.state.lock().unwrap().pending.get(&ack.read) {
+            let _ = sender.send((ack.from, ack.index));
+        }
+    }
+}
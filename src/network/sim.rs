@@ -0,0 +1,223 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time::{self, Instant};
+
+use crate::configs::server::{SIM_JITTER, SIM_LATENCY};
+use crate::models::package::Package;
+
+//xorshift64* generator, the same seed always gives the same sequence
+pub(crate) struct SimRng(u64);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        //the state must never be zero
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    //a number in [0, n)
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+struct SimState {
+    rng: SimRng,
+    start: Instant,
+    inboxes: HashMap<u64, Sender<Package>>,
+    pending: HashMap<u64, Receiver<Package>>,
+    clients: HashMap<u64, Sender<String>>,
+    //the clients waiting for a reply from each node, in the order their commands arrived
+    requesters: HashMap<u64, VecDeque<u64>>,
+    //when the last message to each client arrives, so the messages to a client keep their order
+    delivered: HashMap<u64, Instant>,
+    trace: Vec<String>,
+}
+
+impl SimState {
+    fn record(&mut self, event: String) {
+        let now = self.start.elapsed().as_millis();
+        self.trace.push(format!("{:>8}ms {}", now, event));
+    }
+
+    fn delay(&mut self) -> Duration {
+        Duration::from_millis(SIM_LATENCY + self.rng.below(SIM_JITTER + 1))
+    }
+}
+
+//in-memory network where every delivery is delayed by a seeded random latency,
+//run on a paused tokio clock so the time is virtual as well
+#[derive(Clone)]
+pub(crate) struct SimNetwork {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl SimNetwork {
+    pub fn new(seed: u64) -> Self {
+        SimNetwork {
+            state: Arc::new(Mutex::new(SimState {
+                rng: SimRng::new(seed),
+                start: Instant::now(),
+                inboxes: HashMap::new(),
+                pending: HashMap::new(),
+                clients: HashMap::new(),
+                requesters: HashMap::new(),
+                delivered: HashMap::new(),
+                trace: vec![],
+            })),
+        }
+    }
+
+    //create the inbox of a node, must be done for every node before any of them starts
+    pub fn register(&self, pid: u64) {
+        let (sender, receiver) = mpsc::channel::<Package>(1024);
+        let mut state = self.state.lock().unwrap();
+        state.inboxes.insert(pid, sender);
+        state.pending.insert(pid, receiver);
+    }
+
+    //take a node that was shut down off the network: what is sent to it is lost until it
+    //is registered again, and the clients still waiting for it time out
+    pub fn unregister(&self, pid: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.inboxes.remove(&pid);
+        state.pending.remove(&pid);
+        state.requesters.remove(&pid);
+    }
+
+    //hand the inbox of a registered node to its forward thread
+    pub fn inbox(&self, pid: u64) -> Receiver<Package> {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .remove(&pid)
+            .expect("Node is not registered in the simulated network")
+    }
+
+    //create the channel that receives the replies meant for a client
+    pub fn register_client(&self, client: u64) -> Receiver<String> {
+        let (sender, receiver) = mpsc::channel::<String>(1024);
+        self.state.lock().unwrap().clients.insert(client, sender);
+        receiver
+    }
+
+    //deliver a package after a random delay plus `extra` ms
+    pub async fn send(&self, from: u64, to: u64, pkg: Package, extra: u64) {
+        let (delay, inbox) = {
+            let mut state = self.state.lock().unwrap();
+            let delay = state.delay() + Duration::from_millis(extra);
+            (delay, state.inboxes.get(&to).cloned())
+        };
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            state
+                .lock()
+                .unwrap()
+                .record(format!("{} -> {} {:?}", from, to, pkg.types));
+            if let Some(inbox) = inbox {
+                let _ = inbox.send(pkg).await;
+            }
+        });
+    }
+
+    //deliver a command of a client after a random delay, clients are not subject to faults;
+    //a node that is down when it arrives never answers it
+    pub async fn request(&self, client: u64, to: u64, pkg: Package) {
+        let delay = self.state.lock().unwrap().delay();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            let inbox = {
+                let mut state = state.lock().unwrap();
+                state.record(format!("client{} -> {} {:?}", client, to, pkg.types));
+                let inbox = state.inboxes.get(&to).cloned();
+                if inbox.is_some() {
+                    state.requesters.entry(to).or_default().push_back(client);
+                }
+                inbox
+            };
+            if let Some(inbox) = inbox {
+                let _ = inbox.send(pkg).await;
+            }
+        });
+    }
+
+    //record a package lost by the fault layer
+    pub fn lost(&self, from: u64, to: u64, pkg: &Package) {
+        self.record(format!("{} -> {} {:?} lost", from, to, pkg.types));
+    }
+
+    //deliver a reply to the client whose command `from` handled
+    pub async fn reply(&self, from: u64, str: &str) {
+        let client = self.requester(from);
+        self.push(from, client, str);
+    }
+
+    //the client whose command `from` handles now,
+    //a node answers its commands one by one in the order they arrived
+    pub fn requester(&self, from: u64) -> u64 {
+        self.state
+            .lock()
+            .unwrap()
+            .requesters
+            .get_mut(&from)
+            .and_then(|queue| queue.pop_front())
+            .expect("Reply without a command")
+    }
+
+    //deliver a message to a client after a random delay, the messages to a client
+    //arrive in the order they are sent, false if the client is gone
+    pub fn push(&self, from: u64, client: u64, str: &str) -> bool {
+        let at = {
+            let mut state = self.state.lock().unwrap();
+            if !state.clients.contains_key(&client) {
+                return false;
+            }
+            let mut at = Instant::now() + state.delay();
+            if let Some(last) = state.delivered.get(&client) {
+                at = at.max(*last + Duration::from_millis(1));
+            }
+            state.delivered.insert(client, at);
+            at
+        };
+        let state = self.state.clone();
+        let str = str.to_string();
+        tokio::spawn(async move {
+            time::sleep_until(at).await;
+            let sender = {
+                let mut state = state.lock().unwrap();
+                state.record(format!("{} -> client{} {}", from, client, str));
+                state.clients.get(&client).cloned()
+            };
+            if let Some(sender) = sender {
+                let _ = sender.send(str).await;
+            }
+        });
+        true
+    }
+
+    //a number in [0, n) drawn from the seeded generator
+    pub fn random(&self, n: u64) -> u64 {
+        self.state.lock().unwrap().rng.below(n)
+    }
+
+    //add an event to the trace
+    pub fn record(&self, event: String) {
+        self.state.lock().unwrap().record(event);
+    }
+
+    pub fn trace(&self) -> Vec<String> {
+        self.state.lock().unwrap().trace.clone()
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+
+use crate::configs::server::{
+    TRANSFER_ACK_TIMEOUT, TRANSFER_CHUNK, TRANSFER_EXPIRY, TRANSFER_RETRIES, TRANSFER_WINDOW_CHUNKS,
+};
+use crate::models::msg::{Chunk, ChunkAck, Msg};
+use crate::models::package::{Package, Types};
+use crate::models::persist::crc32;
+
+use super::Network;
+
+//a transfer this node receives and when its last chunk came in
+struct Incoming {
+    total: u64,
+    crc: u32,
+    chunks: Vec<String>,
+    seen: Instant,
+}
+
+//a transfer that was handed on, kept without its chunks so late copies of them are
+//only acknowledged
+struct Finished {
+    total: u64,
+    crc: u32,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct TransferState {
+    next_id: u64,
+    //the acks of the transfers this node sends, by transfer id
+    outgoing: HashMap<u64, mpsc::UnboundedSender<u64>>,
+    //the transfers this node receives, by sender and transfer id
+    incoming: HashMap<(u64, u64), Incoming>,
+    finished: HashMap<(u64, u64), Finished>,
+}
+
+impl TransferState {
+    //drop the transfers no chunk came in for since TRANSFER_EXPIRY ms, their sender gave
+    //up or is gone, and forget the finished ones once their late copies are over
+    fn expire(&mut self, now: Instant) {
+        let expiry = Duration::from_millis(TRANSFER_EXPIRY);
+        self.incoming
+            .retain(|_, incoming| now.duration_since(incoming.seen) < expiry);
+        self.finished
+            .retain(|_, finished| now.duration_since(finished.at) < expiry);
+    }
+}
+
+//large SP messages, like the snapshot for a follower behind the compaction point, go
+//apart from the SP path in bounded chunks: the receiver acknowledges the next chunk it
+//needs, so a lost chunk resumes the transfer from there, and it checks the checksum of
+//the whole message before handing it on
+#[derive(Clone, Default)]
+pub(crate) struct Transfers {
+    state: Arc<Mutex<TransferState>>,
+}
+
+impl Transfers {
+    //whether a serialized message is sent in chunks
+    pub fn large(msg: &str) -> bool {
+        msg.len() > TRANSFER_CHUNK
+    }
+
+    //send a serialized message from `from` to `to` in chunks until the receiver has all
+    //of them, false if it gave up
+    pub async fn send(&self, network: &Network, from: u64, to: u64, msg: String) -> bool {
+        let chunks = split(&msg);
+        let total = chunks.len() as u64;
+        let crc = crc32(msg.as_bytes());
+        let (sender, mut acks) = mpsc::unbounded_channel();
+        let transfer = {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+            let transfer = state.next_id;
+            state.outgoing.insert(transfer, sender);
+            transfer
+        };
+
+        let mut next = 0;
+        let mut retries = 0;
+        while next < total && retries < TRANSFER_RETRIES {
+            let end = total.min(next + TRANSFER_WINDOW_CHUNKS);
+            for index in next..end {
+                let chunk = Chunk {
+                    from,
+                    to,
+                    transfer,
+                    index,
+                    total,
+                    crc,
+                    data: chunks[index as usize].to_string(),
+                };
+                let pkg = Package {
+                    types: Types::Chunk,
+                    msg: Msg::Chunk(chunk),
+                };
+                network.send(from, to, pkg).await;
+            }
+            let deadline = Instant::now() + Duration::from_millis(TRANSFER_ACK_TIMEOUT);
+            loop {
+                match time::timeout_at(deadline, acks.recv()).await {
+                    Ok(Some(acked)) if acked >= end => {
+                        next = acked;
+                        retries = 0;
+                        break;
+                    }
+                    //the receiver lost its progress or the checksum failed
+                    Ok(Some(acked)) if acked < next => {
+                        next = acked;
+                        retries += 1;
+                        break;
+                    }
+                    Ok(Some(acked)) => next = acked,
+                    _ => {
+                        retries += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        self.state.lock().unwrap().outgoing.remove(&transfer);
+        next >= total
+    }
+
+    //take a chunk, the ack to send back and the whole message once it is complete
+    pub fn receive(&self, chunk: Chunk) -> (ChunkAck, Option<String>) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.expire(now);
+        let mut ack = ChunkAck {
+            from: chunk.to,
+            to: chunk.from,
+            transfer: chunk.transfer,
+            next: chunk.total,
+        };
+        //the ids start over when the sender restarts, so another message under a known
+        //id is a new transfer
+        let key = (chunk.from, chunk.transfer);
+        let message = (chunk.total, chunk.crc);
+        match state.finished.get(&key) {
+            Some(finished) if (finished.total, finished.crc) == message => return (ack, None),
+            Some(_) => {
+                state.finished.remove(&key);
+            }
+            None => {}
+        }
+        let known = state.incoming.get(&key).map(|i| (i.total, i.crc));
+        if known != Some(message) {
+            let incoming = Incoming {
+                total: chunk.total,
+                crc: chunk.crc,
+                chunks: vec![],
+                seen: now,
+            };
+            state.incoming.insert(key, incoming);
+        }
+        let incoming = state.incoming.get_mut(&key).unwrap();
+        incoming.seen = now;
+        //chunks are taken in order, a later one is sent again after the ack
+        if chunk.index == incoming.chunks.len() as u64 {
+            incoming.chunks.push(chunk.data);
+        }
+        if incoming.chunks.len() as u64 == incoming.total {
+            let whole = incoming.chunks.concat();
+            incoming.chunks.clear();
+            if crc32(whole.as_bytes()) == incoming.crc {
+                state.incoming.remove(&key);
+                let finished = Finished {
+                    total: chunk.total,
+                    crc: chunk.crc,
+                    at: now,
+                };
+                state.finished.insert(key, finished);
+                return (ack, Some(whole));
+            }
+        }
+        ack.next = incoming.chunks.len() as u64;
+        (ack, None)
+    }
+
+    //hand an ack to the transfer it belongs to
+    pub fn acked(&self, ack: ChunkAck) {
+        if let Some(sender) = self.state.lock().unwrap().outgoing.get(&ack.transfer) {
+            let _ = sender.send(ack.next);
+        }
+    }
+}
+
+//cut a message into chunks of at most TRANSFER_CHUNK bytes, on character boundaries
+fn split(msg: &str) -> Vec<&str> {
+    let mut chunks = vec![];
+    let mut rest = msg;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(TRANSFER_CHUNK);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
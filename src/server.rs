@@ -1,181 +1,710 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpListener;
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time;
 
 use omnipaxos_core::{
-    ballot_leader_election::messages::BLEMessage, messages::Message,
-    storage::memory_storage::MemoryStorage,
+    ballot_leader_election::messages::BLEMessage,
+    messages::Message,
+    storage::{memory_storage::MemoryStorage, Storage},
 };
 
-use omnipaxos_runtime::omnipaxos::{
-    NodeConfig, OmniPaxosHandle, OmniPaxosNode, ReadEntry, ReadEntry::Decided,
-    ReadEntry::Snapshotted,
-};
+use omnipaxos_runtime::omnipaxos::{NodeConfig, OmniPaxosHandle, OmniPaxosNode};
 
 use structopt::StructOpt;
 
 mod models;
-use crate::models::kv::{KVSnapshot, KeyValue};
-use crate::models::msg::{CMDMessage, Msg, Operation};
+use crate::models::export::{self, Format, Row};
+use crate::models::kv::{Command, KVSnapshot, LogEntry, Outcome, Record, ID_BITS};
+use crate::models::lsm::LsmStorage;
+use crate::models::msg::{CMDMessage, Msg, Operation, ReadIndex, ReadIndexAck};
 use crate::models::node::Node;
 use crate::models::package::{Package, Types};
+use crate::models::persist::Backup;
+use crate::models::storage::{Backend, Engine, Recovery, Repair, StateStore};
+use crate::models::wal::{Wal, WalStorage};
+
+mod compaction;
+use crate::compaction::{snapshot_thread, SnapshotPolicy};
+
+mod disk;
+use crate::disk::StorageConfig;
 
 mod configs;
-use crate::configs::client::CLIENT_ADDR;
+use crate::configs::server::APPLY_INTERVAL;
 use crate::configs::server::DEBUG_OUTPUT;
-use crate::configs::server::START_PORT;
+use crate::configs::server::SHUTDOWN_TIMEOUT;
+
+mod handover;
+use crate::handover::{transfer_thread, Steering, Transfer};
+
+mod network;
+use crate::network::transfer::Transfers;
+use crate::network::{node_addr, ClientStream, Network, Transport};
+
+mod shutdown;
+use crate::shutdown::Shutdown;
+
+mod simulation;
+use crate::simulation::simulate;
+
+mod store;
+use crate::store::{apply_thread, expire_thread, Event, PastErr, Store};
 
-#[tokio::main]
-async fn main() {
+fn main() {
     //get the args from terminal
     let node = Node::from_args();
 
+    //the pid is in the upper bits of the ids the node proposes
+    if node.pid >= 1 << (64 - ID_BITS) {
+        println!("Pid {} does not fit in {} bits", node.pid, 64 - ID_BITS);
+        process::exit(1);
+    }
+
+    match node.seed {
+        //run the whole cluster on a single thread with a paused clock,
+        //so the time only advances when every task is idle
+        Some(seed) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap()
+            .block_on(simulate(node, seed)),
+        None => {
+            if let Some(path) = &node.restore {
+                restore(path, &node);
+            }
+            let code = tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let (trigger, shutdown) = Shutdown::new();
+                tokio::spawn(async move {
+                    shutdown::signal().await;
+                    println!("Shutting down node {}", node.pid);
+                    let _ = trigger.send(true);
+                });
+                let network = Network::tcp(node.pid);
+                let policy = SnapshotPolicy::new(&node);
+                let storage = StorageConfig::new(&node);
+                let (pid, peers, handover) = (node.pid, node.peers, node.handover);
+                run_node(pid, peers, network, shutdown, handover, policy, storage).await
+            });
+            process::exit(code);
+        }
+    }
+}
+
+//write the snapshot of a backup to the data directory of the node, the node then starts
+//from it like from its own snapshot, exit if that fails
+fn restore(path: &Path, node: &Node) {
+    let pid = node.pid;
+    let dir = match &node.data_dir {
+        Some(dir) => dir,
+        None => {
+            println!("Node {} can not restore a backup without --data-dir", pid);
+            process::exit(1);
+        }
+    };
+    match disk::restore_backup(path, dir, pid) {
+        Ok(backup) => {
+            let index = backup.index;
+            println!("Node {} restores the backup up to index {}", pid, index);
+            let mut cluster = node.peers.clone();
+            cluster.push(pid);
+            cluster.sort_unstable();
+            if cluster != backup.cluster {
+                println!("The backup was taken on cluster {:?}", backup.cluster);
+            }
+        }
+        Err(e) => {
+            println!("Node {} failed to restore {}: {}", pid, path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+//open the log storage of a node and the state it applied and report what they hold,
+//None if they can not be read
+fn open_storage<B: Backend>(config: &StorageConfig, pid: u64) -> Option<(B, Box<dyn StateStore>)> {
+    let dir = config.dir.as_deref();
+    let state = B::state(dir.map(|dir| disk::snapshot_path(dir, pid)).as_deref());
+    if let Some(dir) = dir {
+        verify_snapshot(state.as_ref(), dir, pid)?;
+    }
+    let path = dir.and_then(|dir| disk::storage_path(dir, pid, B::ENGINE));
+    let (mut storage, recovery) = match B::open(path.as_deref(), &disk::storage_options()) {
+        Ok(storage) => storage,
+        Err(e) => {
+            println!("Node {} can not open its log: {}", pid, e);
+            return None;
+        }
+    };
+    if let Some((segment, offset)) = recovery.torn {
+        println!(
+            "Node {} drops a torn record at offset {} of segment {}",
+            pid, offset, segment
+        );
+    }
+    report_repairs(&recovery, pid);
+    if recovery.is_new() {
+        restore_snapshot(&mut storage, state.as_ref(), pid);
+        return Some((storage, state));
+    }
+    if recovery.runs > 0 {
+        println!("Node {} loads {} runs of its log", pid, recovery.runs);
+    }
+    println!(
+        "Node {} replays {} records of its log, decided up to index {}",
+        pid, recovery.records, recovery.decided
+    );
+    if recovery.snapshot {
+        let index = recovery.compacted;
+        println!("Node {} restores its snapshot up to index {}", pid, index);
+    }
+    Some((storage, state))
+}
+
+//print what was found corrupted in the storage of a node and discarded, the node gets
+//the log after the cut from its peers like after being down
+fn report_repairs(recovery: &Recovery, pid: u64) {
+    for repair in &recovery.repairs {
+        match repair {
+            Repair::Segment {
+                segment,
+                offset,
+                bytes,
+            } => println!(
+                "Node {} repairs its log: segment {} is corrupted at offset {}, {} bytes are discarded",
+                pid, segment, offset, bytes
+            ),
+            Repair::Entry { run, index } => println!(
+                "Node {} repairs its log: run {} is corrupted at index {}, the log is cut there",
+                pid, run, index
+            ),
+            Repair::Run { run } => println!(
+                "Node {} repairs its log: the header of run {} is corrupted, it and the later runs are cut",
+                pid, run
+            ),
+        }
+    }
+    if !recovery.repairs.is_empty() {
+        println!(
+            "Node {} gets the log from index {} from its peers",
+            pid, recovery.end
+        );
+    }
+}
+
+//check the snapshot file of a node on start, a corrupted one is moved out of the way so
+//the node starts without it, None for a snapshot of another version
+fn verify_snapshot(state: &dyn StateStore, dir: &Path, pid: u64) -> Option<()> {
+    let e = match state.load() {
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => e,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            println!("Node {} can not open its snapshot: {}", pid, e);
+            return None;
+        }
+        _ => return Some(()),
+    };
+    match disk::discard_snapshot(dir, pid) {
+        Ok(path) => println!(
+            "Node {} repairs its snapshot: {}, it is moved to {}",
+            pid,
+            e,
+            path.display()
+        ),
+        Err(e) => println!("Node {} can not move its corrupted snapshot: {}", pid, e),
+    }
+    Some(())
+}
+
+//start a log without records from the last snapshot of the node if there is one, the
+//peers send the entries after it
+fn restore_snapshot<B: Storage<LogEntry, KVSnapshot>>(
+    storage: &mut B,
+    state: &dyn StateStore,
+    pid: u64,
+) {
+    match state.load() {
+        Ok(Some(snapshot)) => {
+            let index = snapshot.len;
+            println!("Node {} restores its snapshot up to index {}", pid, index);
+            storage.set_decided_idx(snapshot.len);
+            storage.set_compacted_idx(snapshot.len);
+            storage.set_snapshot(snapshot);
+        }
+        Ok(None) => {}
+        Err(e) => println!("Node {} starts without its snapshot: {}", pid, e),
+    }
+}
+
+//the write-ahead log a storage queues its changes in, the state machine of the node and
+//the omni paxos handler on the storage
+type Started = (
+    Option<Arc<Wal>>,
+    Box<dyn StateStore>,
+    OmniPaxosHandle<LogEntry, KVSnapshot>,
+);
+
+fn start<B: Backend>(node_conf: NodeConfig, (backend, state): (B, Box<dyn StateStore>)) -> Started {
+    let wal = backend.sync_handle();
+    (wal, state, OmniPaxosNode::new(node_conf, backend))
+}
+
+//run one omni paxos node and all of its threads on the given network until the shutdown
+//is triggered, then hand the leadership over if asked to and return the exit code
+pub(crate) async fn run_node(
+    pid: u64,
+    peers: Vec<u64>,
+    network: Network,
+    shutdown: Shutdown,
+    handover: bool,
+    policy: SnapshotPolicy,
+    storage: StorageConfig,
+) -> i32 {
     //create the node by args
     let mut node_conf = NodeConfig::default();
-    node_conf.set_pid(node.pid);
-    node_conf.set_peers(node.peers);
+    node_conf.set_pid(pid);
+    node_conf.set_peers(peers.clone());
 
-    //create a memory storage
-    let storage = MemoryStorage::<KeyValue, KVSnapshot>::default();
-
-    //create the omni paxos handler
+    //create the omni paxos handler on the log storage of the engine
+    let handle = match storage.engine {
+        Engine::Memory => open_storage::<MemoryStorage<_, _>>(&storage, pid)
+            .map(|backend| start(node_conf, backend)),
+        Engine::File => {
+            open_storage::<WalStorage>(&storage, pid).map(|backend| start(node_conf, backend))
+        }
+        Engine::Lsm => {
+            open_storage::<LsmStorage>(&storage, pid).map(|backend| start(node_conf, backend))
+        }
+    };
+    let (wal, state, handle) = match handle {
+        Some(handle) => handle,
+        None => return 1,
+    };
     let OmniPaxosHandle {
         omni_paxos,
         seq_paxos_handle,
         ble_handle,
-    } = OmniPaxosNode::new(node_conf, storage);
+    } = handle;
 
     //get the incoming and outgoing channel of BLE and SP
-    let sp_in: mpsc::Sender<Message<KeyValue, KVSnapshot>> = seq_paxos_handle.incoming;
-    let mut sp_out: mpsc::Receiver<Message<KeyValue, KVSnapshot>> = seq_paxos_handle.outgoing;
+    let sp_in: mpsc::Sender<Message<LogEntry, KVSnapshot>> = seq_paxos_handle.incoming;
+    let mut sp_out: mpsc::Receiver<Message<LogEntry, KVSnapshot>> = seq_paxos_handle.outgoing;
     let ble_in: mpsc::Sender<BLEMessage> = ble_handle.incoming;
     let mut ble_out: mpsc::Receiver<BLEMessage> = ble_handle.outgoing;
 
-    //init the node address to string
-    let port = START_PORT + node.pid;
-    let addr = "127.0.0.1:".to_string() + &port.to_string();
-    let addr: SocketAddr = addr.parse().unwrap();
-    print_log(format!("Node address is {}", addr));
+    print_log(format!("Node {} is started", pid));
 
     //create three message channel for the communication between the treads later
     let (sp_sender, mut sp_rec) = mpsc::channel::<String>(24);
     let (ble_sender, mut ble_rec) = mpsc::channel::<String>(24);
     let (cmd_sender, mut cmd_rec) = mpsc::channel::<String>(24);
+    //and one for the leader transfers, so the command thread does not wait for them
+    let (transfer_sender, mut transfer_rec) = mpsc::channel::<Transfer>(24);
+    //and one for the read index requests of the peers, they are answered right away
+    let (read_sender, mut read_rec) = mpsc::channel::<ReadIndex>(24);
+
+    //the outgoing threads keep running until the incoming side is drained
+    let (stop_out, out_stopped) = Shutdown::new();
+    //and the peers are heard until the commands in flight are decided
+    let (stop_transport, drained) = Shutdown::new();
+
+    //the key-value store built from the decided log
+    let store = Store::new(pid);
+    store.seed(&omni_paxos).await;
+    let steering = Steering::default();
 
     //create the tasks
-    let sp_out_task = sp_out_thread(&mut sp_out);
-    let ble_out_task = ble_out_thread(&mut ble_out);
+    let sp_out_task = sp_out_thread(&mut sp_out, &network, wal, out_stopped.clone());
+    let ble_out_task = ble_out_thread(&mut ble_out, &network, out_stopped.clone());
+    let apply_task = apply_thread(&store, &omni_paxos, out_stopped.clone());
+    let expire_task = expire_thread(&store, &omni_paxos, pid, out_stopped.clone());
+    let snapshot_task = snapshot_thread(
+        &store,
+        &omni_paxos,
+        pid,
+        policy,
+        state.as_ref(),
+        out_stopped,
+    );
     let sp_in_task = sp_in_thread(&mut sp_rec, &sp_in);
-    let ble_in_task = ble_in_thread(&mut ble_rec, &ble_in);
-    let cmd_task = command_thread(&mut cmd_rec, &omni_paxos);
-    let fw_task = forward_thread(&addr, &sp_sender, &ble_sender, &cmd_sender);
+    let ble_in_task = ble_in_thread(&mut ble_rec, &ble_in, &steering);
+    let cmd_task = command_thread(
+        &mut cmd_rec,
+        &omni_paxos,
+        &store,
+        pid,
+        &peers,
+        &network,
+        transfer_sender,
+        shutdown.clone(),
+    );
+    let transfer_task = transfer_thread(
+        &mut transfer_rec,
+        &omni_paxos,
+        &store,
+        pid,
+        &peers,
+        &network,
+        &steering,
+    );
+    let read_task = read_thread(&mut read_rec, &omni_paxos, &store, pid, &network);
+    let fw_task = forward_thread(
+        pid,
+        &network,
+        drained,
+        sp_sender,
+        ble_sender,
+        cmd_sender,
+        read_sender,
+    );
+
+    let mut stepping_down = shutdown.clone();
+    let step_down_task = async {
+        if handover {
+            stepping_down.wait().await;
+            handover::step_down(&omni_paxos, &store, pid, &peers, &network).await;
+        }
+    };
+
+    //on shutdown the command thread takes no new commands and answers the ones it already
+    //received; once their proposals are decided the forward thread stops and drops its
+    //senders, so the incoming threads end once they handled everything already received
+    let commands = async {
+        tokio::join!(cmd_task, step_down_task, transfer_task);
+        while store.pending() > 0 {
+            time::sleep(Duration::from_millis(APPLY_INTERVAL)).await;
+        }
+        let _ = stop_transport.send(true);
+    };
+    let incoming = async {
+        tokio::join!(sp_in_task, ble_in_task, read_task, commands, fw_task);
+        let _ = stop_out.send(true);
+    };
 
     //execute all tasks in parallel.
-    tokio::join!(
-        sp_out_task,
-        ble_out_task,
-        sp_in_task,
-        ble_in_task,
-        cmd_task,
-        fw_task
-    );
+    let node = async {
+        tokio::join!(
+            incoming,
+            sp_out_task,
+            ble_out_task,
+            apply_task,
+            expire_task,
+            snapshot_task
+        );
+    };
+
+    //give up on the in-flight commands if they are not drained in time
+    let mut deadline = shutdown.clone();
+    let timeout = async {
+        deadline.wait().await;
+        time::sleep(Duration::from_millis(SHUTDOWN_TIMEOUT)).await;
+    };
+
+    tokio::select! {
+        _ = node => {
+            //the log is in memory, a last snapshot keeps what it applied
+            compaction::save(&store, state.as_ref(), pid);
+            println!("Node {} is shut down", pid);
+            0
+        }
+        _ = timeout => {
+            println!("Node {} failed to drain in-flight commands in time", pid);
+            1
+        }
+    }
 }
 
-//The thread about the message forward
+//The thread about the message forward, it runs until the node is drained
 async fn forward_thread(
-    addr: &SocketAddr,
-    sp_sender: &Sender<String>,
-    ble_sender: &Sender<String>,
-    cmd_sender: &Sender<String>,
+    pid: u64,
+    network: &Network,
+    mut drained: Shutdown,
+    sp_sender: Sender<String>,
+    ble_sender: Sender<String>,
+    cmd_sender: Sender<String>,
+    read_sender: Sender<ReadIndex>,
 ) {
-    let tcp_listener = TcpListener::bind(addr).await.unwrap();
-    loop {
-        let sp_sender = sp_sender.clone();
-        let ble_sender = ble_sender.clone();
-        let cmd_sender = cmd_sender.clone();
-        let (mut socket, _) = tcp_listener.accept().await.unwrap();
+    match &network.transport {
+        Transport::Tcp => {
+            let addr = node_addr(pid);
+            print_log(format!("Node address is {}", addr));
+            let tcp_listener = TcpListener::bind(addr).await.unwrap();
+            loop {
+                let network = network.clone();
+                let mut drained = drained.clone();
+                let sp_sender = sp_sender.clone();
+                let ble_sender = ble_sender.clone();
+                let cmd_sender = cmd_sender.clone();
+                let read_sender = read_sender.clone();
+                let (mut socket, _) = tokio::select! {
+                    accepted = tcp_listener.accept() => accepted.unwrap(),
+                    _ = drained.wait() => break,
+                };
 
-        tokio::spawn(async move {
-            let (r, _) = socket.split();
-            let mut reader = BufReader::new(r);
-            let mut buffer = String::new();
+                tokio::spawn(async move {
+                    let (r, _) = socket.split();
+                    let mut reader = BufReader::new(r);
+                    let mut buffer = String::new();
 
-            loop {
-                print_log(format!("-----fw_thread-----"));
-                let line = reader.read_line(&mut buffer).await.unwrap();
-                if line == 0 {
-                    break;
-                }
-                print_log(format!("receive string: {}", buffer));
-                let pkg: Package = serde_json::from_str(&buffer).unwrap();
-                print_log(format!("deserialized: {:?}", pkg));
-                //send to corresponding thread
-                match pkg.types {
-                    Types::SP => {
-                        //serialization
-                        let msg = serde_json::to_string(&pkg.msg).unwrap();
-                        sp_sender
-                            .send(msg)
-                            .await
-                            .expect("Failed to send message to SP thread");
-                    }
-                    Types::BLE => {
-                        //serialization
-                        let msg = serde_json::to_string(&pkg.msg).unwrap();
-                        ble_sender
-                            .send(msg)
-                            .await
-                            .expect("Failed to send message to BLE thread");
+                    loop {
+                        print_log(format!("-----fw_thread-----"));
+                        let line = tokio::select! {
+                            line = reader.read_line(&mut buffer) => line.unwrap(),
+                            _ = drained.wait() => break,
+                        };
+                        if line == 0 {
+                            break;
+                        }
+                        print_log(format!("receive string: {}", buffer));
+                        let pkg: Package = serde_json::from_str(&buffer).unwrap();
+                        forward_package(
+                            pid,
+                            &network,
+                            pkg,
+                            &sp_sender,
+                            &ble_sender,
+                            &cmd_sender,
+                            &read_sender,
+                        )
+                        .await;
+                        buffer.clear();
                     }
-                    Types::CMD => {
-                        //serialization
-                        let msg = serde_json::to_string(&pkg.msg).unwrap();
-                        cmd_sender
-                            .send(msg)
-                            .await
-                            .expect("Failed to send message to CMD thread");
+                });
+            }
+        }
+        Transport::Sim(sim) => {
+            let mut inbox = sim.inbox(pid);
+            loop {
+                let pkg = tokio::select! {
+                    pkg = inbox.recv() => pkg,
+                    _ = drained.wait() => break,
+                };
+                match pkg {
+                    Some(pkg) => {
+                        print_log(format!("-----fw_thread-----"));
+                        forward_package(
+                            pid,
+                            network,
+                            pkg,
+                            &sp_sender,
+                            &ble_sender,
+                            &cmd_sender,
+                            &read_sender,
+                        )
+                        .await;
                     }
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+//send a received package to the corresponding thread
+async fn forward_package(
+    pid: u64,
+    network: &Network,
+    pkg: Package,
+    sp_sender: &Sender<String>,
+    ble_sender: &Sender<String>,
+    cmd_sender: &Sender<String>,
+    read_sender: &Sender<ReadIndex>,
+) {
+    print_log(format!("deserialized: {:?}", pkg));
+    //a partition cuts the link in both directions
+    let from = match &pkg.msg {
+        Msg::BLE(msg) => Some(msg.from),
+        Msg::SP(msg) => Some(msg.from),
+        Msg::Chunk(chunk) => Some(chunk.from),
+        Msg::ChunkAck(ack) => Some(ack.from),
+        Msg::ReadIndex(read) => Some(read.from),
+        Msg::ReadIndexAck(ack) => Some(ack.from),
+        Msg::Elect(elect) => Some(elect.from),
+        Msg::CMD(_) => None,
+    };
+    if let Some(from) = from {
+        if !network.faults.connected(from, pid) {
+            print_log(format!("Package from {} is cut by the partition", from));
+            return;
+        }
+    }
+    match pkg.types {
+        Types::SP => {
+            //serialization
+            let msg = serde_json::to_string(&pkg.msg).unwrap();
+            sp_sender
+                .send(msg)
+                .await
+                .expect("Failed to send message to SP thread");
+        }
+        Types::BLE => {
+            //serialization
+            let msg = serde_json::to_string(&pkg.msg).unwrap();
+            ble_sender
+                .send(msg)
+                .await
+                .expect("Failed to send message to BLE thread");
+        }
+        //a client only sends commands
+        Types::CMD => {
+            if let Msg::CMD(_) = pkg.msg {
+                //serialization
+                let msg = serde_json::to_string(&pkg.msg).unwrap();
+                //the command thread is closed once the node shuts down
+                if cmd_sender.send(msg).await.is_err() {
+                    send_to_client("Failed, the node is shutting down", pid, network).await;
                 }
-                buffer.clear();
             }
-        });
+        }
+        //the command thread hands the transfer on, nobody waits for a reply
+        Types::Elect => {
+            if let Msg::Elect(_) = pkg.msg {
+                let msg = serde_json::to_string(&pkg.msg).unwrap();
+                let _ = cmd_sender.send(msg).await;
+            }
+        }
+        Types::Chunk => match pkg.msg {
+            Msg::Chunk(chunk) => {
+                let (ack, msg) = network.transfers.receive(chunk);
+                let to = ack.to;
+                let wrapped_ack = Package {
+                    types: Types::Chunk,
+                    msg: Msg::ChunkAck(ack),
+                };
+                network.send(pid, to, wrapped_ack).await;
+                //the whole SP message, as if it came in one package
+                if let Some(msg) = msg {
+                    sp_sender
+                        .send(msg)
+                        .await
+                        .expect("Failed to send message to SP thread");
+                }
+            }
+            Msg::ChunkAck(ack) => network.transfers.acked(ack),
+            _ => {}
+        },
+        Types::Read => match pkg.msg {
+            //the read thread is gone once the node is drained
+            Msg::ReadIndex(read) => {
+                let _ = read_sender.send(read).await;
+            }
+            Msg::ReadIndexAck(ack) => network.reads.acked(ack),
+            _ => {}
+        },
+    }
+}
+
+//answer the read index requests of the peers with the end of the log of this node
+async fn read_thread(
+    read_rec: &mut Receiver<ReadIndex>,
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    store: &Store,
+    pid: u64,
+    network: &Network,
+) {
+    while let Some(read) = read_rec.recv().await {
+        let ack = ReadIndexAck {
+            from: pid,
+            to: read.from,
+            read: read.read,
+            index: store.accepted(op).await,
+        };
+        let pkg = Package {
+            types: Types::Read,
+            msg: Msg::ReadIndexAck(ack),
+        };
+        network.send(pid, read.from, pkg).await;
+    }
+}
+
+//wait until this node applied every write decided before the call, so a read of its
+//store is linearizable, false if a majority could not be reached or the log did not get
+//decided up to the read index in time
+async fn linearize(
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    store: &Store,
+    pid: u64,
+    peers: &[u64],
+    network: &Network,
+) -> bool {
+    let own = store.accepted(op).await;
+    match network.reads.index(network, pid, peers, own).await {
+        Some(index) => store.reach(op, index).await,
+        None => false,
     }
 }
 
 //SP messages outgoing thread
-async fn sp_out_thread(sp_out: &mut mpsc::Receiver<Message<KeyValue, KVSnapshot>>) {
+async fn sp_out_thread(
+    sp_out: &mut mpsc::Receiver<Message<LogEntry, KVSnapshot>>,
+    network: &Network,
+    wal: Option<Arc<Wal>>,
+    mut stopped: Shutdown,
+) {
+    let mut queues = HashMap::new();
     loop {
         print_log(format!("-----sp_out_thread-----"));
-        match sp_out.recv().await {
-            Some(msg) => {
-                print_log(format!("SP message: {:?} is received from channel", msg));
-                let port = START_PORT + msg.to;
-                let addr = "127.0.0.1:".to_string() + &port.to_string();
-                let addr: SocketAddr = addr.parse().unwrap();
-
-                let wrapped_msg = Package {
-                    types: Types::SP,
-                    msg: Msg::SP(msg),
-                };
-
-                let serialized = serde_json::to_string(&wrapped_msg).unwrap();
+        let msg = tokio::select! {
+            msg = sp_out.recv() => msg,
+            _ = stopped.wait() => break,
+        };
+        let mut batch = match msg {
+            Some(msg) => vec![msg],
+            None => break,
+        };
+        //the messages waiting now go out together, once the changes of the storage they
+        //answer are on disk with one fsync
+        while let Ok(msg) = sp_out.try_recv() {
+            batch.push(msg);
+        }
+        if let Some(wal) = &wal {
+            wal.sync().expect("Failed to write the write-ahead log");
+        }
+        for msg in batch {
+            print_log(format!("SP message: {:?} is received from channel", msg));
+            let (from, to) = (msg.from, msg.to);
+            let queue = queues.entry(to).or_insert_with(|| peer_queue(network));
+            let _ = queue.send((from, to, Msg::SP(msg)));
+        }
+    }
+}
 
-                if let Ok(mut tcp_stream) = TcpStream::connect(addr).await {
-                    let (_, mut write) = tcp_stream.split();
-                    write.write_all(serialized.as_bytes()).await.unwrap();
+//the SP messages to one peer, sent in order by one task: a large message, like a
+//snapshot, goes in chunks beside the SP path, and the messages after it wait until the
+//peer has it, so they can not overtake it
+fn peer_queue(network: &Network) -> mpsc::UnboundedSender<(u64, u64, Msg)> {
+    let (sender, mut queue) = mpsc::unbounded_channel::<(u64, u64, Msg)>();
+    let network = network.clone();
+    tokio::spawn(async move {
+        while let Some((from, to, msg)) = queue.recv().await {
+            let serialized = serde_json::to_string(&msg).unwrap();
+            if Transfers::large(&serialized) {
+                if !network.transfers.send(&network, from, to, serialized).await {
+                    print_log(format!("Transfer from {} to {} failed", from, to));
                 }
+                continue;
             }
-            None => {}
+            let wrapped_msg = Package {
+                types: Types::SP,
+                msg,
+            };
+            network.send(from, to, wrapped_msg).await;
         }
-    }
+    });
+    sender
 }
 
 //SP messages incoming thread
 async fn sp_in_thread(
     sp_rec: &mut Receiver<String>,
-    sp_in: &mpsc::Sender<Message<KeyValue, KVSnapshot>>,
+    sp_in: &mpsc::Sender<Message<LogEntry, KVSnapshot>>,
 ) {
     loop {
         print_log(format!("-----sp_in_thread-----"));
@@ -188,113 +717,517 @@ async fn sp_in_thread(
                     .await
                     .expect("Failed to send message to SP")
             }
-            None => {}
+            None => break,
         }
     }
 }
 
 //BLE messages outgoing thread
-async fn ble_out_thread(ble_out: &mut mpsc::Receiver<BLEMessage>) {
+async fn ble_out_thread(
+    ble_out: &mut mpsc::Receiver<BLEMessage>,
+    network: &Network,
+    mut stopped: Shutdown,
+) {
     loop {
         print_log(format!("-----ble_out_thread-----"));
-        match ble_out.recv().await {
+        let msg = tokio::select! {
+            msg = ble_out.recv() => msg,
+            _ = stopped.wait() => break,
+        };
+        match msg {
             Some(msg) => {
                 print_log(format!("BLE message: {:?} is received from channel", msg));
-                let port = START_PORT + msg.to;
-                let addr = "127.0.0.1:".to_string() + &port.to_string();
-                let addr: SocketAddr = addr.parse().unwrap();
-
+                let (from, to) = (msg.from, msg.to);
                 let wrapped_msg = Package {
                     types: Types::BLE,
                     msg: Msg::BLE(msg),
                 };
-
-                let serialized = serde_json::to_string(&wrapped_msg).unwrap();
-
-                if let Ok(mut tcp_stream) = TcpStream::connect(addr).await {
-                    let (_, mut write) = tcp_stream.split();
-                    write.write_all(serialized.as_bytes()).await.unwrap();
-                }
+                network.send(from, to, wrapped_msg).await;
             }
-            None => {}
+            None => break,
         }
     }
 }
 
 //BLE messages incoming thread
-async fn ble_in_thread(ble_rec: &mut Receiver<String>, ble_in: &mpsc::Sender<BLEMessage>) {
+async fn ble_in_thread(
+    ble_rec: &mut Receiver<String>,
+    ble_in: &mpsc::Sender<BLEMessage>,
+    steering: &Steering,
+) {
     loop {
         print_log(format!("-----ble_in_thread-----"));
         match ble_rec.recv().await {
             Some(msg) => {
                 print_log(format!("BLE message: {} is received from channel", msg));
                 let sp_msg = serde_json::from_str(&msg).unwrap();
+                //a leader transfer hides the old leader from this node for a while
+                if steering.ignores(&sp_msg) {
+                    continue;
+                }
                 ble_in
                     .send(sp_msg)
                     .await
                     .expect("Failed to send message to channel")
             }
-            None => {}
+            None => break,
         }
     }
 }
 
 //commands messages incoming thread
-async fn command_thread(cmd_rec: &mut Receiver<String>, op: &OmniPaxosNode<KeyValue, KVSnapshot>) {
+#[allow(clippy::too_many_arguments)]
+async fn command_thread(
+    cmd_rec: &mut Receiver<String>,
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    store: &Store,
+    pid: u64,
+    peers: &[u64],
+    network: &Network,
+    transfers: Sender<Transfer>,
+    shutdown: Shutdown,
+) {
+    let mut stopping = shutdown.clone();
+    let mut closed = false;
     loop {
         print_log(format!("-----cmd_thread-----"));
-        match cmd_rec.recv().await {
+        //on shutdown no new command is taken, the ones already received are answered
+        let msg = tokio::select! {
+            msg = cmd_rec.recv() => msg,
+            _ = stopping.wait(), if !closed => {
+                cmd_rec.close();
+                closed = true;
+                continue;
+            }
+        };
+        match msg {
             Some(msg) => {
                 print_log(format!("Command: {} is received from network layer", msg));
-                let msg: CMDMessage = serde_json::from_str(&msg).unwrap();
+                let msg: CMDMessage = match serde_json::from_str(&msg).unwrap() {
+                    Msg::CMD(msg) => msg,
+                    //sent by the node that handles a transfer, no reply to the client
+                    Msg::Elect(elect) => {
+                        let (old, decided) = (elect.old, elect.decided);
+                        let _ = transfers.send(Transfer::Elect { old, decided }).await;
+                        continue;
+                    }
+                    _ => continue,
+                };
                 match msg.operation {
                     Operation::Get => {
-                        let key = msg.kv.key;
-                        if let Some(entries) = op.read_entries(0..).await {
-                            if let Some(v) = fetch_value(&key, entries.to_vec()).await {
-                                let mut prefix = "This value is : ".to_string();
-                                let value = v.to_string();
-                                prefix += &value;
-                                send_to_client(&prefix).await;
-                            } else {
-                                send_to_client("No value about the key").await;
+                        //a read at the read index, so it sees every write decided before it
+                        let reply = match linearize(op, store, pid, peers, network).await {
+                            true => record_reply(store.record(&msg.kv.key)),
+                            false => "Failed to get".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::GetAt { at_index } => {
+                        let reply = match store.at(op, at_index).await {
+                            Ok(past) => record_reply(past.data.get(&msg.kv.key).cloned()),
+                            Err(err) => past_reply(at_index, err),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::SnapshotAt { at_index } => {
+                        let reply = match store.at(op, at_index).await {
+                            Ok(past) => {
+                                let page = past.data.into_iter().map(|(k, r)| (k, r.value));
+                                page_reply((page.collect(), None))
                             }
+                            Err(err) => past_reply(at_index, err),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Put => {
+                        //reply once the entry is decided and applied
+                        let command = Command::Put(msg.kv);
+                        if let Some(Outcome::Done) = store.propose(op, command).await {
+                            send_to_client("Successfully to put value", pid, network).await;
                         } else {
-                            //println!("This key does not exist");
-                            send_to_client("No value about the key").await;
+                            send_to_client("Failed to put", pid, network).await;
                         }
                     }
 
-                    Operation::Put => {
-                        //get the key value
-                        let write_entry = msg.kv;
-                        //append
-                        if let Ok(_) = op.append(write_entry).await {
-                            send_to_client("Successfully to put value").await;
+                    Operation::PutTtl { ttl } => {
+                        let command = Command::PutTtl { kv: msg.kv, ttl };
+                        if let Some(Outcome::Done) = store.propose(op, command).await {
+                            send_to_client("Successfully to put value", pid, network).await;
+                        } else {
+                            send_to_client("Failed to put", pid, network).await;
+                        }
+                    }
+
+                    Operation::MultiGet { keys } => {
+                        if !linearize(op, store, pid, peers, network).await {
+                            send_to_client("Failed to get", pid, network).await;
+                            continue;
+                        }
+                        let values: Vec<String> = keys
+                            .iter()
+                            .map(|key| match store.get(key) {
+                                Some(v) => format!("{}={}", key, v),
+                                None => format!("{}=-", key),
+                            })
+                            .collect();
+                        let reply = format!("These values are : {}", values.join(" "));
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Scan {
+                        start,
+                        end,
+                        limit,
+                        token,
+                    } => {
+                        if !linearize(op, store, pid, peers, network).await {
+                            send_to_client("Failed to scan", pid, network).await;
+                            continue;
+                        }
+                        let from = token.unwrap_or(start);
+                        let within = |key: &str| match &end {
+                            Some(end) => key < end.as_str(),
+                            None => true,
+                        };
+                        let page = store.page(&from, within, limit);
+                        send_to_client(&page_reply(page), pid, network).await;
+                    }
+
+                    Operation::Prefix {
+                        prefix,
+                        limit,
+                        token,
+                    } => {
+                        if !linearize(op, store, pid, peers, network).await {
+                            send_to_client("Failed to scan", pid, network).await;
+                            continue;
+                        }
+                        let from = token.unwrap_or_else(|| prefix.clone());
+                        let page = store.page(&from, |key| key.starts_with(&prefix), limit);
+                        send_to_client(&page_reply(page), pid, network).await;
+                    }
+
+                    Operation::Watch {
+                        prefix,
+                        from_revision,
+                    } => {
+                        let stream = network.stream(pid);
+                        match store.watch(msg.kv.key, prefix, from_revision) {
+                            Ok(events) => {
+                                stream.send("Successfully to watch").await;
+                                tokio::spawn(watch_thread(stream, events, shutdown.clone()));
+                            }
+                            Err(start) => {
+                                let reply = format!(
+                                    "Failed to watch, the history starts at revision {}",
+                                    start
+                                );
+                                stream.send(&reply).await;
+                            }
+                        }
+                    }
+
+                    Operation::MultiPut { kvs } => {
+                        //every pair is in a single entry, so it costs one consensus round
+                        let command = Command::MultiPut(kvs);
+                        if let Some(Outcome::Done) = store.propose(op, command).await {
+                            send_to_client("Successfully to put values", pid, network).await;
                         } else {
-                            send_to_client("Failed to put").await;
+                            send_to_client("Failed to put", pid, network).await;
                         }
                     }
+
+                    Operation::Cas { expected } => {
+                        let command = Command::Cas {
+                            key: msg.kv.key,
+                            expected,
+                            new: msg.kv.value,
+                        };
+                        let reply = match store.propose(op, command).await {
+                            Some(Outcome::Done) => "Successfully to compare and swap".to_string(),
+                            Some(Outcome::Mismatch(Some(v))) => {
+                                format!("Failed to compare and swap, this value is : {}", v)
+                            }
+                            Some(Outcome::Mismatch(None)) => {
+                                "Failed to compare and swap, no value about the key".to_string()
+                            }
+                            _ => "Failed to compare and swap".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+                    Operation::Incr | Operation::Decr => {
+                        let (key, delta) = (msg.kv.key, msg.kv.value);
+                        let (command, name, limit) = match msg.operation {
+                            Operation::Incr => {
+                                (Command::Incr { key, delta }, "increment", "overflow")
+                            }
+                            _ => (Command::Decr { key, delta }, "decrement", "underflow"),
+                        };
+                        let reply = match store.propose(op, command).await {
+                            Some(Outcome::Value(v)) => {
+                                format!("Successfully to {}, this value is : {}", name, v)
+                            }
+                            Some(Outcome::OutOfRange(v)) => format!(
+                                "Failed to {}, it would {}, this value is : {}",
+                                name, limit, v
+                            ),
+                            _ => format!("Failed to {}", name),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Txn {
+                        conditions,
+                        mutations,
+                    } => {
+                        let command = Command::Txn {
+                            conditions,
+                            mutations,
+                        };
+                        let reply = match store.propose(op, command).await {
+                            Some(Outcome::Done) => {
+                                "Successfully to commit the transaction".to_string()
+                            }
+                            Some(Outcome::Aborted(i)) => format!(
+                                "Failed to commit the transaction, condition {} does not hold",
+                                i
+                            ),
+                            _ => "Failed to commit the transaction".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Grant { ttl } => {
+                        let reply = match store.propose(op, Command::Grant { ttl }).await {
+                            Some(Outcome::Value(lease)) => {
+                                format!("Successfully to grant lease {}", lease)
+                            }
+                            _ => "Failed to grant".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::KeepAlive { lease } => {
+                        let reply = match store.propose(op, Command::KeepAlive { lease }).await {
+                            Some(Outcome::Done) => {
+                                format!("Successfully to keep lease {} alive", lease)
+                            }
+                            Some(Outcome::NoLease) => {
+                                format!("Failed to keep alive, no lease {}", lease)
+                            }
+                            _ => "Failed to keep alive".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Revoke { lease } => {
+                        let command = Command::Revoke {
+                            lease,
+                            revision: None,
+                        };
+                        let reply = match store.propose(op, command).await {
+                            Some(Outcome::Done) => {
+                                format!("Successfully to revoke lease {}", lease)
+                            }
+                            Some(Outcome::NoLease) => {
+                                format!("Failed to revoke, no lease {}", lease)
+                            }
+                            _ => "Failed to revoke".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::PutLease { lease } => {
+                        let command = Command::PutLease { kv: msg.kv, lease };
+                        let reply = match store.propose(op, command).await {
+                            Some(Outcome::Done) => "Successfully to put value".to_string(),
+                            Some(Outcome::NoLease) => format!("Failed to put, no lease {}", lease),
+                            _ => "Failed to put".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Lock { lease } => {
+                        let name = msg.kv.key;
+                        let command = Command::Lock {
+                            name: name.clone(),
+                            lease,
+                        };
+                        let reply = match store.propose(op, command).await {
+                            Some(Outcome::Done) => format!("Successfully to lock {}", name),
+                            Some(Outcome::Mismatch(Some(holder))) => {
+                                format!("Failed to lock {}, it is held by lease {}", name, holder)
+                            }
+                            Some(Outcome::NoLease) => format!("Failed to lock, no lease {}", lease),
+                            _ => "Failed to lock".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Unlock { lease } => {
+                        let name = msg.kv.key;
+                        let command = Command::Unlock {
+                            name: name.clone(),
+                            lease,
+                        };
+                        let reply = match store.propose(op, command).await {
+                            Some(Outcome::Done) => format!("Successfully to unlock {}", name),
+                            Some(Outcome::Mismatch(_)) => format!(
+                                "Failed to unlock {}, it is not held by lease {}",
+                                name, lease
+                            ),
+                            _ => "Failed to unlock".to_string(),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Export => {
+                        if !linearize(op, store, pid, peers, network).await {
+                            send_to_client("Failed to export", pid, network).await;
+                            continue;
+                        }
+                        //one reply from a single copy of the store, so the keys are
+                        //consistent at one index, with a json line for each key
+                        let snapshot = store.snapshot();
+                        let data = snapshot.snapshotted.data;
+                        let mut reply = format!(
+                            "Successfully to export {} keys at index {}",
+                            data.len(),
+                            snapshot.len
+                        );
+                        for (key, record) in data {
+                            reply.push('\n');
+                            reply += &export::write(Format::Jsonl, &Row::new(key, &record));
+                        }
+                        send_to_client(&reply, pid, network).await;
+                    }
+
                     Operation::Snap => {
-                        //something will cause omni paxos wrong
-                        if let Ok(_) = op.snapshot(None, false).await {
-                            send_to_client("Successfully to make a snapshot").await;
+                        //the same compaction the snapshot thread triggers, up to the
+                        //index this node applied, which every replica has decided
+                        if store.compact(op).await.is_some() {
+                            send_to_client("Successfully to make a snapshot", pid, network).await;
                         } else {
-                            send_to_client("Failed to snapshot").await;
+                            send_to_client("Failed to snapshot", pid, network).await;
                         }
                     }
+
+                    Operation::Backup { path } => {
+                        //the state at the index this node applied, which is decided
+                        let snapshot = store.snapshot();
+                        let mut cluster = peers.to_vec();
+                        cluster.push(pid);
+                        cluster.sort_unstable();
+                        let created = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                        let backup = Backup {
+                            cluster,
+                            pid,
+                            index: snapshot.len,
+                            created: created.as_millis() as u64,
+                            snapshot,
+                        };
+                        let reply = match disk::save_backup(Path::new(&path), &backup) {
+                            Ok(_) => format!(
+                                "Successfully to back up the log up to index {} to {}",
+                                backup.index, path
+                            ),
+                            Err(e) => format!("Failed to back up: {}", e),
+                        };
+                        send_to_client(&reply, pid, network).await;
+                    }
+
+                    Operation::Leader => {
+                        let leader = op.get_current_leader().await;
+                        send_to_client(&format!("Leader is : {}", leader), pid, network).await;
+                    }
+
+                    Operation::Fault(config) => {
+                        print_log(format!("Inject faults {:?}", config));
+                        network.faults.set(config);
+                        send_to_client("Successfully to inject faults", pid, network).await;
+                    }
+
+                    Operation::TransferLeader { to } => {
+                        let stream = network.stream(pid);
+                        let _ = transfers.send(Transfer::Leader { to, stream }).await;
+                    }
+
                 }
             }
-            None => {}
+            None => break,
         }
     }
 }
 
+//push the changes a client watches until it is gone or the node shuts down
+async fn watch_thread(
+    stream: ClientStream,
+    mut events: mpsc::UnboundedReceiver<Event>,
+    mut shutdown: Shutdown,
+) {
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = shutdown.wait() => break,
+        };
+        let event = match event {
+            Some(Event {
+                revision,
+                key,
+                value: Some(value),
+            }) => format!("Event : {}={} at revision {}", key, value, revision),
+            Some(Event { revision, key, .. }) => {
+                format!("Event : {} deleted at revision {}", key, revision)
+            }
+            None => break,
+        };
+        if !stream.send(&event).await {
+            break;
+        }
+    }
+}
+
+//the reply to a get, with the version and revisions of the key
+fn record_reply(record: Option<Record>) -> String {
+    match record {
+        Some(record) => format!(
+            "This value is : {} (version {}, create revision {}, mod revision {})",
+            record.value, record.version, record.create_revision, record.mod_revision
+        ),
+        None => "No value about the key".to_string(),
+    }
+}
+
+//the reply to a read at an index that can not be rebuilt
+fn past_reply(index: u64, err: PastErr) -> String {
+    match err {
+        PastErr::Compacted(start) => format!(
+            "Failed to read at index {}, it is compacted, the history starts at index {}",
+            index, start
+        ),
+        PastErr::Undecided(last) => format!(
+            "Failed to read at index {}, the log is applied up to index {}",
+            index, last
+        ),
+    }
+}
+
+//the reply to a scan, with the token of the next page if there is one
+fn page_reply((page, next): (Vec<(String, u64)>, Option<String>)) -> String {
+    let values: Vec<String> = page.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    let mut reply = format!("These values are : {}", values.join(" "));
+    if let Some(next) = next {
+        reply += &format!(", next page : {}", next);
+    }
+    reply
+}
+
 //to send message to client
-async fn send_to_client(str: &str) {
-    if let Ok(mut tcp_stream) = TcpStream::connect(CLIENT_ADDR).await {
-        let (_, mut write) = tcp_stream.split();
-        write.write_all(str.as_bytes()).await.unwrap();
+async fn send_to_client(str: &str, pid: u64, network: &Network) {
+    if network.reply(pid, str).await {
         // print_log(format!("Replay: {} is send to network layer", &str));
         println!("Replay: {} is send to network layer", &str);
     } else {
@@ -302,38 +1235,6 @@ async fn send_to_client(str: &str) {
     }
 }
 
-//fetch value by key
-async fn fetch_value(key: &str, vec: Vec<ReadEntry<KeyValue, KVSnapshot>>) -> Option<u64> {
-    print!("vec {:?}", vec);
-    let mut index = vec.len() - 1;
-    let mut value = None;
-    let vec = vec.clone();
-    loop {
-        match vec.get(index).unwrap() {
-            Decided(kv) => {
-                println!("Now kv {:?}", kv);
-                if kv.key == key {
-                    value = Some(kv.value);
-                    break;
-                }
-            }
-            Snapshotted(snapshotted_entry) => {
-                let hashmap = snapshotted_entry.snapshot.snapshotted.clone();
-                println!("Now snapshot {:?}", hashmap);
-                if let Some(v) = hashmap.get(key) {
-                    value = Some(*v);
-                }
-            }
-            _ => {}
-        }
-        if index == 0 || value != None {
-            break;
-        }
-        index -= 1;
-    }
-    value
-}
-
 //print logs into the terminal
 fn print_log(log: String) {
     if DEBUG_OUTPUT {
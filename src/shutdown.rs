@@ -0,0 +1,39 @@
+use std::future;
+
+use tokio::sync::watch;
+
+//tells the threads of a node that the node is shutting down
+#[derive(Clone)]
+pub(crate) struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn new() -> (watch::Sender<bool>, Shutdown) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Shutdown(receiver))
+    }
+
+    //wait until the shutdown is triggered
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow() {
+            //the sender is gone, so the shutdown can not be triggered anymore
+            if self.0.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+//wait for SIGINT or SIGTERM
+pub(crate) async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+        let mut terminate = unix::signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.unwrap();
+}
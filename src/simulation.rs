@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::process;
+use std::time::Duration;
+
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::compaction::SnapshotPolicy;
+use crate::configs::server::{
+    SIM_CLIENTS, SIM_KEYS, SIM_OPS, SIM_REPLY_TIMEOUT, SIM_RESTART, SIM_SETTLE, SIM_WARMUP,
+};
+use crate::disk::StorageConfig;
+use crate::models::kv::KeyValue;
+use crate::models::msg::{CMDMessage, Msg, Operation};
+use crate::models::node::Node;
+use crate::models::package::{Package, Types};
+use crate::network::fault::FaultLayer;
+use crate::network::sim::SimNetwork;
+use crate::network::Network;
+use crate::run_node;
+use crate::shutdown::Shutdown;
+
+//the failure scenarios, each runs on its own simulated cluster and checks the replies
+#[cfg(test)]
+mod scenarios;
+
+//the simulated cluster and its main client
+struct Cluster {
+    sim: SimNetwork,
+    faults: FaultLayer,
+    pids: Vec<u64>,
+    client: SimClient,
+    policy: SnapshotPolicy,
+    storage: StorageConfig,
+    //the shutdown trigger and the task of every running node
+    nodes: HashMap<u64, (watch::Sender<bool>, JoinHandle<i32>)>,
+}
+
+//a client of the simulated cluster, it has one request in flight at a time
+struct SimClient {
+    id: u64,
+    sim: SimNetwork,
+    replies: Receiver<String>,
+}
+
+//run the node and its peers in one process on the simulated network,
+//drive a seeded scenario against them and print the trace
+pub(crate) async fn simulate(node: Node, seed: u64) {
+    if node.scenario.as_deref() == Some("history") && node.data_dir.is_none() {
+        println!("The history scenario restarts the nodes, it needs --data-dir");
+        process::exit(1);
+    }
+    let mut cluster = Cluster::new(&node, seed).await;
+
+    println!("sim seed {} nodes {:?}", seed, cluster.pids);
+    match node.scenario.as_deref() {
+        None => workload(&mut cluster).await,
+        Some("history") => history(&mut cluster).await,
+        Some(other) => panic!("Unknown scenario {}", other),
+    }
+
+    for event in cluster.sim.trace() {
+        println!("sim {}", event);
+    }
+}
+
+//random gets and puts against random nodes, then every key is read on every node
+async fn workload(cluster: &mut Cluster) {
+    for _ in 0..SIM_OPS {
+        cluster.client.random_op(&cluster.pids).await;
+    }
+
+    //let the last writes reach every node, then read every key everywhere
+    settle().await;
+    for k in 0..SIM_KEYS {
+        cluster.read_everywhere(&format!("key{}", k)).await;
+    }
+}
+
+//concurrent clients send random operations to random nodes while one node after the
+//other is shut down and started again on its storage, every invocation and return is in
+//the trace, so the history can be checked for linearizability
+async fn history(cluster: &mut Cluster) {
+    let mut clients = vec![];
+    for id in 1..=SIM_CLIENTS {
+        let mut client = SimClient::new(id, &cluster.sim);
+        let pids = cluster.pids.clone();
+        clients.push(tokio::spawn(async move {
+            for _ in 0..SIM_OPS {
+                client.random_op(&pids).await;
+            }
+        }));
+    }
+    let clients = async {
+        for client in clients {
+            client.await.unwrap();
+        }
+    };
+    tokio::pin!(clients);
+
+    //a node is only stopped between restarts, so every node is up once the clients are done
+    loop {
+        let up = Duration::from_millis(cluster.sim.random(SIM_RESTART));
+        tokio::select! {
+            _ = &mut clients => break,
+            _ = time::sleep(up) => {}
+        }
+        let down = cluster.pids[cluster.sim.random(cluster.pids.len() as u64) as usize];
+        let downtime = cluster.sim.random(SIM_RESTART);
+        cluster.restart(down, downtime).await;
+    }
+}
+
+impl Cluster {
+    //register the node and its peers on the simulated network, start them on the storage
+    //of the node and let BLE elect a leader before a scenario starts
+    async fn new(node: &Node, seed: u64) -> Self {
+        let sim = SimNetwork::new(seed);
+        //every node shares the fault layer, so a scenario controls the whole cluster
+        let faults = FaultLayer::new(seed);
+        let mut pids = node.peers.clone();
+        pids.push(node.pid);
+        pids.sort_unstable();
+
+        for pid in &pids {
+            sim.register(*pid);
+        }
+        let client = SimClient::new(0, &sim);
+
+        let mut cluster = Cluster {
+            sim,
+            faults,
+            pids: pids.clone(),
+            client,
+            policy: SnapshotPolicy::new(node),
+            storage: StorageConfig::new(node),
+            nodes: HashMap::new(),
+        };
+        for pid in pids {
+            cluster.run(pid);
+        }
+        time::sleep(Duration::from_millis(SIM_WARMUP)).await;
+        cluster
+    }
+
+    //run a registered node until it is stopped
+    fn run(&mut self, pid: u64) {
+        let peers = self.pids.iter().filter(|p| **p != pid).cloned().collect();
+        let network = Network::sim(self.sim.clone(), self.faults.clone());
+        let (trigger, shutdown) = Shutdown::new();
+        let storage = self.storage.clone();
+        let node = run_node(pid, peers, network, shutdown, false, self.policy, storage);
+        self.nodes.insert(pid, (trigger, tokio::spawn(node)));
+    }
+
+    //shut a node down like on SIGTERM and take it off the network once it is drained
+    async fn stop(&mut self, pid: u64) {
+        let (trigger, node) = self.nodes.remove(&pid).expect("Node is not running");
+        self.sim.record(format!("stop node {}", pid));
+        let _ = trigger.send(true);
+        let _ = node.await;
+        self.sim.unregister(pid);
+    }
+
+    //start a stopped node again, it recovers its log from its storage and gets the rest
+    //from its peers
+    fn start(&mut self, pid: u64) {
+        self.sim.record(format!("start node {}", pid));
+        self.sim.register(pid);
+        self.run(pid);
+    }
+
+    //stop a node and start it again after `downtime` ms
+    async fn restart(&mut self, pid: u64, downtime: u64) {
+        self.stop(pid).await;
+        time::sleep(Duration::from_millis(downtime)).await;
+        self.start(pid);
+    }
+
+    //read a key on every node
+    async fn read_everywhere(&mut self, key: &str) -> Vec<String> {
+        let mut values = vec![];
+        for pid in self.pids.clone() {
+            values.push(self.client.get(pid, key).await);
+        }
+        values
+    }
+}
+
+impl SimClient {
+    fn new(id: u64, sim: &SimNetwork) -> Self {
+        SimClient {
+            id,
+            sim: sim.clone(),
+            replies: sim.register_client(id),
+        }
+    }
+
+    //send one command and wait for its reply
+    async fn request(&mut self, to: u64, msg: CMDMessage) -> Option<String> {
+        self.sim.record(format!(
+            "client{} invokes {:?} {} {} on {}",
+            self.id, msg.operation, msg.kv.key, msg.kv.value, to
+        ));
+        let pkg = Package {
+            types: Types::CMD,
+            msg: Msg::CMD(msg),
+        };
+        self.sim.request(self.id, to, pkg).await;
+        let timeout = Duration::from_millis(SIM_REPLY_TIMEOUT);
+        match time::timeout(timeout, self.replies.recv()).await {
+            Ok(Some(reply)) => {
+                self.sim
+                    .record(format!("client{} returns {}", self.id, reply));
+                Some(reply)
+            }
+            _ => {
+                self.sim.record(format!("client{} timed out", self.id));
+                //continue under a new id, so a late reply is not taken for the next one
+                self.id += SIM_CLIENTS + 1;
+                self.replies = self.sim.register_client(self.id);
+                None
+            }
+        }
+    }
+
+    //a random get, put or compare and swap of a random key against a random node, its
+    //reply or None if it timed out
+    async fn random_op(&mut self, pids: &[u64]) -> Option<String> {
+        let to = pids[self.sim.random(pids.len() as u64) as usize];
+        let key = format!("key{}", self.sim.random(SIM_KEYS));
+        let msg = match self.sim.random(3) {
+            0 => command(Operation::Get, key, 0),
+            1 => command(Operation::Put, key, self.sim.random(1000)),
+            _ => {
+                //expect one of a few values, so some of the swaps succeed
+                let expected = match self.sim.random(4) {
+                    0 => None,
+                    v => Some(v),
+                };
+                command(Operation::Cas { expected }, key, self.sim.random(4))
+            }
+        };
+        self.request(to, msg).await
+    }
+
+    //the value of a key without its version and revisions
+    async fn get(&mut self, to: u64, key: &str) -> String {
+        let reply = self.record(to, key).await;
+        match reply.split_once(" (") {
+            Some((value, _)) => value.to_string(),
+            None => reply,
+        }
+    }
+
+    async fn record(&mut self, to: u64, key: &str) -> String {
+        let msg = command(Operation::Get, key.to_string(), 0);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+}
+
+fn command(operation: Operation, key: String, value: u64) -> CMDMessage {
+    CMDMessage {
+        operation,
+        kv: KeyValue { key, value },
+    }
+}
+
+async fn settle() {
+    time::sleep(Duration::from_millis(SIM_SETTLE)).await;
+}
@@ -0,0 +1,956 @@
+use std::fs;
+use std::future::Future;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use structopt::StructOpt;
+use tokio::runtime::Builder;
+use tokio::time;
+
+use super::{command, settle, workload, Cluster, SimClient};
+use crate::configs::server::{
+    SIM_BULK_KEYS, SIM_KEYS, SIM_OPS, SIM_REPLY_TIMEOUT, SIM_SNAPSHOT_ENTRIES, SIM_WARMUP,
+};
+use crate::models::fault::FaultConfig;
+use crate::models::kv::{Condition, KeyValue, Mutation};
+use crate::models::msg::{CMDMessage, Operation};
+use crate::models::node::Node;
+use crate::network::transfer::Transfers;
+
+//run a scenario for every seed on its own three node cluster, started on a paused clock
+//like in the simulation mode, and return the trace of every run; `args` are given to
+//every node
+fn scenario<F, R>(seeds: Range<u64>, args: &[&str], run: F) -> Vec<Vec<String>>
+where
+    F: Fn(Cluster) -> R,
+    R: Future<Output = ()>,
+{
+    seeds
+        .map(|seed| {
+            let runtime = Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()
+                .unwrap();
+            let seed = seed.to_string();
+            let mut node = vec!["server", "--pid", "1", "--peers", "2", "3", "--seed", &seed];
+            node.extend(args);
+            let node = Node::from_iter(node);
+            runtime.block_on(async {
+                let cluster = Cluster::new(&node, node.seed.unwrap()).await;
+                let sim = cluster.sim.clone();
+                run(cluster).await;
+                sim.trace()
+            })
+        })
+        .collect()
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+//Test 1: Every request of the random workload is answered.
+#[test]
+fn every_request_is_answered() {
+    scenario(42..43, &[], |mut cluster| async move {
+        let pids = cluster.pids.clone();
+        for _ in 0..SIM_OPS {
+            assert!(cluster.client.random_op(&pids).await.is_some());
+        }
+    });
+}
+
+//Test 2: Drops, delays, duplicates and reordering do not make the nodes diverge.
+#[test]
+fn lossy_network() {
+    scenario(1..4, &[], lossy);
+}
+
+//Test 3: Faults are drawn from the seed, so a scenario reproduces exactly.
+#[test]
+fn faults_are_reproducible() {
+    assert_eq!(scenario(9..10, &[], lossy), scenario(9..10, &[], lossy));
+}
+
+//Test 4: The majority elects a new leader and keeps writing when the leader is cut off.
+#[test]
+fn leader_is_lost() {
+    scenario(1..4, &[], leader_loss);
+}
+
+//Test 5: Nothing is decided without a quorum, the cluster recovers once it is back.
+#[test]
+fn quorum_is_lost() {
+    scenario(1..4, &[], quorum_loss);
+}
+
+//Test 6: A node that restarts recovers its log from its storage and catches up.
+#[test]
+fn restart_from_storage() {
+    for seed in 1..4 {
+        let dir = data_dir(&format!("sim-restart-{}", seed));
+        let args = ["--data-dir", dir.to_str().unwrap()];
+        scenario(seed..seed + 1, &args, restart);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
+
+//Test 7: Leadership is handed to another node, which keeps accepting writes.
+#[test]
+fn transfer_leader() {
+    scenario(1..4, &[], transfer);
+}
+
+//Test 8: Racing compare and swaps are ordered by the log, exactly one of them wins.
+#[test]
+fn cas_race() {
+    scenario(1..4, &[], cas);
+}
+
+//Test 9: Concurrent increments through different nodes are all counted.
+#[test]
+fn concurrent_counter() {
+    scenario(1..4, &[], counter);
+}
+
+//Test 10: Transactions take a lock, move money under it and release it atomically.
+#[test]
+fn transactions() {
+    scenario(1..4, &[], txn);
+}
+
+//Test 11: A batch of puts is one log entry, a multi get reads it back from every node.
+#[test]
+fn multi_put_and_get() {
+    scenario(3..4, &[], batch);
+}
+
+//Test 12: A prefix is listed page by page and a range scan stops at its end.
+#[test]
+fn prefix_and_range() {
+    scenario(5..6, &[], scan);
+}
+
+//Test 13: Keys carry the log index they were created and last changed at.
+#[test]
+fn key_revisions() {
+    scenario(11..12, &[], revisions);
+}
+
+//Test 14: Keys with a ttl expire on every node, also after the leader is lost.
+#[test]
+fn expiring_keys() {
+    scenario(1..4, &[], ttl);
+}
+
+//Test 15: A watch streams every change of a prefix and resumes from a revision.
+#[test]
+fn watch_prefix() {
+    scenario(1..4, &[], watch);
+}
+
+//Test 16: A lock is held while its lease is kept alive and released once it expired.
+#[test]
+fn lock_with_lease() {
+    scenario(1..4, &[], lock);
+}
+
+//Test 17: Past values are rebuilt from the log at every index on every node.
+#[test]
+fn reads_at_index() {
+    scenario(1..4, &[], at_index);
+}
+
+//Test 18: Reads stay correct while the log is compacted again and again.
+#[test]
+fn repeated_compaction() {
+    let entries = SIM_SNAPSHOT_ENTRIES.to_string();
+    scenario(1..4, &["--snapshot-entries", &entries], compaction);
+}
+
+//Test 19: Messages too large for one package are sent in chunks and still arrive.
+#[test]
+fn large_batches_in_chunks() {
+    scenario(1..4, &[], chunks);
+}
+
+//the workload on a network that drops, delays, duplicates and reorders packages,
+//once it is healed every node must agree on every key
+async fn lossy(mut cluster: Cluster) {
+    cluster.faults.set(FaultConfig {
+        drop: 10,
+        delay: 5,
+        duplicate: 10,
+        reorder: 20,
+        partition: vec![],
+    });
+    workload(&mut cluster).await;
+    cluster.faults.set(FaultConfig::default());
+    settle().await;
+    for k in 0..SIM_KEYS {
+        let values = cluster.read_everywhere(&format!("key{}", k)).await;
+        assert!(
+            values.windows(2).all(|w| w[0] == w[1]),
+            "Nodes disagree on key{}: {:?}",
+            k,
+            values
+        );
+        assert!(!values[0].starts_with("Failed"), "{:?}", values);
+    }
+}
+
+//the leader is cut from the others, the majority elects a new one and keeps
+//accepting writes, the old leader catches up once the partition heals
+async fn leader_loss(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let rest: Vec<u64> = cluster
+        .pids
+        .iter()
+        .filter(|p| **p != leader)
+        .cloned()
+        .collect();
+    cluster.client.put(leader, "key", 1).await;
+    settle().await;
+
+    cluster.sim.record(format!("isolate leader {}", leader));
+    cluster.faults.set(FaultConfig {
+        partition: vec![vec![leader], rest.clone()],
+        ..FaultConfig::default()
+    });
+    settle().await;
+
+    let new_leader = cluster.client.leader(rest[0]).await;
+    assert!(
+        rest.contains(&new_leader),
+        "Majority did not elect a new leader, still {}",
+        new_leader
+    );
+    cluster.client.put(rest[0], "key", 2).await;
+    settle().await;
+    for pid in &rest {
+        assert_eq!(cluster.client.get(*pid, "key").await, "This value is : 2");
+    }
+
+    cluster.sim.record("heal".to_string());
+    cluster.faults.set(FaultConfig::default());
+    settle().await;
+    assert_eq!(cluster.client.get(leader, "key").await, "This value is : 2");
+}
+
+//every node is isolated, so nothing can be decided until the partition heals
+async fn quorum_loss(mut cluster: Cluster) {
+    let pids = cluster.pids.clone();
+    cluster.client.put(pids[0], "key", 1).await;
+    settle().await;
+
+    cluster.sim.record("isolate every node".to_string());
+    cluster.faults.set(FaultConfig {
+        partition: pids.iter().map(|p| vec![*p]).collect(),
+        ..FaultConfig::default()
+    });
+    cluster.client.put(pids[0], "key", 2).await;
+    settle().await;
+    //a get needs the read index of a majority as well, so no isolated node can answer it
+    for pid in &pids {
+        assert_eq!(cluster.client.get(*pid, "key").await, "Failed to get");
+    }
+
+    cluster.sim.record("heal".to_string());
+    cluster.faults.set(FaultConfig::default());
+    settle().await;
+    cluster.client.put(pids[0], "key", 3).await;
+    settle().await;
+    for pid in &pids {
+        assert_eq!(cluster.client.get(*pid, "key").await, "This value is : 3");
+    }
+}
+
+//a follower is shut down while the others keep writing, then started again cut off from
+//its peers: it reads what it decided before from its own storage, and once the partition
+//heals it gets what was written while it was down
+async fn restart(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let down = *cluster.pids.iter().find(|p| **p != leader).unwrap();
+    let rest: Vec<u64> = cluster
+        .pids
+        .iter()
+        .filter(|p| **p != down)
+        .cloned()
+        .collect();
+    cluster.client.put(leader, "a", 1).await;
+    settle().await;
+
+    cluster.stop(down).await;
+    let reply = cluster.client.put(leader, "b", 2).await;
+    assert_eq!(reply, "Successfully to put value");
+    cluster.faults.set(FaultConfig {
+        partition: vec![vec![down], rest],
+        ..FaultConfig::default()
+    });
+    cluster.start(down);
+    settle().await;
+    let msg = command(Operation::GetAt { at_index: 0 }, String::from("a"), 0);
+    let reply = cluster.client.request(down, msg).await.unwrap_or_default();
+    let a = "This value is : 1 (version 1, create revision 1, mod revision 1)";
+    assert_eq!(reply, a);
+
+    cluster.faults.set(FaultConfig::default());
+    settle().await;
+    assert_eq!(cluster.client.get(down, "b").await, "This value is : 2");
+}
+
+//the leadership is handed to another node, which then keeps accepting writes
+async fn transfer(mut cluster: Cluster) {
+    let pids = cluster.pids.clone();
+    let leader = cluster.client.leader(pids[0]).await;
+    cluster.client.put(leader, "key", 1).await;
+    settle().await;
+
+    let to = *pids.iter().find(|p| **p != leader).unwrap();
+    let reply = cluster.client.transfer(pids[0], to).await;
+    assert_eq!(reply, format!("Successfully to transfer leader to {}", to));
+    settle().await;
+    for pid in &pids {
+        assert_eq!(cluster.client.leader(*pid).await, to);
+    }
+
+    cluster.client.put(to, "key", 2).await;
+    settle().await;
+    for pid in &pids {
+        assert_eq!(cluster.client.get(*pid, "key").await, "This value is : 2");
+    }
+}
+
+//clients race to create the same key through different nodes, the log orders their
+//compare and swaps so exactly one of them wins and the others see its value
+async fn cas(mut cluster: Cluster) {
+    let mut clients = vec![];
+    for id in 1..=cluster.pids.len() as u64 {
+        let mut client = SimClient::new(id, &cluster.sim);
+        let to = cluster.pids[id as usize - 1];
+        clients.push(tokio::spawn(async move {
+            client.cas(to, "key", None, id).await
+        }));
+    }
+    let mut replies = vec![];
+    for client in clients {
+        replies.push(client.await.unwrap());
+    }
+    let winners: Vec<usize> = (0..replies.len())
+        .filter(|i| replies[*i] == "Successfully to compare and swap")
+        .collect();
+    assert_eq!(winners.len(), 1);
+    let value = winners[0] + 1;
+    for (i, reply) in replies.iter().enumerate() {
+        if i != winners[0] {
+            let lost = format!("Failed to compare and swap, this value is : {}", value);
+            assert_eq!(*reply, lost);
+        }
+    }
+
+    //a swap from the current value succeeds, a stale one does not
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let reply = cluster
+        .client
+        .cas(leader, "key", Some(value as u64), 10)
+        .await;
+    assert_eq!(reply, "Successfully to compare and swap");
+    let reply = cluster
+        .client
+        .cas(leader, "key", Some(value as u64), 11)
+        .await;
+    assert_eq!(reply, "Failed to compare and swap, this value is : 10");
+    settle().await;
+    for pid in cluster.pids.clone() {
+        assert_eq!(cluster.client.get(pid, "key").await, "This value is : 10");
+    }
+}
+
+//clients increment the same counter through different nodes, no increment is lost
+//and a decrement below zero fails without changing the counter
+async fn counter(mut cluster: Cluster) {
+    let mut clients = vec![];
+    for id in 1..=cluster.pids.len() as u64 {
+        let mut client = SimClient::new(id, &cluster.sim);
+        let to = cluster.pids[id as usize - 1];
+        clients.push(tokio::spawn(async move {
+            for _ in 0..SIM_OPS / 5 {
+                client.count(to, Operation::Incr, "counter", id).await;
+            }
+        }));
+    }
+    for client in clients {
+        client.await.unwrap();
+    }
+
+    let total = (1..=cluster.pids.len() as u64).sum::<u64>() * (SIM_OPS / 5);
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let reply = cluster
+        .client
+        .count(leader, Operation::Decr, "counter", total + 1);
+    let underflow = format!(
+        "Failed to decrement, it would underflow, this value is : {}",
+        total
+    );
+    assert_eq!(reply.await, underflow);
+    let reply = cluster
+        .client
+        .count(leader, Operation::Decr, "counter", total);
+    assert_eq!(reply.await, "Successfully to decrement, this value is : 0");
+    settle().await;
+    for pid in cluster.pids.clone() {
+        let value = cluster.client.get(pid, "counter").await;
+        assert_eq!(value, "This value is : 0");
+    }
+}
+
+//clients race for a lock with transactions through different nodes, then the winner
+//moves money between two accounts under the lock and releases it in one transaction
+async fn txn(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let open = vec![absent("a"), absent("b")];
+    let reply = cluster
+        .client
+        .txn(leader, open, vec![set("a", 100), set("b", 0)]);
+    assert_eq!(reply.await, "Successfully to commit the transaction");
+
+    let mut clients = vec![];
+    for id in 1..=cluster.pids.len() as u64 {
+        let mut client = SimClient::new(id, &cluster.sim);
+        let to = cluster.pids[id as usize - 1];
+        clients.push(tokio::spawn(async move {
+            client
+                .txn(to, vec![absent("lock")], vec![set("lock", id)])
+                .await
+        }));
+    }
+    let mut won = 0;
+    for client in clients {
+        if client.await.unwrap() == "Successfully to commit the transaction" {
+            won += 1;
+        }
+    }
+    assert_eq!(won, 1);
+
+    let locked = vec![
+        Condition::Version {
+            key: String::from("lock"),
+            version: 1,
+        },
+        Condition::Equals {
+            key: String::from("a"),
+            value: 100,
+        },
+    ];
+    let transfer = vec![
+        set("a", 70),
+        set("b", 30),
+        Mutation::Delete {
+            key: String::from("lock"),
+        },
+    ];
+    let reply = cluster.client.txn(leader, locked.clone(), transfer.clone());
+    assert_eq!(reply.await, "Successfully to commit the transaction");
+    //the lock is gone, so the same transaction is not applied twice
+    let reply = cluster.client.txn(leader, locked, transfer);
+    let aborted = "Failed to commit the transaction, condition 0 does not hold";
+    assert_eq!(reply.await, aborted);
+
+    settle().await;
+    for pid in cluster.pids.clone() {
+        assert_eq!(cluster.client.get(pid, "a").await, "This value is : 70");
+        assert_eq!(cluster.client.get(pid, "b").await, "This value is : 30");
+        assert_eq!(
+            cluster.client.get(pid, "lock").await,
+            "No value about the key"
+        );
+    }
+}
+
+//many keys are written in one request and read back in one request from every node
+async fn batch(mut cluster: Cluster) {
+    let kvs: Vec<KeyValue> = (0..SIM_OPS)
+        .map(|i| KeyValue {
+            key: format!("key{}", i),
+            value: i,
+        })
+        .collect();
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let operation = Operation::MultiPut { kvs };
+    let reply = cluster
+        .client
+        .request(leader, command(operation, String::from("_"), 0));
+    assert_eq!(
+        reply.await.unwrap_or_default(),
+        "Successfully to put values"
+    );
+    settle().await;
+
+    let keys = vec![
+        String::from("key0"),
+        String::from("key7"),
+        String::from("none"),
+    ];
+    for pid in cluster.pids.clone() {
+        let operation = Operation::MultiGet { keys: keys.clone() };
+        let reply = cluster
+            .client
+            .request(pid, command(operation, String::from("_"), 0));
+        let values = "These values are : key0=0 key7=7 none=-";
+        assert_eq!(reply.await.unwrap_or_default(), values);
+    }
+}
+
+//keys with a prefix are listed page by page, and a range scan stops at its end
+async fn scan(mut cluster: Cluster) {
+    let mut kvs: Vec<KeyValue> = (0..SIM_OPS / 2)
+        .map(|i| KeyValue {
+            key: format!("user/{:02}", i),
+            value: i,
+        })
+        .collect();
+    kvs.push(KeyValue {
+        key: String::from("users"),
+        value: 0,
+    });
+    kvs.push(KeyValue {
+        key: String::from("admin/00"),
+        value: 0,
+    });
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let operation = Operation::MultiPut { kvs };
+    let reply = cluster
+        .client
+        .request(leader, command(operation, String::from("_"), 0));
+    assert_eq!(
+        reply.await.unwrap_or_default(),
+        "Successfully to put values"
+    );
+    settle().await;
+
+    for pid in cluster.pids.clone() {
+        let mut keys = vec![];
+        let mut token = None;
+        loop {
+            let operation = Operation::Prefix {
+                prefix: String::from("user/"),
+                limit: 10,
+                token,
+            };
+            let reply = cluster
+                .client
+                .request(pid, command(operation, String::from("_"), 0));
+            let reply = reply.await.unwrap_or_default();
+            let (page, next) = match reply.split_once(", next page : ") {
+                Some((page, next)) => (page, Some(next.to_string())),
+                None => (reply.as_str(), None),
+            };
+            let page = page.trim_start_matches("These values are : ");
+            keys.extend(page.split_whitespace().map(|kv| kv.to_string()));
+            token = next;
+            if token.is_none() {
+                break;
+            }
+        }
+        let expected: Vec<String> = (0..SIM_OPS / 2)
+            .map(|i| format!("user/{:02}={}", i, i))
+            .collect();
+        assert_eq!(keys, expected);
+
+        let operation = Operation::Scan {
+            start: String::from("user/10"),
+            end: Some(String::from("user/13")),
+            limit: 0,
+            token: None,
+        };
+        let reply = cluster
+            .client
+            .request(pid, command(operation, String::from("_"), 0));
+        let values = "These values are : user/10=10 user/11=11 user/12=12";
+        assert_eq!(reply.await.unwrap_or_default(), values);
+    }
+}
+
+//every change of a key moves its mod revision to the revision of its log entry, a write
+//conditioned on the mod revision a client read fails once another client changed the key
+async fn revisions(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    cluster.client.put(leader, "a", 1).await;
+    cluster.client.put(leader, "b", 1).await;
+    cluster.client.put(leader, "a", 2).await;
+    settle().await;
+    for pid in cluster.pids.clone() {
+        let a = "This value is : 2 (version 2, create revision 1, mod revision 3)";
+        assert_eq!(cluster.client.record(pid, "a").await, a);
+        let b = "This value is : 1 (version 1, create revision 2, mod revision 2)";
+        assert_eq!(cluster.client.record(pid, "b").await, b);
+    }
+
+    //two clients read `a` at mod revision 3 and both try to change it
+    let read = vec![Condition::ModRevision {
+        key: String::from("a"),
+        revision: 3,
+    }];
+    let reply = cluster.client.txn(leader, read.clone(), vec![set("a", 3)]);
+    assert_eq!(reply.await, "Successfully to commit the transaction");
+    let reply = cluster.client.txn(leader, read, vec![set("a", 4)]);
+    let aborted = "Failed to commit the transaction, condition 0 does not hold";
+    assert_eq!(reply.await, aborted);
+    settle().await;
+    let a = "This value is : 3 (version 3, create revision 1, mod revision 4)";
+    assert_eq!(cluster.client.record(leader, "a").await, a);
+}
+
+//a key with a ttl vanishes from every node once it expired, a change of the key
+//restarts its ttl, and the expiry survives the loss of the leader
+async fn ttl(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let msg = command(Operation::PutTtl { ttl: 3000 }, String::from("session"), 1);
+    cluster.client.request(leader, msg).await;
+    let msg = command(Operation::PutTtl { ttl: 3000 }, String::from("kept"), 1);
+    cluster.client.request(leader, msg).await;
+    settle().await;
+    for pid in cluster.pids.clone() {
+        let value = cluster.client.get(pid, "session").await;
+        assert_eq!(value, "This value is : 1");
+    }
+
+    //a put without ttl keeps the key
+    cluster.client.put(leader, "kept", 2).await;
+    let rest: Vec<u64> = cluster
+        .pids
+        .iter()
+        .filter(|p| **p != leader)
+        .cloned()
+        .collect();
+    cluster.sim.record(format!("isolate leader {}", leader));
+    cluster.faults.set(FaultConfig {
+        partition: vec![vec![leader], rest.clone()],
+        ..FaultConfig::default()
+    });
+    time::sleep(Duration::from_millis(SIM_WARMUP + 3000)).await;
+    cluster.faults.set(FaultConfig::default());
+    settle().await;
+
+    for pid in cluster.pids.clone() {
+        let value = cluster.client.get(pid, "session").await;
+        assert_eq!(value, "No value about the key");
+        assert_eq!(cluster.client.get(pid, "kept").await, "This value is : 2");
+    }
+}
+
+//a client watches a prefix on a follower and gets every change of it in order,
+//a second client resumes from a revision and gets the changes from there again
+async fn watch(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let follower = *cluster.pids.iter().find(|p| **p != leader).unwrap();
+    let mut watcher = SimClient::new(1, &cluster.sim);
+    let reply = watcher.watch(follower, "cfg/", 0).await;
+    assert_eq!(reply, "Successfully to watch");
+
+    cluster.client.put(leader, "cfg/a", 1).await;
+    cluster.client.put(leader, "other", 1).await;
+    cluster.client.put(leader, "cfg/b", 2).await;
+    let delete = vec![Mutation::Delete {
+        key: String::from("cfg/a"),
+    }];
+    cluster.client.txn(leader, vec![], delete).await;
+
+    let events = [
+        "Event : cfg/a=1 at revision 1",
+        "Event : cfg/b=2 at revision 3",
+        "Event : cfg/a deleted at revision 4",
+    ];
+    for event in events {
+        assert_eq!(watcher.receive().await.unwrap_or_default(), event);
+    }
+
+    let mut resumed = SimClient::new(2, &cluster.sim);
+    let reply = resumed.watch(follower, "cfg/", 3).await;
+    assert_eq!(reply, "Successfully to watch");
+    for event in &events[1..] {
+        assert_eq!(resumed.receive().await.unwrap_or_default(), *event);
+    }
+}
+
+//two clients race for a lock with their leases, the holder keeps its lease alive for
+//longer than its ttl, then stops and the lock and the keys of the lease are released
+async fn lock(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let follower = *cluster.pids.iter().find(|p| **p != leader).unwrap();
+    let mut other = SimClient::new(1, &cluster.sim);
+    let holder = cluster.client.grant(leader, 2000).await;
+    let waiter = other.grant(follower, 2000).await;
+
+    let reply = cluster.client.lock(leader, "job", holder).await;
+    assert_eq!(reply, "Successfully to lock job");
+    let held = format!("Failed to lock job, it is held by lease {}", holder);
+    assert_eq!(other.lock(follower, "job", waiter).await, held);
+    let msg = command(
+        Operation::PutLease { lease: holder },
+        String::from("owner"),
+        1,
+    );
+    cluster.client.request(leader, msg).await;
+
+    //the lease outlives its ttl as long as it is kept alive
+    for _ in 0..4 {
+        time::sleep(Duration::from_millis(1000)).await;
+        let kept = format!("Successfully to keep lease {} alive", holder);
+        assert_eq!(cluster.client.keep_alive(leader, holder).await, kept);
+    }
+    assert_eq!(other.lock(follower, "job", waiter).await, held);
+    let msg = command(Operation::KeepAlive { lease: waiter }, String::from("_"), 0);
+    other.request(follower, msg).await;
+
+    time::sleep(Duration::from_millis(2000)).await;
+    settle().await;
+    for pid in cluster.pids.clone() {
+        assert_eq!(
+            cluster.client.get(pid, "job").await,
+            "No value about the key"
+        );
+        assert_eq!(
+            cluster.client.get(pid, "owner").await,
+            "No value about the key"
+        );
+    }
+    let gone = format!("Failed to keep alive, no lease {}", holder);
+    assert_eq!(cluster.client.keep_alive(leader, holder).await, gone);
+
+    //the waiter's lease expired too, it needs a new one
+    let waiter = other.grant(follower, 2000).await;
+    assert_eq!(
+        other.lock(follower, "job", waiter).await,
+        "Successfully to lock job"
+    );
+    let msg = command(Operation::Unlock { lease: waiter }, String::from("job"), 0);
+    let reply = other.request(follower, msg).await.unwrap_or_default();
+    assert_eq!(reply, "Successfully to unlock job");
+}
+
+//the values of a key and the whole store at past log indexes are rebuilt from the
+//log on every node, an index that is not decided yet can not be read
+async fn at_index(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    cluster.client.put(leader, "a", 1).await;
+    cluster.client.put(leader, "b", 2).await;
+    cluster.client.put(leader, "a", 3).await;
+    let delete = vec![Mutation::Delete {
+        key: String::from("a"),
+    }];
+    cluster.client.txn(leader, vec![], delete).await;
+    settle().await;
+
+    let a = [
+        "This value is : 1 (version 1, create revision 1, mod revision 1)",
+        "This value is : 1 (version 1, create revision 1, mod revision 1)",
+        "This value is : 3 (version 2, create revision 1, mod revision 3)",
+        "No value about the key",
+    ];
+    for pid in cluster.pids.clone() {
+        for (index, value) in a.iter().enumerate() {
+            let operation = Operation::GetAt {
+                at_index: index as u64,
+            };
+            let msg = command(operation, String::from("a"), 0);
+            let reply = cluster.client.request(pid, msg).await.unwrap_or_default();
+            assert_eq!(reply, *value);
+        }
+        let msg = command(Operation::SnapshotAt { at_index: 2 }, String::from("_"), 0);
+        let reply = cluster.client.request(pid, msg).await.unwrap_or_default();
+        assert_eq!(reply, "These values are : a=3 b=2");
+    }
+
+    let msg = command(Operation::GetAt { at_index: 9 }, String::from("a"), 0);
+    let reply = cluster
+        .client
+        .request(leader, msg)
+        .await
+        .unwrap_or_default();
+    let undecided = "Failed to read at index 9, the log is applied up to index 3";
+    assert_eq!(reply, undecided);
+}
+
+//the log is compacted every few entries while keys and a counter are written, every
+//node still reads every value and the counter counts every increment
+async fn compaction(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    for round in 0..4 {
+        for i in 0..SIM_SNAPSHOT_ENTRIES {
+            cluster
+                .client
+                .put(leader, &format!("key{}", i), round)
+                .await;
+            cluster
+                .client
+                .count(leader, Operation::Incr, "count", 1)
+                .await;
+        }
+        settle().await;
+        let count = format!("This value is : {}", (round + 1) * SIM_SNAPSHOT_ENTRIES);
+        for pid in cluster.pids.clone() {
+            for i in 0..SIM_SNAPSHOT_ENTRIES {
+                let value = cluster.client.get(pid, &format!("key{}", i)).await;
+                assert_eq!(value, format!("This value is : {}", round));
+            }
+            assert_eq!(cluster.client.get(pid, "count").await, count);
+        }
+    }
+
+    let msg = command(Operation::GetAt { at_index: 0 }, String::from("count"), 0);
+    let reply = cluster
+        .client
+        .request(leader, msg)
+        .await
+        .unwrap_or_default();
+    assert!(reply.contains("it is compacted"), "{}", reply);
+}
+
+//batches too large for one package reach the followers in chunks, first on a network
+//that drops, duplicates and reorders them, then on a healthy one
+async fn chunks(mut cluster: Cluster) {
+    let leader = cluster.client.leader(cluster.pids[0]).await;
+    let bulk = |batch: u64| -> CMDMessage {
+        let kvs = (0..SIM_BULK_KEYS)
+            .map(|i| KeyValue {
+                key: format!("bulk{}/{:05}", batch, i),
+                value: i,
+            })
+            .collect();
+        command(Operation::MultiPut { kvs }, String::from("_"), 0)
+    };
+    let serialized = serde_json::to_string(&bulk(0)).unwrap();
+    assert!(Transfers::large(&serialized), "a batch fits in one package");
+
+    cluster.faults.set(FaultConfig {
+        drop: 10,
+        delay: 5,
+        duplicate: 10,
+        reorder: 20,
+        partition: vec![],
+    });
+    cluster.client.request(leader, bulk(0)).await;
+    cluster.faults.set(FaultConfig::default());
+    settle().await;
+    let last = format!("bulk0/{:05}", SIM_BULK_KEYS - 1);
+    let values = cluster.read_everywhere(&last).await;
+    assert!(values.windows(2).all(|w| w[0] == w[1]), "{:?}", values);
+
+    let reply = cluster.client.request(leader, bulk(1)).await;
+    assert_eq!(reply.unwrap_or_default(), "Successfully to put values");
+    settle().await;
+    let last = format!("bulk1/{:05}", SIM_BULK_KEYS - 1);
+    let value = format!("This value is : {}", SIM_BULK_KEYS - 1);
+    for pid in cluster.pids.clone() {
+        assert_eq!(cluster.client.get(pid, &last).await, value);
+    }
+}
+
+impl SimClient {
+    async fn put(&mut self, to: u64, key: &str, value: u64) -> String {
+        let msg = command(Operation::Put, key.to_string(), value);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    async fn cas(&mut self, to: u64, key: &str, expected: Option<u64>, new: u64) -> String {
+        let msg = command(Operation::Cas { expected }, key.to_string(), new);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    //watch the keys with a prefix from a revision on
+    async fn watch(&mut self, to: u64, prefix: &str, from_revision: u64) -> String {
+        let operation = Operation::Watch {
+            prefix: true,
+            from_revision,
+        };
+        let msg = command(operation, prefix.to_string(), 0);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    //the next message pushed to the client
+    async fn receive(&mut self) -> Option<String> {
+        let timeout = Duration::from_millis(SIM_REPLY_TIMEOUT);
+        time::timeout(timeout, self.replies.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    //an increment or decrement
+    async fn count(&mut self, to: u64, operation: Operation, key: &str, delta: u64) -> String {
+        let msg = command(operation, key.to_string(), delta);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    async fn txn(
+        &mut self,
+        to: u64,
+        conditions: Vec<Condition>,
+        mutations: Vec<Mutation>,
+    ) -> String {
+        let operation = Operation::Txn {
+            conditions,
+            mutations,
+        };
+        let msg = command(operation, String::from("_"), 0);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    //a new lease with a ttl in ms
+    async fn grant(&mut self, to: u64, ttl: u64) -> u64 {
+        let msg = command(Operation::Grant { ttl }, String::from("_"), 0);
+        let reply = self.request(to, msg).await.unwrap_or_default();
+        reply
+            .trim_start_matches("Successfully to grant lease ")
+            .parse()
+            .expect("Failed to parse the lease")
+    }
+
+    async fn keep_alive(&mut self, to: u64, lease: u64) -> String {
+        let msg = command(Operation::KeepAlive { lease }, String::from("_"), 0);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    async fn lock(&mut self, to: u64, name: &str, lease: u64) -> String {
+        let msg = command(Operation::Lock { lease }, name.to_string(), 0);
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    async fn transfer(&mut self, to: u64, leader: u64) -> String {
+        let msg = command(
+            Operation::TransferLeader { to: leader },
+            String::from("_"),
+            0,
+        );
+        self.request(to, msg).await.unwrap_or_default()
+    }
+
+    //the leader as seen by the node `to`
+    async fn leader(&mut self, to: u64) -> u64 {
+        let msg = command(Operation::Leader, String::from("_"), 0);
+        let reply = self.request(to, msg).await.unwrap_or_default();
+        reply
+            .trim_start_matches("Leader is : ")
+            .parse()
+            .expect("Failed to parse the leader")
+    }
+}
+
+fn absent(key: &str) -> Condition {
+    Condition::Exists {
+        key: key.to_string(),
+        exists: false,
+    }
+}
+
+fn set(key: &str, value: u64) -> Mutation {
+    Mutation::Put(KeyValue {
+        key: key.to_string(),
+        value,
+    })
+}
@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use omnipaxos_runtime::omnipaxos::{OmniPaxosNode, ReadEntry};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant};
+
+use crate::configs::server::{APPLY_INTERVAL, PROPOSAL_TIMEOUT, WATCH_HISTORY};
+use crate::models::kv::{self, Command, KVSnapshot, LogEntry, Outcome, Record, ID_BITS};
+use crate::print_log;
+use crate::shutdown::Shutdown;
+
+struct State {
+    kv: kv::Store,
+    //the number of log entries applied to `kv`
+    applied: u64,
+    //the index the log was last compacted up to, when, and the size of the entries
+    //applied since
+    compacted: u64,
+    compacted_at: Instant,
+    bytes: u64,
+    //the proposals of this node that wait for their outcome
+    waiting: HashMap<u64, oneshot::Sender<Outcome>>,
+    //the pid of this node in the upper bits and a counter in the lower ones
+    next_id: u64,
+    pid: u64,
+    //the highest proposal id applied of every node, by pid, kept in the snapshots
+    ids: BTreeMap<u64, u64>,
+    //the keys with a ttl, with their mod revision and when they expire on the clock of
+    //this node, every replica tracks them so a new leader can take over the expiry,
+    //ordered so every leader proposes the expiries in the same order
+    expiring: BTreeMap<String, (u64, Instant)>,
+    //the same for the leases, with the revision they were kept alive at
+    leases: BTreeMap<u64, (u64, Instant)>,
+    //the last changes, complete from the revision `history` on
+    events: VecDeque<Event>,
+    history: u64,
+    watchers: Vec<Watcher>,
+}
+
+//a change of a key, `value` is None if it was deleted
+#[derive(Clone, Debug)]
+pub(crate) struct Event {
+    pub revision: u64,
+    pub key: String,
+    pub value: Option<u64>,
+}
+
+struct Watcher {
+    key: String,
+    prefix: bool,
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+impl Watcher {
+    fn matches(&self, key: &str) -> bool {
+        match self.prefix {
+            true => key.starts_with(&self.key),
+            false => key == self.key,
+        }
+    }
+}
+
+impl State {
+    //keep a change in the history and hand it to the watchers of the key,
+    //a watcher whose client is gone is dropped
+    fn publish(&mut self, event: Event) {
+        self.watchers
+            .retain(|w| !w.matches(&event.key) || w.sender.send(event.clone()).is_ok());
+        self.events.push_back(event);
+        if self.events.len() > WATCH_HISTORY {
+            if let Some(event) = self.events.pop_front() {
+                self.history = event.revision + 1;
+            }
+        }
+    }
+
+    //forget the history, it only starts at `revision`
+    fn reset_history(&mut self, revision: u64) {
+        self.events.clear();
+        self.history = revision;
+    }
+
+    //track the expiry of the keys a command changed
+    fn track(&mut self, keys: &[String]) {
+        for key in keys {
+            match self.kv.data.get(key) {
+                Some(Record {
+                    mod_revision,
+                    ttl: Some(ttl),
+                    ..
+                }) => {
+                    let deadline = Instant::now() + Duration::from_millis(*ttl);
+                    self.expiring
+                        .insert(key.to_string(), (*mod_revision, deadline));
+                }
+                _ => {
+                    self.expiring.remove(key);
+                }
+            }
+        }
+    }
+
+    //track the expiry of a lease a command granted, kept alive or revoked
+    fn track_lease(&mut self, lease: u64) {
+        match self.kv.leases.get(&lease) {
+            Some(kv::Lease { ttl, revision }) => {
+                let deadline = Instant::now() + Duration::from_millis(*ttl);
+                self.leases.insert(lease, (*revision, deadline));
+            }
+            None => {
+                self.leases.remove(&lease);
+            }
+        }
+    }
+}
+
+//why the state at a past index can not be rebuilt
+#[derive(Debug)]
+pub(crate) enum PastErr {
+    //the index was compacted into a snapshot, the history starts at the index
+    Compacted(u64),
+    //the index is not applied yet, the log is applied up to the index
+    Undecided(u64),
+}
+
+//the key-value state machine of a node, every replica applies the decided log in
+//the same order, so they all reach the same state and the same outcomes
+#[derive(Clone)]
+pub(crate) struct Store {
+    state: Arc<Mutex<State>>,
+}
+
+impl Store {
+    pub fn new(pid: u64) -> Self {
+        Store {
+            state: Arc::new(Mutex::new(State {
+                kv: kv::Store::default(),
+                applied: 0,
+                compacted: 0,
+                compacted_at: Instant::now(),
+                bytes: 0,
+                waiting: HashMap::new(),
+                next_id: pid << ID_BITS,
+                pid,
+                ids: BTreeMap::new(),
+                expiring: BTreeMap::new(),
+                leases: BTreeMap::new(),
+                events: VecDeque::new(),
+                history: 0,
+                watchers: vec![],
+            })),
+        }
+    }
+
+    //the number of log entries this node applied, it trails the decided index by at
+    //most one apply interval
+    pub fn applied(&self) -> u64 {
+        self.state.lock().unwrap().applied
+    }
+
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.record(key).map(|r| r.value)
+    }
+
+    //the value of a key with its version and revisions
+    pub fn record(&self, key: &str) -> Option<Record> {
+        self.state.lock().unwrap().kv.data.get(key).cloned()
+    }
+
+    //the keys from `start` on while they pass `within`, at most `limit` of them unless it
+    //is 0, with the key the next page starts at if there are more
+    pub fn page(
+        &self,
+        start: &str,
+        within: impl Fn(&str) -> bool,
+        limit: usize,
+    ) -> (Vec<(String, u64)>, Option<String>) {
+        let state = self.state.lock().unwrap();
+        let mut keys = state
+            .kv
+            .data
+            .range(start.to_string()..)
+            .take_while(|(key, _)| within(key))
+            .map(|(key, record)| (key.clone(), record.value));
+        let page = match limit {
+            0 => keys.by_ref().collect(),
+            _ => keys.by_ref().take(limit).collect(),
+        };
+        (page, keys.next().map(|(key, _)| key))
+    }
+
+    //go on after the highest id of this node in the log it recovered, decided or not, or
+    //in the snapshot the log starts with, before the node proposes anything
+    pub async fn seed(&self, op: &OmniPaxosNode<LogEntry, KVSnapshot>) {
+        let entries = op.read_entries(0..).await.unwrap_or_default();
+        let mut state = self.state.lock().unwrap();
+        let pid = state.pid;
+        for entry in entries {
+            let last = match entry {
+                ReadEntry::Decided(entry) | ReadEntry::Undecided(entry)
+                    if entry.id >> ID_BITS == pid =>
+                {
+                    entry.id
+                }
+                ReadEntry::Snapshotted(snapshotted) => match snapshotted.snapshot.ids.get(&pid) {
+                    Some(id) => *id,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            state.next_id = state.next_id.max(last);
+        }
+    }
+
+    //a unique id for a proposal
+    fn next_id(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        state.next_id
+    }
+
+    //append a command to the log and wait until it is applied,
+    //None if it failed or was not decided in time
+    pub async fn propose(
+        &self,
+        op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+        command: Command,
+    ) -> Option<Outcome> {
+        let (sender, receiver) = oneshot::channel();
+        let id = self.next_id();
+        self.state.lock().unwrap().waiting.insert(id, sender);
+        if op.append(LogEntry { id, command }).await.is_err() {
+            self.state.lock().unwrap().waiting.remove(&id);
+            return None;
+        }
+        let outcome = time::timeout(Duration::from_millis(PROPOSAL_TIMEOUT), receiver).await;
+        self.state.lock().unwrap().waiting.remove(&id);
+        match outcome {
+            Ok(Ok(outcome)) => Some(outcome),
+            _ => None,
+        }
+    }
+
+    //the number of proposals of this node that wait for their outcome
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    //the number of entries and bytes applied since the last compaction and the time
+    //since then
+    pub fn uncompacted(&self) -> (u64, u64, Duration) {
+        let state = self.state.lock().unwrap();
+        let entries = state.applied - state.compacted;
+        (entries, state.bytes, state.compacted_at.elapsed())
+    }
+
+    //the state of this node as a snapshot of the log up to the index it applied
+    pub fn snapshot(&self) -> KVSnapshot {
+        let state = self.state.lock().unwrap();
+        KVSnapshot {
+            snapshotted: state.kv.clone(),
+            delta: vec![],
+            len: state.applied,
+            ids: state.ids.clone(),
+        }
+    }
+
+    //the same snapshot, counting as the last snapshot of the node
+    pub fn checkpoint(&self) -> KVSnapshot {
+        let mut state = self.state.lock().unwrap();
+        state.compacted = state.applied;
+        state.compacted_at = Instant::now();
+        state.bytes = 0;
+        KVSnapshot {
+            snapshotted: state.kv.clone(),
+            delta: vec![],
+            len: state.applied,
+            ids: state.ids.clone(),
+        }
+    }
+
+    //compact the log of every replica up to the index this node applied,
+    //None if it failed
+    pub async fn compact(&self, op: &OmniPaxosNode<LogEntry, KVSnapshot>) -> Option<u64> {
+        let index = self.state.lock().unwrap().applied;
+        op.snapshot(Some(index), false).await.ok()?;
+        let mut state = self.state.lock().unwrap();
+        state.compacted = index;
+        state.compacted_at = Instant::now();
+        state.bytes = 0;
+        Some(index)
+    }
+
+    //the state right after the entry at `index` was applied, rebuilt from the log:
+    //from the start, or from the snapshot the log was compacted into if it does not
+    //reach past `index`
+    pub async fn at(
+        &self,
+        op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+        index: u64,
+    ) -> Result<kv::Store, PastErr> {
+        let applied = self.state.lock().unwrap().applied;
+        if index >= applied {
+            return Err(PastErr::Undecided(applied.saturating_sub(1)));
+        }
+        let entries = op
+            .read_entries(0..index + 1)
+            .await
+            .ok_or(PastErr::Undecided(applied - 1))?;
+        let mut store = kv::Store::default();
+        let mut next = 0;
+        for entry in entries {
+            match entry {
+                ReadEntry::Decided(entry) => {
+                    entry.command.apply(&mut store, next + 1);
+                    next += 1;
+                }
+                //the snapshot holds the state after its last entry
+                ReadEntry::Snapshotted(snapshotted) if snapshotted.trimmed_idx <= index + 1 => {
+                    store = snapshotted.snapshot.state();
+                    next = snapshotted.trimmed_idx;
+                }
+                ReadEntry::Snapshotted(snapshotted) => {
+                    return Err(PastErr::Compacted(snapshotted.trimmed_idx - 1));
+                }
+                ReadEntry::Trimmed(idx) => return Err(PastErr::Compacted(idx)),
+                _ => {}
+            }
+        }
+        Ok(store)
+    }
+
+    //the changes of a key, or of the keys with a prefix, from `from` on: the ones in the
+    //history and then every change applied later, Err with the start of the history
+    //if it does not reach back to `from`
+    pub fn watch(
+        &self,
+        key: String,
+        prefix: bool,
+        from: u64,
+    ) -> Result<mpsc::UnboundedReceiver<Event>, u64> {
+        let mut state = self.state.lock().unwrap();
+        if from < state.history {
+            return Err(state.history);
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let watcher = Watcher {
+            key,
+            prefix,
+            sender,
+        };
+        for event in &state.events {
+            if event.revision >= from && watcher.matches(&event.key) {
+                let _ = watcher.sender.send(event.clone());
+            }
+        }
+        state.watchers.push(watcher);
+        Ok(receiver)
+    }
+
+    //the expiry of the keys and the revocation of the leases whose ttl ran out, they
+    //are due again if they are not decided in time
+    fn expired(&self) -> Vec<Command> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let retry = now + Duration::from_millis(PROPOSAL_TIMEOUT);
+        let mut expired = vec![];
+        for (key, (revision, deadline)) in state.expiring.iter_mut() {
+            if *deadline <= now {
+                let key = key.clone();
+                let revision = *revision;
+                expired.push(Command::Expire { key, revision });
+                *deadline = retry;
+            }
+        }
+        for (lease, (revision, deadline)) in state.leases.iter_mut() {
+            if *deadline <= now {
+                let lease = *lease;
+                let revision = Some(*revision);
+                expired.push(Command::Revoke { lease, revision });
+                *deadline = retry;
+            }
+        }
+        expired
+    }
+
+    //the index the log of this node reaches, the entries it accepted but that are not
+    //decided yet included
+    pub async fn accepted(&self, op: &OmniPaxosNode<LogEntry, KVSnapshot>) -> u64 {
+        let applied = self.applied();
+        let entries = match op.read_entries(applied..).await {
+            Some(entries) => entries,
+            None => return applied,
+        };
+        let mut end = applied;
+        for entry in entries {
+            match entry {
+                ReadEntry::Decided(_) | ReadEntry::Undecided(_) => end += 1,
+                ReadEntry::Snapshotted(snapshotted) => end = snapshotted.trimmed_idx,
+                ReadEntry::Trimmed(idx) => end = idx,
+                _ => {}
+            }
+        }
+        end
+    }
+
+    //apply the decided log until it reaches `index`, false if it does not in time,
+    //e.g. because the entries up to there are not decided yet
+    pub async fn reach(&self, op: &OmniPaxosNode<LogEntry, KVSnapshot>, index: u64) -> bool {
+        let deadline = Instant::now() + Duration::from_millis(PROPOSAL_TIMEOUT);
+        loop {
+            self.catch_up(op).await;
+            if self.applied() >= index {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            time::sleep(Duration::from_millis(APPLY_INTERVAL)).await;
+        }
+    }
+
+    //apply the entries decided since the last call
+    pub async fn catch_up(&self, op: &OmniPaxosNode<LogEntry, KVSnapshot>) {
+        let applied = self.state.lock().unwrap().applied;
+        let entries = match op.read_decided_suffix(applied).await {
+            Some(entries) => entries,
+            None => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        //another call applied them in the meantime
+        if state.applied != applied {
+            return;
+        }
+        for entry in entries {
+            match entry {
+                ReadEntry::Decided(entry) => {
+                    let revision = state.applied + 1;
+                    let keys = entry.command.keys(&state.kv);
+                    let before: Vec<Option<u64>> = keys
+                        .iter()
+                        .map(|key| state.kv.data.get(key).map(|r| r.mod_revision))
+                        .collect();
+                    let outcome = entry.command.apply(&mut state.kv, revision);
+                    state.applied += 1;
+                    state.bytes += serde_json::to_vec(&entry).map_or(0, |b| b.len() as u64);
+                    state.track(&keys);
+                    match entry.command {
+                        Command::Grant { .. } => state.track_lease(revision),
+                        Command::KeepAlive { lease } | Command::Revoke { lease, .. } => {
+                            state.track_lease(lease)
+                        }
+                        _ => {}
+                    }
+                    for (key, before) in keys.iter().zip(before) {
+                        let after = state.kv.data.get(key);
+                        if after.map(|r| r.mod_revision) != before {
+                            let event = Event {
+                                revision,
+                                key: key.clone(),
+                                value: after.map(|r| r.value),
+                            };
+                            state.publish(event);
+                        }
+                    }
+                    //a restarted node goes on after the ids it finds in its log
+                    kv::seen(&mut state.ids, entry.id);
+                    if entry.id >> ID_BITS == state.pid {
+                        state.next_id = state.next_id.max(entry.id);
+                    }
+                    if let Some(sender) = state.waiting.remove(&entry.id) {
+                        let _ = sender.send(outcome);
+                    }
+                }
+                //the entries before the compaction point are only left as a snapshot
+                ReadEntry::Snapshotted(snapshotted) => {
+                    state.kv = snapshotted.snapshot.state();
+                    for id in snapshotted.snapshot.ids.values() {
+                        kv::seen(&mut state.ids, *id);
+                    }
+                    if let Some(id) = snapshotted.snapshot.ids.get(&state.pid) {
+                        state.next_id = state.next_id.max(*id);
+                    }
+                    state.applied = snapshotted.trimmed_idx;
+                    state.compacted = snapshotted.trimmed_idx;
+                    state.compacted_at = Instant::now();
+                    state.bytes = 0;
+                    let keys: Vec<String> = state.kv.data.keys().cloned().collect();
+                    let leases: Vec<u64> = state.kv.leases.keys().cloned().collect();
+                    state.expiring.clear();
+                    state.leases.clear();
+                    state.track(&keys);
+                    for lease in leases {
+                        state.track_lease(lease);
+                    }
+                    state.reset_history(snapshotted.trimmed_idx + 1);
+                }
+                ReadEntry::Trimmed(idx) => {
+                    print_log(format!(
+                        "Entries up to {} are trimmed without snapshot",
+                        idx
+                    ));
+                    state.applied = idx;
+                    state.reset_history(idx + 1);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+//keep applying the decided log until the node stops
+pub(crate) async fn apply_thread(
+    store: &Store,
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    mut stopped: Shutdown,
+) {
+    loop {
+        print_log(format!("-----apply_thread-----"));
+        store.catch_up(op).await;
+        tokio::select! {
+            _ = time::sleep(Duration::from_millis(APPLY_INTERVAL)) => {}
+            _ = stopped.wait() => break,
+        }
+    }
+}
+
+//the leader proposes the expiry of every key and lease whose ttl ran out, so they expire
+//at the same point of the log on every replica whatever their clocks say
+pub(crate) async fn expire_thread(
+    store: &Store,
+    op: &OmniPaxosNode<LogEntry, KVSnapshot>,
+    pid: u64,
+    mut stopped: Shutdown,
+) {
+    loop {
+        tokio::select! {
+            _ = time::sleep(Duration::from_millis(APPLY_INTERVAL)) => {}
+            _ = stopped.wait() => break,
+        }
+        if op.get_current_leader().await != pid {
+            continue;
+        }
+        for command in store.expired() {
+            print_log(format!("{:?} expires", command));
+            let id = store.next_id();
+            let _ = op.append(LogEntry { id, command }).await;
+        }
+    }
+}
@@ -0,0 +1,136 @@
+#![allow(dead_code)]
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::kv::{KeyValue, Record};
+
+//the file formats of an export, one key per line: a json object per line, or csv
+//with a header line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format {}, jsonl or csv", s)),
+        }
+    }
+}
+
+pub(crate) const CSV_HEADER: &str = "key,value,version,create_revision,mod_revision";
+
+//a key of an export, the revisions are only informative, an import writes the values
+//and the log gives them new revisions
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Row {
+    pub key: String,
+    pub value: u64,
+    #[serde(default)]
+    pub version: u64,
+    #[serde(default)]
+    pub create_revision: u64,
+    #[serde(default)]
+    pub mod_revision: u64,
+}
+
+impl Row {
+    pub fn new(key: String, record: &Record) -> Self {
+        Row {
+            key,
+            value: record.value,
+            version: record.version,
+            create_revision: record.create_revision,
+            mod_revision: record.mod_revision,
+        }
+    }
+
+    pub fn kv(self) -> KeyValue {
+        KeyValue {
+            key: self.key,
+            value: self.value,
+        }
+    }
+}
+
+//the line of a row in a format
+pub(crate) fn write(format: Format, row: &Row) -> String {
+    match format {
+        Format::Jsonl => serde_json::to_string(row).unwrap(),
+        Format::Csv => format!(
+            "{},{},{},{},{}",
+            csv_field(&row.key),
+            row.value,
+            row.version,
+            row.create_revision,
+            row.mod_revision
+        ),
+    }
+}
+
+//read a row from its line, a csv row needs the key and the value, the revisions are 0
+//if they are missing
+pub(crate) fn read(format: Format, line: &str) -> Result<Row, String> {
+    match format {
+        Format::Jsonl => serde_json::from_str(line).map_err(|e| e.to_string()),
+        Format::Csv => {
+            let fields = csv_fields(line)?;
+            if fields.len() < 2 || fields.len() > 5 {
+                return Err(format!("expected 2 to 5 fields, got {}", fields.len()));
+            }
+            let mut numbers = vec![];
+            for field in &fields[1..] {
+                let number = field.parse::<u64>();
+                numbers.push(number.map_err(|_| format!("{} is not a number", field))?);
+            }
+            numbers.resize(4, 0);
+            Ok(Row {
+                key: fields[0].clone(),
+                value: numbers[0],
+                version: numbers[1],
+                create_revision: numbers[2],
+                mod_revision: numbers[3],
+            })
+        }
+    }
+}
+
+//quote a field that holds a comma or a quote, a quote inside is doubled
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_fields(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => fields.push(std::mem::take(&mut field)),
+            (_, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
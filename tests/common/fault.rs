@@ -0,0 +1,29 @@
+#![allow(dead_code)]
+use serde::{Deserialize, Serialize};
+
+//faults a node injects into its own network traffic
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FaultConfig {
+    //percentage of outgoing packages that are dropped
+    pub drop: u64,
+    //delay in ms added to every outgoing package
+    pub delay: u64,
+    //percentage of outgoing packages that are sent twice
+    pub duplicate: u64,
+    //percentage of outgoing packages held back, so later packages overtake them
+    pub reorder: u64,
+    //groups of pids that can only reach the pids in the same group,
+    //a pid in no group is isolated, no groups means no partition
+    pub partition: Vec<Vec<u64>>,
+}
+
+impl FaultConfig {
+    //whether a and b can reach each other
+    pub fn connected(&self, a: u64, b: u64) -> bool {
+        self.partition.is_empty()
+            || self
+                .partition
+                .iter()
+                .any(|group| group.contains(&a) && group.contains(&b))
+    }
+}
@@ -1,32 +1,459 @@
+#![allow(dead_code)]
 use omnipaxos_core::storage::Snapshot;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) struct KeyValue {
     pub key: String,
     pub value: u64,
 }
 
+//a key in the key-value store
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Record {
+    pub value: u64,
+    //number of changes since the key was created, 1 after it is created
+    pub version: u64,
+    //the revision of the entry that created the key and of the last one that changed it,
+    //the revision of an entry is its log index plus one
+    pub create_revision: u64,
+    pub mod_revision: u64,
+    //the key expires `ttl` ms after its last change
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    //the key is deleted with the lease it is attached to
+    #[serde(default)]
+    pub lease: Option<u64>,
+}
+
+//a lease expires `ttl` ms after it was granted or kept alive the last time, at `revision`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Lease {
+    pub ttl: u64,
+    pub revision: u64,
+}
+
+//the replicated state, the keys are ordered so they can be listed in ranges,
+//a lease is named after the revision it was granted at
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Store {
+    pub data: BTreeMap<String, Record>,
+    #[serde(default)]
+    pub leases: BTreeMap<u64, Lease>,
+}
+
+impl Store {
+    //delete a lease with the keys attached to it
+    fn revoke(&mut self, lease: u64) {
+        self.leases.remove(&lease);
+        self.data.retain(|_, record| record.lease != Some(lease));
+    }
+}
+
+//what a log entry does to the key-value store
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum Command {
+    //read a key at its place in the log, reads go through the read index now and this
+    //is only applied from logs that still hold one
+    Get {
+        key: String,
+    },
+    Put(KeyValue),
+    //a put that expires `ttl` ms after the last change of the key
+    PutTtl {
+        kv: KeyValue,
+        ttl: u64,
+    },
+    //proposed by the leader once the ttl of the key ran out,
+    //it is dropped if it did not change since `revision`
+    Expire {
+        key: String,
+        revision: u64,
+    },
+    //many puts in one entry
+    MultiPut(Vec<KeyValue>),
+    //create a lease, its id is the index of the entry
+    Grant {
+        ttl: u64,
+    },
+    KeepAlive {
+        lease: u64,
+    },
+    //delete the lease and its keys, the leader proposes it with the revision the lease
+    //expired at and it is dropped if the lease was kept alive since then
+    Revoke {
+        lease: u64,
+        revision: Option<u64>,
+    },
+    //a put of a key attached to a lease
+    PutLease {
+        kv: KeyValue,
+        lease: u64,
+    },
+    //create the key `name` holding the lease if it does not exist, so the lock is
+    //held until it is unlocked or the lease ends
+    Lock {
+        name: String,
+        lease: u64,
+    },
+    Unlock {
+        name: String,
+        lease: u64,
+    },
+    //set the key to `new` if it holds `expected`, None expects the key to be absent
+    Cas {
+        key: String,
+        expected: Option<u64>,
+        new: u64,
+    },
+    //add or subtract `delta`, a missing key counts as 0
+    Incr {
+        key: String,
+        delta: u64,
+    },
+    Decr {
+        key: String,
+        delta: u64,
+    },
+    //apply every mutation if every condition holds, nothing otherwise
+    Txn {
+        conditions: Vec<Condition>,
+        mutations: Vec<Mutation>,
+    },
+}
+
+//a condition of a transaction
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Condition {
+    Equals { key: String, value: u64 },
+    Exists { key: String, exists: bool },
+    //the version of a missing key is 0
+    Version { key: String, version: u64 },
+    //the key was last changed at the revision `revision`, 0 for a missing key, which no
+    //entry has as revisions start at 1
+    ModRevision { key: String, revision: u64 },
+}
+
+//a change made by a transaction
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Mutation {
+    Put(KeyValue),
+    Delete { key: String },
+}
+
+//the result of applying a command
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Outcome {
+    Done,
+    //the compare and swap did not match, with the value the key holds
+    Mismatch(Option<u64>),
+    //the new value of a counter
+    Value(u64),
+    //the counter would overflow or underflow, it keeps the value it holds
+    OutOfRange(u64),
+    //the transaction was not applied, with the index of the first condition that failed
+    Aborted(usize),
+    //the lease does not exist, it expired or was revoked
+    NoLease,
+    //the key read by a Get
+    Record(Option<Record>),
+}
+
+impl Command {
+    //the keys the command can change
+    pub fn keys(&self, store: &Store) -> Vec<String> {
+        match self {
+            Command::Put(kv) | Command::PutTtl { kv, .. } | Command::PutLease { kv, .. } => {
+                vec![kv.key.clone()]
+            }
+            Command::MultiPut(kvs) => kvs.iter().map(|kv| kv.key.clone()).collect(),
+            Command::Expire { key, .. }
+            | Command::Cas { key, .. }
+            | Command::Incr { key, .. }
+            | Command::Decr { key, .. }
+            | Command::Lock { name: key, .. }
+            | Command::Unlock { name: key, .. } => vec![key.clone()],
+            Command::Txn { mutations, .. } => mutations
+                .iter()
+                .map(|m| match m {
+                    Mutation::Put(kv) => kv.key.clone(),
+                    Mutation::Delete { key } => key.clone(),
+                })
+                .collect(),
+            Command::Revoke { lease, .. } => store
+                .data
+                .iter()
+                .filter(|(_, record)| record.lease == Some(*lease))
+                .map(|(key, _)| key.clone())
+                .collect(),
+            Command::Get { .. } | Command::Grant { .. } | Command::KeepAlive { .. } => vec![],
+        }
+    }
+
+    //apply the command of the log entry with the revision `revision`, one past its log
+    //index so that no key has the revision 0 of a missing key, it only depends on the
+    //store and the revision so every replica gets the same outcome
+    pub fn apply(&self, store: &mut Store, revision: u64) -> Outcome {
+        match self {
+            Command::Get { key } => Outcome::Record(store.data.get(key).cloned()),
+            Command::Put(KeyValue { key, value }) => {
+                put(store, key, *value, revision);
+                Outcome::Done
+            }
+            Command::PutTtl {
+                kv: KeyValue { key, value },
+                ttl,
+            } => {
+                put(store, key, *value, revision);
+                store.data.get_mut(key).unwrap().ttl = Some(*ttl);
+                Outcome::Done
+            }
+            Command::Expire { key, revision } => {
+                if store.data.get(key).map(|r| r.mod_revision) == Some(*revision) {
+                    store.data.remove(key);
+                }
+                Outcome::Done
+            }
+            Command::Grant { ttl } => {
+                let lease = Lease {
+                    ttl: *ttl,
+                    revision,
+                };
+                store.leases.insert(revision, lease);
+                Outcome::Value(revision)
+            }
+            Command::KeepAlive { lease } => match store.leases.get_mut(lease) {
+                Some(lease) => {
+                    lease.revision = revision;
+                    Outcome::Done
+                }
+                None => Outcome::NoLease,
+            },
+            Command::Revoke {
+                lease,
+                revision: expired,
+            } => match store.leases.get(lease) {
+                Some(Lease { revision, .. })
+                    if expired.is_none() || *expired == Some(*revision) =>
+                {
+                    store.revoke(*lease);
+                    Outcome::Done
+                }
+                Some(_) => Outcome::Done,
+                None => Outcome::NoLease,
+            },
+            Command::PutLease {
+                kv: KeyValue { key, value },
+                lease,
+            } => {
+                if !store.leases.contains_key(lease) {
+                    return Outcome::NoLease;
+                }
+                put(store, key, *value, revision);
+                store.data.get_mut(key).unwrap().lease = Some(*lease);
+                Outcome::Done
+            }
+            Command::Lock { name, lease } => {
+                if !store.leases.contains_key(lease) {
+                    return Outcome::NoLease;
+                }
+                match store.data.get(name).map(|r| r.value) {
+                    Some(holder) if holder != *lease => Outcome::Mismatch(Some(holder)),
+                    Some(_) => Outcome::Done,
+                    None => {
+                        put(store, name, *lease, revision);
+                        store.data.get_mut(name).unwrap().lease = Some(*lease);
+                        Outcome::Done
+                    }
+                }
+            }
+            Command::Unlock { name, lease } => {
+                let record = store.data.get(name);
+                if record.map(|r| r.lease) == Some(Some(*lease)) {
+                    store.data.remove(name);
+                    Outcome::Done
+                } else {
+                    Outcome::Mismatch(record.map(|r| r.value))
+                }
+            }
+            Command::MultiPut(kvs) => {
+                for KeyValue { key, value } in kvs {
+                    put(store, key, *value, revision);
+                }
+                Outcome::Done
+            }
+            Command::Cas { key, expected, new } => {
+                let current = store.data.get(key).map(|r| r.value);
+                if current == *expected {
+                    put(store, key, *new, revision);
+                    Outcome::Done
+                } else {
+                    Outcome::Mismatch(current)
+                }
+            }
+            Command::Incr { key, delta } => count(store, key, revision, |v| v.checked_add(*delta)),
+            Command::Decr { key, delta } => count(store, key, revision, |v| v.checked_sub(*delta)),
+            Command::Txn {
+                conditions,
+                mutations,
+            } => {
+                if let Some(i) = conditions.iter().position(|c| !c.holds(store)) {
+                    return Outcome::Aborted(i);
+                }
+                for mutation in mutations {
+                    match mutation {
+                        Mutation::Put(KeyValue { key, value }) => put(store, key, *value, revision),
+                        Mutation::Delete { key } => {
+                            store.data.remove(key);
+                        }
+                    }
+                }
+                Outcome::Done
+            }
+        }
+    }
+}
+
+impl Condition {
+    pub fn holds(&self, store: &Store) -> bool {
+        match self {
+            Condition::Equals { key, value } => {
+                store.data.get(key).map(|r| r.value) == Some(*value)
+            }
+            Condition::Exists { key, exists } => store.data.contains_key(key) == *exists,
+            Condition::Version { key, version } => {
+                store.data.get(key).map_or(0, |r| r.version) == *version
+            }
+            Condition::ModRevision { key, revision } => {
+                store.data.get(key).map_or(0, |r| r.mod_revision) == *revision
+            }
+        }
+    }
+}
+
+//write a key, a change clears its ttl and lease unless the command sets them again
+fn put(store: &mut Store, key: &str, value: u64, revision: u64) {
+    let record = match store.data.get(key) {
+        Some(record) => Record {
+            value,
+            version: record.version + 1,
+            create_revision: record.create_revision,
+            mod_revision: revision,
+            ttl: None,
+            lease: None,
+        },
+        None => Record {
+            value,
+            version: 1,
+            create_revision: revision,
+            mod_revision: revision,
+            ttl: None,
+            lease: None,
+        },
+    };
+    store.data.insert(key.to_string(), record);
+}
+
+//update a counter unless the new value is out of the range of u64
+fn count(store: &mut Store, key: &str, revision: u64, f: impl Fn(u64) -> Option<u64>) -> Outcome {
+    let current = store.data.get(key).map_or(0, |r| r.value);
+    match f(current) {
+        Some(value) => {
+            put(store, key, value, revision);
+            Outcome::Value(value)
+        }
+        None => Outcome::OutOfRange(current),
+    }
+}
+
+//the bits of a proposal id left to the counter of the node, the pid is above them, so
+//no two nodes propose the same id and a pid has to fit in the bits above them
+pub(crate) const ID_BITS: u32 = 48;
+
+//note the id of an entry in the highest ids by pid
+pub(crate) fn seen(ids: &mut BTreeMap<u64, u64>, id: u64) {
+    let last = ids.entry(id >> ID_BITS).or_default();
+    *last = (*last).max(id);
+}
+
+//an entry of the replicated log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct LogEntry {
+    //unique id of the proposal, so the node that proposed it can find its outcome
+    pub id: u64,
+    pub command: Command,
+}
+
+//a snapshot of the entries [0, len) of the log: `snapshotted` is the state after the
+//first `len - delta.len()` of them and `delta` holds the rest, applied in log order.
+//Commands can depend on the state before them, so a snapshot created from a slice of
+//the log keeps them all in the delta, unresolved, until it is merged behind the
+//snapshot of its prefix, which also gives them their log index. Only a merge resolves
+//commands, so a snapshot with a resolved state always starts at index 0.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct KVSnapshot {
-    pub snapshotted: HashMap<String, u64>,
+    pub snapshotted: Store,
+    #[serde(default)]
+    pub delta: Vec<Command>,
+    #[serde(default)]
+    pub len: u64,
+    //the highest proposal id of every node in the entries, by pid, so a node that
+    //restarts from the snapshot goes on after its ids
+    #[serde(default)]
+    pub ids: BTreeMap<u64, u64>,
+}
+
+impl KVSnapshot {
+    //the key-value store the snapshot stands for
+    pub fn state(&self) -> Store {
+        let mut state = self.snapshotted.clone();
+        let start = self.len - self.delta.len() as u64;
+        for (i, command) in self.delta.iter().enumerate() {
+            command.apply(&mut state, start + i as u64 + 1);
+        }
+        state
+    }
 }
 
-impl Snapshot<KeyValue> for KVSnapshot {
-    fn create(entries: &[KeyValue]) -> Self {
-        let mut snapshotted = HashMap::new();
-        for e in entries {
-            let KeyValue { key, value } = e;
-            snapshotted.insert(key.clone(), *value);
+impl Snapshot<LogEntry> for KVSnapshot {
+    fn create(entries: &[LogEntry]) -> Self {
+        let mut ids = BTreeMap::new();
+        for entry in entries {
+            seen(&mut ids, entry.id);
+        }
+        Self {
+            snapshotted: Store::default(),
+            delta: entries.iter().map(|e| e.command.clone()).collect(),
+            len: entries.len() as u64,
+            ids,
         }
-        Self { snapshotted }
     }
 
+    //append the slice [len, len + delta.len) of the log; a delta with a resolved state
+    //covers the log from index 0 itself, it replaces this snapshot if it reaches as
+    //far, so keys it deleted stay deleted instead of being merged back from this one
     fn merge(&mut self, delta: Self) {
-        for (k, v) in delta.snapshotted {
-            self.snapshotted.insert(k, v);
+        if delta.delta.len() as u64 != delta.len {
+            if delta.len >= self.len {
+                let ids = std::mem::take(&mut self.ids);
+                *self = delta;
+                for id in ids.into_values() {
+                    seen(&mut self.ids, id);
+                }
+            }
+            return;
+        }
+        let mut state = self.state();
+        for (i, command) in delta.delta.iter().enumerate() {
+            command.apply(&mut state, self.len + i as u64 + 1);
+        }
+        for id in delta.ids.into_values() {
+            seen(&mut self.ids, id);
         }
+        self.snapshotted = state;
+        self.delta.clear();
+        self.len += delta.len;
     }
 
     fn use_snapshots() -> bool {
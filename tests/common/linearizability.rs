@@ -0,0 +1,189 @@
+#![allow(dead_code)]
+use std::collections::{HashMap, HashSet};
+
+//an operation on a single key
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Op {
+    Get,
+    Put(u64),
+    Cas { expected: Option<u64>, new: u64 },
+    Delete,
+}
+
+//what the cluster answered
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Ret {
+    Value(Option<u64>),
+    Done,
+    Swapped(bool),
+}
+
+//one operation of a history, `ret` is None and `complete` is usize::MAX when the
+//outcome is unknown (timed out or failed), then it may or may not have taken effect
+#[derive(Clone, Debug)]
+pub(crate) struct Event {
+    pub client: u64,
+    pub key: String,
+    pub op: Op,
+    pub ret: Option<Ret>,
+    pub invoke: usize,
+    pub complete: usize,
+}
+
+//the register model: the state after applying `op` that answered `ret`,
+//None if the register could not have given that answer
+fn step(state: Option<u64>, op: &Op, ret: &Option<Ret>) -> Option<Option<u64>> {
+    match (op, ret) {
+        (Op::Get, Some(Ret::Value(v))) if *v == state => Some(state),
+        (Op::Get, None) => Some(state),
+        (Op::Put(v), Some(Ret::Done) | None) => Some(Some(*v)),
+        (Op::Delete, Some(Ret::Done) | None) => Some(None),
+        (Op::Cas { expected, new }, Some(Ret::Swapped(true)) | None) if state == *expected => {
+            Some(Some(*new))
+        }
+        (Op::Cas { expected, .. }, Some(Ret::Swapped(false))) if state != *expected => Some(state),
+        _ => None,
+    }
+}
+
+//check a history for linearizability, keys are independent registers so every key
+//is checked on its own, returns the first key without a valid linearization
+pub(crate) fn check(history: &[Event]) -> Result<(), String> {
+    let mut keys: HashMap<&str, Vec<Event>> = HashMap::new();
+    for event in history {
+        keys.entry(&event.key).or_default().push(event.clone());
+    }
+    let mut keys: Vec<_> = keys.into_iter().collect();
+    keys.sort_by(|a, b| a.0.cmp(b.0));
+    for (key, events) in keys {
+        let mut done = vec![false; events.len()];
+        if !search(&events, &mut done, None, &mut HashSet::new()) {
+            return Err(key.to_string());
+        }
+    }
+    Ok(())
+}
+
+//depth first search for a linearization (Wing & Gong), memoizing the visited
+//(linearized operations, register state) pairs like Porcupine does
+fn search(
+    events: &[Event],
+    done: &mut Vec<bool>,
+    state: Option<u64>,
+    visited: &mut HashSet<(Vec<bool>, Option<u64>)>,
+) -> bool {
+    if events
+        .iter()
+        .zip(done.iter())
+        .all(|(e, d)| *d || e.ret.is_none())
+    {
+        return true;
+    }
+    if !visited.insert((done.clone(), state)) {
+        return false;
+    }
+    //an operation can take effect next only if it was invoked before
+    //every operation that is not linearized yet has returned
+    let horizon = events
+        .iter()
+        .zip(done.iter())
+        .filter(|(_, d)| !**d)
+        .map(|(e, _)| e.complete)
+        .min()
+        .unwrap();
+    for i in 0..events.len() {
+        if done[i] || events[i].invoke > horizon {
+            continue;
+        }
+        if let Some(next) = step(state, &events[i].op, &events[i].ret) {
+            done[i] = true;
+            if search(events, done, next, visited) {
+                return true;
+            }
+            done[i] = false;
+        }
+    }
+    false
+}
+
+//rebuild the history from the trace of the simulation mode, where every client
+//logs "clientN invokes <op> <key> <value> on <pid>" and then
+//"clientN returns <reply>" or "clientN timed out"
+pub(crate) fn parse_history(trace: &[String]) -> Vec<Event> {
+    let mut pending: HashMap<u64, Event> = HashMap::new();
+    let mut history = vec![];
+    for (time, line) in trace.iter().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let position = match words.iter().position(|w| w.starts_with("client")) {
+            Some(p) if p + 1 < words.len() => p,
+            _ => continue,
+        };
+        let client = match words[position].trim_start_matches("client").parse::<u64>() {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        let rest = &words[position + 1..];
+        match rest[0] {
+            "invokes" => {
+                //a compare and swap is logged as "Cas { expected: <Some(v)|None> } <key> <new>"
+                let (op, key) = match rest[1] {
+                    "Get" => (Op::Get, rest[2]),
+                    "Put" => (Op::Put(rest[3].parse().unwrap()), rest[2]),
+                    "Cas" => {
+                        let expected = rest[4]
+                            .strip_prefix("Some(")
+                            .map(|v| v.trim_end_matches(')').parse().unwrap());
+                        let new = rest[7].parse().unwrap();
+                        (Op::Cas { expected, new }, rest[6])
+                    }
+                    _ => continue,
+                };
+                let event = Event {
+                    client,
+                    key: key.to_string(),
+                    op,
+                    ret: None,
+                    invoke: time,
+                    complete: usize::MAX,
+                };
+                pending.insert(client, event);
+            }
+            "returns" => {
+                if let Some(mut event) = pending.remove(&client) {
+                    let reply = rest[1..].join(" ");
+                    event.ret = parse_reply(&reply);
+                    if event.ret.is_some() {
+                        event.complete = time;
+                    }
+                    history.push(event);
+                }
+            }
+            "timed" => {
+                if let Some(event) = pending.remove(&client) {
+                    history.push(event);
+                }
+            }
+            _ => {}
+        }
+    }
+    history.extend(pending.into_values());
+    history
+}
+
+fn parse_reply(reply: &str) -> Option<Ret> {
+    if let Some(value) = reply.strip_prefix("This value is : ") {
+        //the value is followed by its version and revisions
+        let value = value.split_whitespace().next().unwrap();
+        Some(Ret::Value(Some(value.parse().unwrap())))
+    } else if reply.starts_with("No value about the key") {
+        Some(Ret::Value(None))
+    } else if reply.starts_with("Successfully to put value") {
+        Some(Ret::Done)
+    } else if reply.starts_with("Successfully to compare and swap") {
+        Some(Ret::Swapped(true))
+    } else if reply.starts_with("Failed to compare and swap, ") {
+        Some(Ret::Swapped(false))
+    } else {
+        None
+    }
+}
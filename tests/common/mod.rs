@@ -1,4 +1,12 @@
+pub(crate) mod export;
+pub(crate) mod fault;
 pub(crate) mod kv;
+pub(crate) mod linearizability;
+pub(crate) mod lsm;
 pub(crate) mod msg;
 pub(crate) mod node;
 pub(crate) mod package;
+pub(crate) mod persist;
+pub(crate) mod simulation;
+pub(crate) mod storage;
+pub(crate) mod wal;
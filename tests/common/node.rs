@@ -1,6 +1,10 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
+use super::storage::Engine;
+
 #[derive(Debug, StructOpt, Serialize, Deserialize)]
 pub(crate) struct Node {
     #[structopt(long)]
@@ -8,4 +12,43 @@ pub(crate) struct Node {
 
     #[structopt(long)]
     pub peers: Vec<u64>,
+
+    //hand the leadership to a peer when shutting down
+    #[structopt(long)]
+    pub handover: bool,
+
+    //simulate the node and its peers in one process with this seed
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    //the simulated scenario: history, concurrent clients while the nodes restart from the
+    //data directory, a random workload if not given
+    #[structopt(long)]
+    pub scenario: Option<String>,
+
+    //compact the log after this many entries, bytes or ms since the last snapshot,
+    //the values of configs/server.rs if not given
+    #[structopt(long)]
+    pub snapshot_entries: Option<u64>,
+
+    #[structopt(long)]
+    pub snapshot_bytes: Option<u64>,
+
+    #[structopt(long)]
+    pub snapshot_interval: Option<u64>,
+
+    //keep the snapshots of the node in this directory and restore the last one on start,
+    //nothing is kept on disk if not given
+    #[structopt(long, parse(from_os_str))]
+    pub data_dir: Option<PathBuf>,
+
+    //keep the log in memory, file or lsm storage, file and lsm need a data directory;
+    //file if there is a data directory and memory otherwise if not given
+    #[structopt(long)]
+    pub storage: Option<Engine>,
+
+    //bootstrap a fresh cluster from this backup: the snapshot in it is written to the
+    //data directory, which must not hold a snapshot yet, before the node starts
+    #[structopt(long, parse(from_os_str))]
+    pub restore: Option<PathBuf>,
 }
@@ -7,6 +7,12 @@ pub(crate) enum Types {
     BLE,
     SP,
     CMD,
+    //the chunks of a large SP message and their acks
+    Chunk,
+    //the read index of a linearizable read and its acks
+    Read,
+    //a leader transfer handed to its target, only a peer sends it
+    Elect,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -0,0 +1,30 @@
+#![allow(dead_code)]
+use std::path::Path;
+use std::process::Command;
+
+//run a three node cluster in simulation mode and return its trace, without a scenario
+//it runs a random workload; the nodes keep their storage in the data directory if given
+pub(crate) fn simulate(scenario: Option<&str>, seed: u64, data_dir: Option<&Path>) -> Vec<String> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_server"));
+    let seed = seed.to_string();
+    command.args(["--pid", "1", "--peers", "2", "3", "--seed", &seed]);
+    if let Some(scenario) = scenario {
+        command.args(["--scenario", scenario]);
+    }
+    if let Some(dir) = data_dir {
+        command.arg("--data-dir").arg(dir);
+    }
+    let output = command.output().expect("Failed to run the simulation");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+        .lines()
+        .filter(|line| line.starts_with("sim "))
+        .map(|line| line.to_string())
+        .collect()
+}
@@ -0,0 +1,48 @@
+#[allow(dead_code)]
+mod common;
+use common::export::{read, write, Format, Row, CSV_HEADER};
+
+fn row(key: &str) -> Row {
+    Row {
+        key: key.to_string(),
+        value: 7,
+        version: 2,
+        create_revision: 3,
+        mod_revision: 5,
+    }
+}
+
+//Test 1: A row reads back the same from both formats, also with a key csv has to quote.
+#[test]
+fn round_trip() {
+    for key in ["user/1", "a,b", "say \"hi\"", ""] {
+        for format in [Format::Jsonl, Format::Csv] {
+            let line = write(format, &row(key));
+            assert_eq!(read(format, &line), Ok(row(key)), "{}", line);
+        }
+    }
+    assert_eq!(write(Format::Csv, &row("user/1")), "user/1,7,2,3,5");
+    assert_eq!(write(Format::Csv, &row("a,b")), "\"a,b\",7,2,3,5");
+    assert_eq!(CSV_HEADER.split(',').count(), 5);
+}
+
+//Test 2: A hand written file only needs the keys and the values.
+#[test]
+fn keys_and_values() {
+    let csv = read(Format::Csv, "A,10").unwrap();
+    let jsonl = read(Format::Jsonl, r#"{"key":"A","value":10}"#).unwrap();
+    assert_eq!(csv, jsonl);
+    assert_eq!((csv.value, csv.version, csv.mod_revision), (10, 0, 0));
+}
+
+//Test 3: A malformed line is reported instead of being imported.
+#[test]
+fn malformed() {
+    assert!(read(Format::Csv, "A").is_err());
+    assert!(read(Format::Csv, "A,ten").is_err());
+    assert!(read(Format::Csv, "\"A,10").is_err());
+    assert!(read(Format::Csv, "A,1,2,3,4,5").is_err());
+    assert!(read(Format::Jsonl, "A,10").is_err());
+    assert!("xml".parse::<Format>().is_err());
+    assert_eq!("csv".parse::<Format>(), Ok(Format::Csv));
+}
@@ -0,0 +1,122 @@
+#![cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[allow(dead_code)]
+mod common;
+use common::fault::FaultConfig;
+use common::kv::KeyValue;
+use common::msg::{CMDMessage, Msg, Operation};
+use common::package::{Package, Types};
+
+const CLIENT: &str = "127.0.0.1:12345";
+
+//a server process, killed if a test fails before it is stopped
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+//start a node of a cluster with `peers`, it keeps nothing on disk
+fn start(pid: u64, peers: &[u64]) -> Server {
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--pid", &pid.to_string(), "--peers"])
+            .args(peers.iter().map(|p| p.to_string()))
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start the server"),
+    );
+    for _ in 0..100 {
+        if TcpStream::connect(addr(pid)).is_ok() {
+            return server;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("Server did not start listening");
+}
+
+fn addr(pid: u64) -> String {
+    format!("127.0.0.1:{}", 11000 + pid)
+}
+
+//send a command to the node and wait for its reply
+fn request(
+    listener: &TcpListener,
+    pid: u64,
+    operation: Operation,
+    key: &str,
+    value: u64,
+) -> String {
+    let pkg = Package {
+        types: Types::CMD,
+        msg: Msg::CMD(CMDMessage {
+            operation,
+            kv: KeyValue {
+                key: key.to_string(),
+                value,
+            },
+        }),
+    };
+    let mut stream = TcpStream::connect(addr(pid)).unwrap();
+    stream
+        .write_all(serde_json::to_string(&pkg).unwrap().as_bytes())
+        .unwrap();
+    drop(stream);
+    let (socket, _) = listener.accept().unwrap();
+    let mut reply = String::new();
+    BufReader::new(socket).read_line(&mut reply).unwrap();
+    reply
+}
+
+//Test 1: A fault config sent to a running node over tcp applies to the traffic of that
+//node: once it drops every package it sends, it can not read at a read index any more,
+//and once the faults are cleared it reads again.
+#[test]
+fn fault_over_tcp() {
+    let listener = TcpListener::bind(CLIENT).unwrap();
+    let _a = start(81, &[82]);
+    let _b = start(82, &[81]);
+    let mut leader = String::new();
+    for _ in 0..100 {
+        leader = request(&listener, 81, Operation::Leader, "", 0);
+        if leader != "Leader is : 0" {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_ne!(leader, "Leader is : 0");
+    let reply = request(&listener, 81, Operation::Put, "key", 1);
+    assert_eq!(reply, "Successfully to put value");
+
+    let drop_all = FaultConfig {
+        drop: 100,
+        ..FaultConfig::default()
+    };
+    let reply = request(&listener, 81, Operation::Fault(drop_all), "", 0);
+    assert_eq!(reply, "Successfully to inject faults");
+    let reply = request(&listener, 81, Operation::Get, "key", 0);
+    assert_eq!(reply, "Failed to get");
+
+    let reply = request(
+        &listener,
+        81,
+        Operation::Fault(FaultConfig::default()),
+        "",
+        0,
+    );
+    assert_eq!(reply, "Successfully to inject faults");
+    let reply = request(&listener, 81, Operation::Get, "key", 0);
+    assert!(
+        reply.starts_with("This value is : 1 (version 1"),
+        "{}",
+        reply
+    );
+}
@@ -0,0 +1,121 @@
+#[allow(dead_code)]
+mod common;
+use common::kv::{Command, KeyValue, Mutation, Store};
+
+fn kv(key: &str, value: u64) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value,
+    }
+}
+
+fn apply(commands: &[Command]) -> Store {
+    let mut store = Store::default();
+    for (i, command) in commands.iter().enumerate() {
+        command.apply(&mut store, i as u64 + 1);
+    }
+    store
+}
+
+//a lease granted at revision 1, then `a` with a ttl and `b` attached to the lease
+fn leased() -> Vec<Command> {
+    vec![
+        Command::Grant { ttl: 1000 },
+        Command::PutTtl {
+            kv: kv("a", 1),
+            ttl: 1000,
+        },
+        Command::PutLease {
+            kv: kv("b", 1),
+            lease: 1,
+        },
+    ]
+}
+
+//Test 1: Every command that overwrites a key clears its ttl and its lease, so the key
+//does not expire and is not deleted with the lease afterwards.
+#[test]
+fn overwrite_clears_ttl_and_lease() {
+    let overwrites = vec![
+        vec![Command::Put(kv("a", 2)), Command::Put(kv("b", 2))],
+        vec![Command::MultiPut(vec![kv("a", 2), kv("b", 2)])],
+        vec![
+            Command::Cas {
+                key: String::from("a"),
+                expected: Some(1),
+                new: 2,
+            },
+            Command::Cas {
+                key: String::from("b"),
+                expected: Some(1),
+                new: 2,
+            },
+        ],
+        vec![
+            Command::Incr {
+                key: String::from("a"),
+                delta: 1,
+            },
+            Command::Incr {
+                key: String::from("b"),
+                delta: 1,
+            },
+        ],
+        vec![
+            Command::Decr {
+                key: String::from("a"),
+                delta: 1,
+            },
+            Command::Decr {
+                key: String::from("b"),
+                delta: 1,
+            },
+        ],
+        vec![Command::Txn {
+            conditions: vec![],
+            mutations: vec![Mutation::Put(kv("a", 2)), Mutation::Put(kv("b", 2))],
+        }],
+    ];
+    for overwrite in overwrites {
+        let mut log = leased();
+        log.extend(overwrite.clone());
+        let mut store = apply(&log);
+        for key in ["a", "b"] {
+            let record = &store.data[key];
+            assert_eq!((record.ttl, record.lease), (None, None), "{:?}", overwrite);
+        }
+
+        //neither the expiry of the old ttl nor the end of the lease deletes them
+        let revision = store.data["a"].mod_revision;
+        let expire = Command::Expire {
+            key: String::from("a"),
+            revision: 2,
+        };
+        expire.apply(&mut store, log.len() as u64 + 1);
+        assert_eq!(store.data["a"].mod_revision, revision);
+        let revoke = Command::Revoke {
+            lease: 1,
+            revision: None,
+        };
+        revoke.apply(&mut store, log.len() as u64 + 2);
+        assert!(store.data.contains_key("b"), "{:?}", overwrite);
+    }
+}
+
+//Test 2: A command that sets the ttl or the lease again keeps it after the overwrite.
+#[test]
+fn overwrite_sets_ttl_and_lease_again() {
+    let mut log = leased();
+    log.push(Command::PutTtl {
+        kv: kv("b", 2),
+        ttl: 500,
+    });
+    log.push(Command::PutLease {
+        kv: kv("a", 2),
+        lease: 1,
+    });
+    let store = apply(&log);
+    let (a, b) = (&store.data["a"], &store.data["b"]);
+    assert_eq!((a.ttl, a.lease), (None, Some(1)));
+    assert_eq!((b.ttl, b.lease), (Some(500), None));
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use omnipaxos_core::ballot_leader_election::Ballot;
+use omnipaxos_core::storage::Storage;
+
+#[allow(dead_code)]
+mod common;
+use common::kv::{self, KVSnapshot, KeyValue, LogEntry, Record, Store};
+use common::lsm::LsmStorage;
+use common::persist::{encode, encode_backup, Backup};
+use common::storage::StorageOptions;
+use common::wal::WalStorage;
+
+fn snapshot() -> KVSnapshot {
+    let mut store = Store::default();
+    for (i, key) in ["a", "user/1", "user/2"].iter().enumerate() {
+        let record = Record {
+            value: i as u64 + 1,
+            version: 1,
+            create_revision: i as u64,
+            mod_revision: i as u64,
+            ttl: None,
+            lease: None,
+        };
+        store.data.insert(key.to_string(), record);
+    }
+    KVSnapshot {
+        snapshotted: store,
+        delta: vec![],
+        len: 3,
+        ids: Default::default(),
+    }
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn inspect(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_kvctl"))
+        .arg("inspect")
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+//Test 1: The snapshots of a data directory are printed with their header and keys.
+#[test]
+fn data_directory() {
+    let dir = data_dir("kvctl-dir");
+    fs::write(dir.join("node1.snap"), encode(&snapshot())).unwrap();
+    fs::write(dir.join("node2.snap"), encode(&snapshot())).unwrap();
+    let (ok, output) = inspect(&[dir.to_str().unwrap()]);
+    assert!(ok, "{}", output);
+    assert_eq!(output.matches("snapshot: version 1, index 3").count(), 2);
+    assert!(output.contains("keys at index 3: 3"), "{}", output);
+    let record = "user/2 = 3 (version 1, create revision 2, mod revision 2)";
+    assert!(output.contains(record), "{}", output);
+
+    let (_, output) = inspect(&[dir.to_str().unwrap(), "--pid", "2"]);
+    assert!(!output.contains("node1.snap"), "{}", output);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 2: Only the keys that match the key, the prefix and the index range are printed.
+#[test]
+fn filters() {
+    let dir = data_dir("kvctl-filter");
+    let file = dir.join("node1.snap");
+    fs::write(&file, encode(&snapshot())).unwrap();
+    let path = file.to_str().unwrap();
+    let (_, output) = inspect(&[path, "--prefix", "user/", "--to", "1"]);
+    assert!(output.contains("  user/1 = 2"), "{}", output);
+    assert!(!output.contains("  user/2 = 3"), "{}", output);
+    assert!(!output.contains("  a = 1"), "{}", output);
+    let (_, output) = inspect(&[path, "--key", "a"]);
+    assert!(output.contains("  a = 1"), "{}", output);
+    assert!(!output.contains("  user/1"), "{}", output);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 3: A backup shows where it was taken, a corrupted file is reported.
+#[test]
+fn backup_and_corruption() {
+    let dir = data_dir("kvctl-backup");
+    let backup = Backup {
+        cluster: vec![1, 2, 3],
+        pid: 2,
+        index: 3,
+        created: 0,
+        snapshot: snapshot(),
+    };
+    let file = dir.join("kv.backup");
+    fs::write(&file, encode_backup(&backup)).unwrap();
+    let (ok, output) = inspect(&[file.to_str().unwrap()]);
+    assert!(ok, "{}", output);
+    assert!(output.contains("taken on node 2 of cluster [1, 2, 3]"));
+
+    let mut bytes = encode(&snapshot());
+    let last = bytes.len() - 2;
+    bytes[last] ^= 1;
+    fs::write(dir.join("node1.snap"), bytes).unwrap();
+    let (ok, output) = inspect(&[dir.to_str().unwrap()]);
+    assert!(!ok);
+    assert!(output.contains("Checksum"), "{}", output);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 4: The log of a node is printed with its ballots and which entries are decided.
+#[test]
+fn write_ahead_log() {
+    let dir = data_dir("kvctl-wal");
+    let (mut storage, _) = WalStorage::open(&dir.join("node1.wal"), 1 << 20).unwrap();
+    let entries = ["a", "b", "c"]
+        .iter()
+        .enumerate()
+        .map(|(id, key)| LogEntry {
+            id: id as u64,
+            command: kv::Command::Put(KeyValue {
+                key: key.to_string(),
+                value: id as u64,
+            }),
+        });
+    let ballot = Ballot {
+        n: 3,
+        priority: 0,
+        pid: 2,
+    };
+    storage.set_promise(ballot);
+    storage.append_entries(entries.collect());
+    storage.set_decided_idx(2);
+    drop(storage);
+
+    let (ok, output) = inspect(&[dir.to_str().unwrap()]);
+    assert!(ok, "{}", output);
+    assert!(
+        output.contains("log: 3 records in segments [1]"),
+        "{}",
+        output
+    );
+    assert!(output.contains("decided up to index 2"), "{}", output);
+    assert!(output.contains("  1 (decided): Put"), "{}", output);
+    assert!(output.contains("  2 (accepted): Put"), "{}", output);
+    let (_, output) = inspect(&[dir.to_str().unwrap(), "--key", "c"]);
+    assert!(!output.contains("  1 (decided)"), "{}", output);
+    assert!(output.contains("  2 (accepted)"), "{}", output);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 5: The lsm storage of a node is listed in its data directory and printed with its
+//runs and the entries of its write-ahead log after them at their index in the log.
+#[test]
+fn lsm_storage() {
+    let dir = data_dir("kvctl-lsm");
+    let options = StorageOptions {
+        segment_bytes: 1 << 20,
+        memtable_entries: 4,
+        max_runs: 4,
+    };
+    let (mut storage, _) = LsmStorage::open(&dir.join("node1.lsm"), &options).unwrap();
+    let entry = |id: u64| LogEntry {
+        id,
+        command: kv::Command::Put(KeyValue {
+            key: format!("key{}", id),
+            value: id,
+        }),
+    };
+    //the first entries fill the memtable and go to a run, the last one stays in the log
+    storage.append_entries((0..6).map(entry).collect());
+    storage.append_entry(entry(6));
+    storage.set_decided_idx(5);
+    drop(storage);
+
+    let (ok, output) = inspect(&[dir.to_str().unwrap(), "--pid", "1"]);
+    assert!(ok, "{}", output);
+    assert!(output.contains("node1.lsm"), "{}", output);
+    assert!(output.contains("runs: 1"), "{}", output);
+    assert!(output.contains("run 1: 6 entries, log [0, 6)"), "{}", output);
+    assert!(output.contains("  6: Put"), "{}", output);
+    assert!(output.contains("  decided up to index 5"), "{}", output);
+    let (_, output) = inspect(&[dir.to_str().unwrap(), "--key", "key6"]);
+    assert!(output.contains("  6: Put"), "{}", output);
+    assert!(!output.contains("  5: Put"), "{}", output);
+    let _ = fs::remove_dir_all(&dir);
+}
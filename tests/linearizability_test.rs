@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::PathBuf;
+
+#[allow(dead_code)]
+mod common;
+use common::linearizability::{check, parse_history, Event, Op, Ret};
+use common::simulation::simulate;
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn event(client: u64, op: Op, ret: Option<Ret>, invoke: usize, complete: usize) -> Event {
+    Event {
+        client,
+        key: String::from("key"),
+        op,
+        ret,
+        invoke,
+        complete,
+    }
+}
+
+//Test 1: Sequential histories follow the register.
+#[test]
+fn sequential_history() {
+    let history = vec![
+        event(1, Op::Get, Some(Ret::Value(None)), 0, 1),
+        event(1, Op::Put(1), Some(Ret::Done), 2, 3),
+        event(1, Op::Get, Some(Ret::Value(Some(1))), 4, 5),
+        event(1, Op::Cas { expected: Some(1), new: 2 }, Some(Ret::Swapped(true)), 6, 7),
+        event(1, Op::Cas { expected: None, new: 3 }, Some(Ret::Swapped(false)), 8, 9),
+        event(1, Op::Delete, Some(Ret::Done), 10, 11),
+        event(1, Op::Get, Some(Ret::Value(None)), 12, 13),
+    ];
+    assert_eq!(check(&history), Ok(()));
+}
+
+//Test 2: Concurrent operations may take effect in any order.
+#[test]
+fn concurrent_history() {
+    let history = vec![
+        event(1, Op::Put(1), Some(Ret::Done), 0, 5),
+        event(2, Op::Put(2), Some(Ret::Done), 1, 4),
+        event(3, Op::Get, Some(Ret::Value(Some(1))), 2, 3),
+        event(3, Op::Get, Some(Ret::Value(Some(2))), 6, 7),
+    ];
+    assert_eq!(check(&history), Ok(()));
+}
+
+//Test 3: A read that misses a write which returned before it started is a stale read.
+#[test]
+fn stale_read() {
+    let history = vec![
+        event(1, Op::Put(1), Some(Ret::Done), 0, 1),
+        event(2, Op::Get, Some(Ret::Value(None)), 2, 3),
+    ];
+    assert_eq!(check(&history), Err(String::from("key")));
+}
+
+//Test 4: Once a read saw a new value, a later read can not see the old one.
+#[test]
+fn value_goes_back_in_time() {
+    let history = vec![
+        event(1, Op::Put(1), Some(Ret::Done), 0, 10),
+        event(2, Op::Get, Some(Ret::Value(Some(1))), 1, 2),
+        event(3, Op::Get, Some(Ret::Value(None)), 3, 4),
+    ];
+    assert!(check(&history).is_err());
+}
+
+//Test 5: A write with an unknown outcome may or may not have taken effect.
+#[test]
+fn unknown_outcome() {
+    let applied = vec![
+        event(1, Op::Put(1), None, 0, usize::MAX),
+        event(2, Op::Get, Some(Ret::Value(Some(1))), 5, 6),
+    ];
+    let lost = vec![
+        event(1, Op::Put(1), None, 0, usize::MAX),
+        event(2, Op::Get, Some(Ret::Value(None)), 5, 6),
+    ];
+    assert_eq!(check(&applied), Ok(()));
+    assert_eq!(check(&lost), Ok(()));
+}
+
+//Test 6: The history is rebuilt from the trace of the simulation mode.
+#[test]
+fn history_from_trace() {
+    let trace: Vec<String> = [
+        "sim        0ms client1 invokes Put key 7 on 1",
+        "sim        1ms client2 invokes Get key 0 on 2",
+        "sim        3ms 1 -> client1 Successfully to put value",
+        "sim        3ms client1 returns Successfully to put value",
+        "sim        5ms client2 returns This value is : 7",
+        "sim        6ms client1 invokes Put key 8 on 1",
+        "sim        9ms client1 timed out",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let history = parse_history(&trace);
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[0].op, Op::Put(7));
+    assert_eq!(history[0].complete, 3);
+    assert_eq!(history[1].ret, Some(Ret::Value(Some(7))));
+    assert_eq!(history[2].ret, None);
+    assert_eq!(check(&history), Ok(()));
+}
+
+//Test 7: Compare and swaps are rebuilt from the trace with their outcome.
+#[test]
+fn cas_from_trace() {
+    let trace: Vec<String> = [
+        "sim        0ms client1 invokes Cas { expected: None } key 1 on 1",
+        "sim        2ms client1 returns Successfully to compare and swap",
+        "sim        3ms client2 invokes Cas { expected: Some(2) } key 3 on 2",
+        "sim        5ms client2 returns Failed to compare and swap, this value is : 1",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    let history = parse_history(&trace);
+    let expected = Some(2);
+    assert_eq!(history[0].op, Op::Cas { expected: None, new: 1 });
+    assert_eq!(history[1].op, Op::Cas { expected, new: 3 });
+    assert_eq!(history[1].ret, Some(Ret::Swapped(false)));
+    assert_eq!(check(&history), Ok(()));
+}
+
+//Test 8: Concurrent clients on a three node cluster whose nodes restart from their
+//storage. Gets go through the log like the writes, so a node that is behind can not
+//answer them with a stale value.
+#[test]
+fn cluster_is_linearizable() {
+    for seed in 1..6 {
+        let dir = data_dir(&format!("history-{}", seed));
+        let history = parse_history(&simulate(Some("history"), seed, Some(&dir)));
+        let _ = fs::remove_dir_all(&dir);
+        assert!(!history.is_empty());
+        assert_eq!(check(&history), Ok(()), "seed {} is not linearizable", seed);
+    }
+}
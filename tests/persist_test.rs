@@ -0,0 +1,87 @@
+#[allow(dead_code)]
+mod common;
+use common::kv::{KVSnapshot, Record, Store};
+use common::persist::{
+    crc32, decode, decode_backup, encode, encode_backup, header, Backup, FileErr,
+};
+
+fn snapshot() -> KVSnapshot {
+    let mut store = Store::default();
+    let record = Record {
+        value: 7,
+        version: 1,
+        create_revision: 0,
+        mod_revision: 0,
+        ttl: None,
+        lease: None,
+    };
+    store.data.insert(String::from("key"), record);
+    KVSnapshot {
+        snapshotted: store,
+        delta: vec![],
+        len: 1,
+        ids: Default::default(),
+    }
+}
+
+//Test 1: The checksum is the standard crc-32.
+#[test]
+fn checksum() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+//Test 2: A snapshot reads back the same, with its index in the header.
+#[test]
+fn round_trip() {
+    let bytes = encode(&snapshot());
+    assert_eq!(header(&bytes).unwrap().0.index, 1);
+    let restored = decode(&bytes).unwrap();
+    assert_eq!(restored.state(), snapshot().state());
+    assert_eq!(restored.len, 1);
+}
+
+//Test 3: A flipped byte, a torn write and another format version are all detected.
+#[test]
+fn corruption() {
+    let bytes = encode(&snapshot());
+    let mut flipped = bytes.clone();
+    let last = flipped.len() - 2;
+    flipped[last] ^= 1;
+    assert_eq!(decode(&flipped).err(), Some(FileErr::Checksum));
+    assert_eq!(
+        decode(&bytes[..bytes.len() - 1]).err(),
+        Some(FileErr::Length)
+    );
+    let text = String::from_utf8(bytes).unwrap();
+    let other = text.replacen("KVSNAP 1 ", "KVSNAP 2 ", 1);
+    assert_eq!(decode(other.as_bytes()).err(), Some(FileErr::Version(2)));
+    assert_eq!(decode(b"{}").err(), Some(FileErr::Format));
+}
+
+//Test 4: A backup reads back with where it was taken, and is not mistaken for a snapshot.
+#[test]
+fn backup_round_trip() {
+    let backup = Backup {
+        cluster: vec![1, 2, 3],
+        pid: 2,
+        index: 1,
+        created: 1_700_000_000_000,
+        snapshot: snapshot(),
+    };
+    let bytes = encode_backup(&backup);
+    assert!(bytes.starts_with(b"KVBACKUP 1 1 "));
+    let restored = decode_backup(&bytes).unwrap();
+    assert_eq!(restored.cluster, vec![1, 2, 3]);
+    assert_eq!((restored.pid, restored.index), (2, 1));
+    assert_eq!(restored.snapshot.state(), snapshot().state());
+    assert_eq!(decode(&bytes).err(), Some(FileErr::Format));
+    assert_eq!(
+        decode_backup(&encode(&snapshot())).err(),
+        Some(FileErr::Format)
+    );
+    let mut flipped = bytes.clone();
+    let last = flipped.len() - 2;
+    flipped[last] ^= 1;
+    assert_eq!(decode_backup(&flipped).err(), Some(FileErr::Checksum));
+}
@@ -0,0 +1,277 @@
+#![cfg(unix)]
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+#[allow(dead_code)]
+mod common;
+use common::kv::KeyValue;
+use common::msg::{CMDMessage, Msg, Operation};
+use common::package::{Package, Types};
+
+const CLIENT: &str = "127.0.0.1:12345";
+
+//the tests share the client address, so they run one at a time
+static CLIENT_LOCK: Mutex<()> = Mutex::new(());
+
+fn listen() -> (MutexGuard<'static, ()>, TcpListener) {
+    let guard = CLIENT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    (guard, TcpListener::bind(CLIENT).unwrap())
+}
+
+//a server process, killed if a test fails before it is stopped
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+//start a single node cluster that keeps its snapshots in `dir` and snapshots after
+//every entry
+fn start(pid: u64, dir: &Path) -> Server {
+    start_with(pid, dir, &[])
+}
+
+fn start_with(pid: u64, dir: &Path, args: &[&str]) -> Server {
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--pid", &pid.to_string(), "--snapshot-entries", "1"])
+            .arg("--data-dir")
+            .arg(dir)
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start the server"),
+    );
+    for _ in 0..100 {
+        if TcpStream::connect(addr(pid)).is_ok() {
+            return server;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("Server did not start listening");
+}
+
+fn addr(pid: u64) -> String {
+    format!("127.0.0.1:{}", 11000 + pid)
+}
+
+//send a command to the node and wait for its reply
+fn request(
+    listener: &TcpListener,
+    pid: u64,
+    operation: Operation,
+    key: &str,
+    value: u64,
+) -> String {
+    let pkg = Package {
+        types: Types::CMD,
+        msg: Msg::CMD(CMDMessage {
+            operation,
+            kv: KeyValue {
+                key: key.to_string(),
+                value,
+            },
+        }),
+    };
+    let mut stream = TcpStream::connect(addr(pid)).unwrap();
+    stream
+        .write_all(serde_json::to_string(&pkg).unwrap().as_bytes())
+        .unwrap();
+    drop(stream);
+    let (socket, _) = listener.accept().unwrap();
+    let mut reply = String::new();
+    BufReader::new(socket).read_line(&mut reply).unwrap();
+    reply
+}
+
+fn stop(mut server: Server, signal: &str) -> String {
+    let child = &mut server.0;
+    Command::new("kill")
+        .args([signal, &child.id().to_string()])
+        .status()
+        .expect("Failed to send the signal");
+    child.wait().unwrap();
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    output
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+//Test 1: A node that is killed restarts from its snapshot on disk with its values.
+#[test]
+fn kill_and_restart() {
+    let dir = data_dir("kv-restart");
+    let (_guard, listener) = listen();
+    let server = start(93, &dir);
+    let reply = request(&listener, 93, Operation::Put, "key", 7);
+    assert_eq!(reply, "Successfully to put value");
+    let file = dir.join("node93.snap");
+    for _ in 0..100 {
+        if file.exists() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    stop(server, "-KILL");
+    assert!(file.exists());
+
+    let server = start(93, &dir);
+    let reply = request(&listener, 93, Operation::Get, "key", 0);
+    let output = stop(server, "-TERM");
+    assert!(
+        reply.starts_with("This value is : 7 (version 1"),
+        "{}",
+        reply
+    );
+    assert!(
+        output.contains("Node 93 restores its snapshot up to index 1"),
+        "{}",
+        output
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 2: A backup taken on one cluster bootstraps a fresh cluster with its values, and
+//is not restored over an existing snapshot.
+#[test]
+fn backup_and_restore() {
+    let dir = data_dir("kv-backup");
+    let file = dir.with_extension("backup");
+    let (_guard, listener) = listen();
+    let server = start(94, &dir);
+    request(&listener, 94, Operation::Put, "a", 1);
+    request(&listener, 94, Operation::Put, "b", 2);
+    let path = file.to_str().unwrap().to_string();
+    let operation = Operation::Backup { path: path.clone() };
+    let reply = request(&listener, 94, operation, "_", 0);
+    stop(server, "-TERM");
+    assert_eq!(
+        reply,
+        format!("Successfully to back up the log up to index 2 to {}", path)
+    );
+
+    let fresh = data_dir("kv-restore");
+    let server = start_with(95, &fresh, &["--restore", &path]);
+    let a = request(&listener, 95, Operation::Get, "a", 0);
+    let b = request(&listener, 95, Operation::Get, "b", 0);
+    let output = stop(server, "-TERM");
+    assert!(a.starts_with("This value is : 1 (version 1"), "{}", a);
+    assert!(b.starts_with("This value is : 2 (version 1"), "{}", b);
+    assert!(
+        output.contains("Node 95 restores the backup up to index 2"),
+        "{}",
+        output
+    );
+    assert!(output.contains("The backup was taken on cluster [94]"));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(["--pid", "95", "--restore", &path])
+        .arg("--data-dir")
+        .arg(&fresh)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+    let _ = fs::remove_dir_all(&dir);
+    let _ = fs::remove_dir_all(&fresh);
+    let _ = fs::remove_file(&file);
+}
+
+//Test 3: The keys of one node exported as csv are imported into another node in batches.
+#[test]
+fn export_and_import() {
+    let dir = data_dir("kv-export");
+    let fresh = data_dir("kv-import");
+    let file = dir.with_extension("csv");
+    let (_guard, listener) = listen();
+    let server = start(96, &dir);
+    for (key, value) in [("a", 1), ("b,c", 2), ("d", 3)] {
+        request(&listener, 96, Operation::Put, key, value);
+    }
+    //the client listens on the client address itself
+    drop(listener);
+    let client = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_client"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let path = file.to_str().unwrap();
+    let export = client(&["export", "--pid", "96", "--format", "csv", "--out", path]);
+    stop(server, "-TERM");
+    assert!(export.status.success());
+    let csv = fs::read_to_string(&file).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "key,value,version,create_revision,mod_revision");
+    assert_eq!(&lines[1..], ["a,1,1,0,0", "\"b,c\",2,1,1,1", "d,3,1,2,2"]);
+
+    let server = start(97, &fresh);
+    let args = ["--pid", "97", "--format", "csv", "--batch", "2"];
+    let import = client(&[&["import"], &args[..], &[path]].concat());
+    let progress = String::from_utf8(import.stderr).unwrap();
+    assert!(import.status.success(), "{}", progress);
+    for done in [2, 3] {
+        let line = format!("Imported {}/3 keys, offset {}", done, done);
+        assert!(progress.contains(&line), "{}", progress);
+    }
+    let listener = TcpListener::bind(CLIENT).unwrap();
+    let reply = request(&listener, 97, Operation::Get, "b,c", 0);
+    stop(server, "-TERM");
+    assert!(reply.starts_with("This value is : 2"), "{}", reply);
+    let _ = fs::remove_dir_all(&dir);
+    let _ = fs::remove_dir_all(&fresh);
+    let _ = fs::remove_file(&file);
+}
+
+//Test 4: A node of the lsm storage whose snapshot file was corrupted on disk moves it out
+//of the way on start, and still has its values from its log.
+#[test]
+fn corrupted_snapshot_is_repaired() {
+    let dir = data_dir("kv-corrupt");
+    let (_guard, listener) = listen();
+    let server = start_with(98, &dir, &["--storage", "lsm"]);
+    let reply = request(&listener, 98, Operation::Put, "key", 7);
+    assert_eq!(reply, "Successfully to put value");
+    stop(server, "-TERM");
+
+    //the node writes its snapshot when it shuts down
+    let file = dir.join("node98.snap");
+    let mut bytes = fs::read(&file).unwrap();
+    let last = bytes.len() - 2;
+    bytes[last] ^= 1;
+    fs::write(&file, bytes).unwrap();
+    let server = start_with(98, &dir, &["--storage", "lsm"]);
+    let reply = request(&listener, 98, Operation::Get, "key", 0);
+    let output = stop(server, "-TERM");
+    assert!(
+        reply.starts_with("This value is : 7 (version 1"),
+        "{}",
+        reply
+    );
+    assert!(
+        output.contains("Node 98 repairs its snapshot"),
+        "{}",
+        output
+    );
+    assert!(dir.join("node98.snap.corrupt").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
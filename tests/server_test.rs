@@ -108,7 +108,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         reader.read_line(&mut buf).await.unwrap();
         println!("{}", &buf);
 
-        assert_eq!(&buf, "This value is : 0");
+        assert_eq!(
+            &buf,
+            "This value is : 0 (version 1, create revision 1, mod revision 1)"
+        );
         buf.clear();
     }
 
@@ -140,7 +143,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut buf = String::new();
         reader.read_line(&mut buf).await.unwrap();
         println!("{}", &buf);
-        assert_eq!(&buf, "This value is : 0");
+        assert_eq!(
+            &buf,
+            "This value is : 0 (version 1, create revision 1, mod revision 1)"
+        );
         buf.clear();
     }
 
@@ -199,7 +205,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut buf = String::new();
     reader.read_line(&mut buf).await.unwrap();
     println!("{}", &buf);
-    assert_eq!(&buf, "This value is : 1");
+    assert_eq!(
+        &buf,
+        "This value is : 1 (version 2, create revision 1, mod revision 2)"
+    );
     buf.clear();
 
     thread::sleep(time::Duration::from_millis(10000));
@@ -228,7 +237,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut buf = String::new();
     reader.read_line(&mut buf).await.unwrap();
     println!("{}", &buf);
-    assert_eq!(&buf, "This value is : 1");
+    assert_eq!(
+        &buf,
+        "This value is : 1 (version 2, create revision 1, mod revision 2)"
+    );
     buf.clear();
 
     //Test8
@@ -286,7 +298,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut buf = String::new();
         reader.read_line(&mut buf).await.unwrap();
         println!("{}", &buf);
-        assert_eq!(&buf, "This value is : 1");
+        assert_eq!(
+            &buf,
+            "This value is : 1 (version 2, create revision 1, mod revision 2)"
+        );
         buf.clear();
     }
 
@@ -348,7 +363,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         reader.read_line(&mut buf).await.unwrap();
         println!("{}", &buf);
 
-        assert_eq!(&buf, "This value is : 2");
+        assert_eq!(
+            &buf,
+            "This value is : 2 (version 3, create revision 1, mod revision 3)"
+        );
         buf.clear();
     }
     Ok(())
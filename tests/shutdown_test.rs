@@ -0,0 +1,71 @@
+#![cfg(unix)]
+use std::io::Read;
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+//a server process, killed if a test fails before it is stopped
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+//start a single node cluster on its own port
+fn start(pid: u64) -> Server {
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--pid", &pid.to_string()])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start the server"),
+    );
+    for _ in 0..100 {
+        if TcpStream::connect(addr(pid)).is_ok() {
+            return server;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("Server did not start listening");
+}
+
+fn addr(pid: u64) -> String {
+    format!("127.0.0.1:{}", 11000 + pid)
+}
+
+//send a signal to the server and return its exit code and output
+fn stop(mut server: Server, signal: &str) -> (Option<i32>, String) {
+    let child = &mut server.0;
+    Command::new("kill")
+        .args([signal, &child.id().to_string()])
+        .status()
+        .expect("Failed to send the signal");
+    let status = child.wait().unwrap();
+    let mut output = String::new();
+    let mut stdout = child.stdout.take().unwrap();
+    stdout.read_to_string(&mut output).unwrap();
+    (status.code(), output)
+}
+
+//Test 1: SIGTERM stops the node cleanly and closes its port.
+#[test]
+fn sigterm() {
+    let server = start(91);
+    let (code, output) = stop(server, "-TERM");
+    assert_eq!(code, Some(0), "{}", output);
+    assert!(output.contains("Node 91 is shut down"));
+    assert!(TcpStream::connect(addr(91)).is_err());
+}
+
+//Test 2: SIGINT stops the node cleanly as well.
+#[test]
+fn sigint() {
+    let server = start(92);
+    let (code, output) = stop(server, "-INT");
+    assert_eq!(code, Some(0), "{}", output);
+    assert!(output.contains("Node 92 is shut down"));
+}
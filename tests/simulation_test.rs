@@ -0,0 +1,18 @@
+#[allow(dead_code)]
+mod common;
+use common::simulation::simulate;
+
+//Test 1: The same seed reproduces exactly the same interleaving of messages.
+#[test]
+fn same_seed_same_trace() {
+    let first = simulate(None, 7, None);
+    let second = simulate(None, 7, None);
+    assert!(first.len() > 1);
+    assert_eq!(first, second);
+}
+
+//Test 2: Different seeds explore different interleavings.
+#[test]
+fn different_seed_different_trace() {
+    assert_ne!(simulate(None, 1, None), simulate(None, 2, None));
+}
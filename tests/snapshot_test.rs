@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+
+use omnipaxos_core::storage::Snapshot;
+
+#[allow(dead_code)]
+mod common;
+use common::kv::{Command, Condition, KVSnapshot, KeyValue, LogEntry, Mutation, Store};
+
+fn entries(commands: Vec<Command>) -> Vec<LogEntry> {
+    commands
+        .into_iter()
+        .enumerate()
+        .map(|(id, command)| LogEntry {
+            id: id as u64,
+            command,
+        })
+        .collect()
+}
+
+fn put(key: &str, value: u64) -> Command {
+    Command::Put(KeyValue {
+        key: key.to_string(),
+        value,
+    })
+}
+
+//a transaction that moves `a` to `b` if `a` holds `value`
+fn move_if(value: u64) -> Command {
+    Command::Txn {
+        conditions: vec![Condition::Equals {
+            key: String::from("a"),
+            value,
+        }],
+        mutations: vec![
+            Mutation::Put(KeyValue {
+                key: String::from("b"),
+                value,
+            }),
+            Mutation::Delete {
+                key: String::from("a"),
+            },
+        ],
+    }
+}
+
+fn apply(commands: &[Command]) -> Store {
+    let mut store = Store::default();
+    for (i, command) in commands.iter().enumerate() {
+        command.apply(&mut store, i as u64 + 1);
+    }
+    store
+}
+
+//Test 1: A transaction that depends on a key set before the snapshotted slice
+//is only folded once the slice is merged behind the snapshot of its prefix.
+#[test]
+fn transaction_across_snapshots() {
+    let log = vec![put("a", 1), put("c", 3), move_if(1), move_if(1)];
+    let mut snapshot = KVSnapshot::create(&entries(log[..2].to_vec()));
+    snapshot.merge(KVSnapshot::create(&entries(log[2..].to_vec())));
+    assert!(snapshot.delta.is_empty());
+    assert_eq!(snapshot.state(), apply(&log));
+    assert!(!snapshot.state().data.contains_key("a"));
+    assert_eq!(snapshot.state().data["b"].value, 1);
+}
+
+//Test 2: A transaction whose condition fails changes nothing, also in a snapshot.
+#[test]
+fn aborted_transaction_in_snapshot() {
+    let log = vec![put("a", 2), move_if(1)];
+    let snapshot = KVSnapshot::create(&entries(log.clone()));
+    assert_eq!(snapshot.state(), apply(&log));
+    assert_eq!(snapshot.state().data["a"].value, 2);
+    assert!(!snapshot.state().data.contains_key("b"));
+}
+
+//Test 3: The commands of a merged slice get the revision of their place in the log.
+#[test]
+fn revisions_across_snapshots() {
+    let log = vec![put("a", 1), put("b", 2), put("a", 3), move_if(3)];
+    let mut snapshot = KVSnapshot::create(&entries(log[..1].to_vec()));
+    snapshot.merge(KVSnapshot::create(&entries(log[1..3].to_vec())));
+    snapshot.merge(KVSnapshot::create(&entries(log[3..].to_vec())));
+    assert_eq!(snapshot.len, 4);
+    assert_eq!(snapshot.state(), apply(&log));
+    assert_eq!(snapshot.state().data["b"].create_revision, 2);
+    assert_eq!(snapshot.state().data["b"].mod_revision, 4);
+}
+
+//Test 4: A lease granted before the snapshotted slice is revoked with its keys
+//when the slice is merged, and a stale expiry of a kept alive lease is dropped.
+#[test]
+fn lease_across_snapshots() {
+    let log = vec![
+        Command::Grant { ttl: 1000 },
+        Command::Lock {
+            name: String::from("job"),
+            lease: 1,
+        },
+        Command::KeepAlive { lease: 1 },
+        Command::Revoke {
+            lease: 1,
+            revision: Some(1),
+        },
+        put("c", 3),
+        Command::Revoke {
+            lease: 1,
+            revision: Some(3),
+        },
+    ];
+    let mut snapshot = KVSnapshot::create(&entries(log[..4].to_vec()));
+    assert_eq!(snapshot.state().data["job"].value, 1);
+    snapshot.merge(KVSnapshot::create(&entries(log[4..].to_vec())));
+    assert_eq!(snapshot.state(), apply(&log));
+    assert!(snapshot.state().leases.is_empty());
+    assert!(!snapshot.state().data.contains_key("job"));
+}
+
+//Test 5: A delta that was resolved itself covers the log from index 0, so it replaces
+//the snapshot instead of being merged key by key, and a key it deleted stays deleted.
+#[test]
+fn resolved_delta_keeps_deletes() {
+    let delete = Command::Txn {
+        conditions: vec![],
+        mutations: vec![Mutation::Delete {
+            key: String::from("a"),
+        }],
+    };
+    let log = vec![put("a", 1), put("b", 2), delete];
+    let mut resolved = KVSnapshot::create(&entries(log[..2].to_vec()));
+    resolved.merge(KVSnapshot::create(&entries(log[2..].to_vec())));
+    let mut snapshot = KVSnapshot::create(&entries(log[..1].to_vec()));
+    snapshot.merge(resolved);
+    assert_eq!(snapshot.len, 3);
+    assert_eq!(snapshot.state(), apply(&log));
+    assert!(!snapshot.state().data.contains_key("a"));
+}
+
+//a small xorshift generator, so the property tests are reproducible by seed
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+fn random_command(rng: &mut Rng) -> Command {
+    let key = ["a", "b", "c", "d"][rng.below(4) as usize].to_string();
+    let value = rng.below(4);
+    match rng.below(5) {
+        0 => put(&key, value),
+        1 => Command::Cas {
+            key,
+            expected: Some(value).filter(|v| *v > 0),
+            new: rng.below(4),
+        },
+        2 => Command::Incr { key, delta: value },
+        3 => Command::Txn {
+            conditions: vec![],
+            mutations: vec![Mutation::Delete { key }],
+        },
+        _ => move_if(value),
+    }
+}
+
+//the reference model: the value of every key after each command, kept in a plain map
+fn model(commands: &[Command]) -> BTreeMap<String, u64> {
+    let mut values = BTreeMap::new();
+    for command in commands {
+        match command {
+            Command::Put(kv) => {
+                values.insert(kv.key.clone(), kv.value);
+            }
+            Command::Cas { key, expected, new } => {
+                if values.get(key).cloned() == *expected {
+                    values.insert(key.clone(), *new);
+                }
+            }
+            Command::Incr { key, delta } => {
+                *values.entry(key.clone()).or_insert(0) += delta;
+            }
+            Command::Txn { conditions, .. } if conditions.is_empty() => {
+                values.remove(&command.keys(&Store::default())[0]);
+            }
+            //move_if
+            Command::Txn { conditions, .. } => {
+                if let Condition::Equals { value, .. } = conditions[0] {
+                    if values.get("a") == Some(&value) {
+                        values.remove("a");
+                        values.insert(String::from("b"), value);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    values
+}
+
+fn values(store: &Store) -> BTreeMap<String, u64> {
+    store
+        .data
+        .iter()
+        .map(|(key, record)| (key.clone(), record.value))
+        .collect()
+}
+
+//Test 6: Whatever points the log is snapshotted at, the snapshot reads the values the
+//reference model holds at that point, before and after every merge.
+#[test]
+fn snapshots_follow_the_model() {
+    for seed in 1..300 {
+        let mut rng = Rng(seed);
+        let log: Vec<Command> = (0..40).map(|_| random_command(&mut rng)).collect();
+        let mut points: Vec<usize> = (0..4).map(|_| rng.below(41) as usize).collect();
+        points.push(log.len());
+        points.sort_unstable();
+        points.dedup();
+
+        let first = points[0];
+        let mut snapshot = KVSnapshot::create(&entries(log[..first].to_vec()));
+        let state = values(&snapshot.state());
+        assert_eq!(state, model(&log[..first]), "seed {}", seed);
+        for pair in points.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let slice: Vec<LogEntry> = entries(log.clone())[from..to].to_vec();
+            let before = snapshot.state();
+            snapshot.merge(KVSnapshot::create(&slice));
+            assert_eq!(snapshot.len, to as u64);
+            assert_eq!(values(&before), model(&log[..from]), "seed {}", seed);
+            let state = values(&snapshot.state());
+            assert_eq!(state, model(&log[..to]), "seed {}", seed);
+        }
+        assert_eq!(snapshot.state(), apply(&log), "seed {}", seed);
+    }
+}
+
+//Test 7: A snapshot keeps the highest proposal id of every node, also after a merge and
+//after a resolved delta replaced it.
+#[test]
+fn snapshots_keep_the_ids() {
+    let id = |pid: u64, n: u64| (pid << 48) + n;
+    let entry = |id: u64| LogEntry {
+        id,
+        command: put("a", id),
+    };
+    let mut snapshot = KVSnapshot::create(&[entry(id(1, 3)), entry(id(2, 1))]);
+    snapshot.merge(KVSnapshot::create(&[entry(id(1, 2)), entry(id(2, 5))]));
+    assert_eq!(snapshot.ids, BTreeMap::from([(1, id(1, 3)), (2, id(2, 5))]));
+
+    let mut resolved = KVSnapshot::create(&[entry(id(3, 1))]);
+    resolved.merge(KVSnapshot::create(&[
+        entry(id(3, 2)),
+        entry(id(1, 1)),
+        entry(id(2, 9)),
+    ]));
+    snapshot.merge(resolved);
+    assert_eq!(snapshot.len, 4);
+    assert_eq!(
+        snapshot.ids,
+        BTreeMap::from([(1, id(1, 3)), (2, id(2, 9)), (3, id(3, 2))])
+    );
+}
@@ -0,0 +1,475 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use omnipaxos_core::ballot_leader_election::Ballot;
+use omnipaxos_core::storage::memory_storage::MemoryStorage;
+use omnipaxos_core::storage::Storage;
+
+#[allow(dead_code)]
+mod common;
+use common::kv::{Command, KVSnapshot, KeyValue, LogEntry, Store};
+use common::lsm::{runs, LsmStorage, Meta};
+use common::persist::crc32;
+use common::storage::{Backend, Repair, StateStore, StorageOptions};
+use common::wal::{segments, WalStorage};
+
+type Memory = MemoryStorage<LogEntry, KVSnapshot>;
+
+fn entry(id: u64) -> LogEntry {
+    LogEntry {
+        id,
+        command: Command::Put(KeyValue {
+            key: format!("key{}", id % 7),
+            value: id,
+        }),
+    }
+}
+
+fn ids(entries: Vec<LogEntry>) -> Vec<u64> {
+    entries.iter().map(|e| e.id).collect()
+}
+
+fn ballot(n: u32) -> Ballot {
+    Ballot {
+        n,
+        priority: 0,
+        pid: 1,
+    }
+}
+
+fn snapshot(len: u64) -> KVSnapshot {
+    KVSnapshot {
+        snapshotted: Store::default(),
+        delta: vec![],
+        len,
+        ids: Default::default(),
+    }
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+//small files, so the suite writes many segments, runs and merges
+fn options() -> StorageOptions {
+    StorageOptions {
+        segment_bytes: 512,
+        memtable_entries: 4,
+        max_runs: 2,
+    }
+}
+
+//everything a storage answers, the entries by id
+fn view<B: Storage<LogEntry, KVSnapshot>>(storage: &B) -> String {
+    format!(
+        "log {:?}, promise {:?}, accepted {:?}, decided {}, compacted {}, snapshot {:?}",
+        ids(storage.get_suffix(0)),
+        storage.get_promise(),
+        storage.get_accepted_round(),
+        storage.get_decided_idx(),
+        storage.get_compacted_idx(),
+        storage.get_snapshot().map(|s| s.len)
+    )
+}
+
+//flip a byte in the body of a frame of a run, frame 0 is its header
+fn corrupt_frame(path: &Path, frame: usize) {
+    let mut bytes = fs::read(path).unwrap();
+    let mut offset = 0;
+    for _ in 0..frame {
+        let length = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        offset += 8 + length as usize;
+    }
+    bytes[offset + 10] ^= 0x20;
+    fs::write(path, bytes).unwrap();
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+//the suite every engine has to pass for its log and its state machine, `path` is where a
+//durable engine keeps the storage, it is opened again after the calls and has to give
+//the same answers
+fn conformance<B: Backend>(path: Option<&Path>) {
+    let (mut storage, recovery) = B::open(path, &options()).unwrap();
+    assert!(recovery.is_new());
+    assert_eq!(storage.get_log_len(), 0);
+    assert_eq!(storage.get_decided_idx(), 0);
+    assert_eq!(storage.get_compacted_idx(), 0);
+    assert_eq!(storage.get_promise(), Ballot::default());
+    assert!(storage.get_snapshot().is_none());
+
+    //the appends return the length of the log
+    assert_eq!(storage.append_entry(entry(0)), 1);
+    assert_eq!(storage.append_entries((1..5).map(entry).collect()), 5);
+    assert_eq!(ids(storage.get_entries(1, 3)), vec![1, 2]);
+    assert_eq!(ids(storage.get_suffix(3)), vec![3, 4]);
+    assert_eq!(storage.append_on_prefix(3, vec![entry(10)]), 4);
+    assert_eq!(ids(storage.get_suffix(0)), vec![0, 1, 2, 10]);
+
+    storage.set_promise(ballot(3));
+    storage.set_accepted_round(ballot(2));
+    storage.set_decided_idx(3);
+    assert_eq!(storage.get_promise(), ballot(3));
+    assert_eq!(storage.get_accepted_round(), ballot(2));
+    assert_eq!(storage.get_decided_idx(), 3);
+
+    //after a compaction the indexes are relative to the first entry left
+    storage.set_snapshot(snapshot(2));
+    storage.trim(2);
+    storage.set_compacted_idx(2);
+    assert_eq!(storage.get_log_len(), 2);
+    assert_eq!(ids(storage.get_entries(0, 2)), vec![2, 10]);
+    assert_eq!(storage.get_compacted_idx(), 2);
+    assert_eq!(storage.get_snapshot().unwrap().len, 2);
+    assert_eq!(storage.append_entries(vec![entry(11), entry(12)]), 4);
+    assert_eq!(ids(storage.get_suffix(2)), vec![11, 12]);
+
+    let expected = view(&storage);
+    if let Some(path) = path {
+        drop(storage);
+        let (storage, recovery) = B::open(Some(path), &options()).unwrap();
+        assert!(!recovery.is_new());
+        assert_eq!((recovery.decided, recovery.compacted), (3, 2));
+        assert_eq!(view(&storage), expected);
+    }
+
+    //the state machine gives back the last state saved, a durable one also once it is
+    //opened again
+    let state_path = path.map(|path| path.with_extension("snap"));
+    let state = B::state(state_path.as_deref());
+    assert!(state.load().unwrap().is_none());
+    let mut applied = Store::default();
+    let put = Command::Put(KeyValue {
+        key: String::from("a"),
+        value: 1,
+    });
+    put.apply(&mut applied, 1);
+    state.save(&snapshot(0)).unwrap();
+    let saved = KVSnapshot {
+        snapshotted: applied,
+        delta: vec![],
+        len: 1,
+        ids: Default::default(),
+    };
+    state.save(&saved).unwrap();
+    let loaded = |state: &dyn StateStore| {
+        let snapshot = state.load().unwrap().unwrap();
+        let record = snapshot.snapshotted.data.get("a").unwrap();
+        (snapshot.len, record.value, record.mod_revision)
+    };
+    assert_eq!(loaded(state.as_ref()), (1, 1, 1));
+    if let Some(state_path) = state_path {
+        drop(state);
+        assert_eq!(loaded(B::state(Some(&state_path)).as_ref()), (1, 1, 1));
+        let _ = fs::remove_file(&state_path);
+    }
+}
+
+//random calls give the same answers as the memory storage, a durable engine is opened
+//again every 50 calls
+fn agrees_with_memory<B: Backend>(path: Option<&Path>, seed: u64) {
+    let mut rng = Rng(seed);
+    let mut memory = Memory::default();
+    let (mut storage, _) = B::open(path, &options()).unwrap();
+    let mut id = 0;
+    for call in 1..=500 {
+        let len = memory.get_log_len();
+        match rng.next(6) {
+            0 => {
+                let n = ballot(rng.next(10) as u32);
+                memory.set_promise(n);
+                storage.set_promise(n);
+            }
+            1 => {
+                let n = ballot(rng.next(10) as u32);
+                memory.set_accepted_round(n);
+                storage.set_accepted_round(n);
+            }
+            2 => {
+                let from = len - rng.next(len.min(3) + 1);
+                let count = rng.next(3);
+                let entries: Vec<_> = (id..id + count).map(entry).collect();
+                let answer = memory.append_on_prefix(from, entries.clone());
+                assert_eq!(storage.append_on_prefix(from, entries), answer);
+            }
+            3 if len > 0 && rng.next(4) == 0 => {
+                let trimmed = rng.next(len) + 1;
+                let compacted = memory.get_compacted_idx() + trimmed;
+                for storage in [&mut memory as &mut dyn Storage<_, _>, &mut storage] {
+                    storage.set_decided_idx(len);
+                    storage.set_snapshot(snapshot(compacted));
+                    storage.trim(trimmed);
+                    storage.set_compacted_idx(compacted);
+                }
+            }
+            _ => {
+                let entries: Vec<_> = (id..id + rng.next(4) + 1).map(entry).collect();
+                let answer = memory.append_entries(entries.clone());
+                assert_eq!(storage.append_entries(entries), answer);
+            }
+        }
+        id += 5;
+        let len = memory.get_log_len();
+        let from = rng.next(len + 1);
+        let to = from + rng.next(len - from + 1);
+        assert_eq!(
+            ids(storage.get_entries(from, to)),
+            ids(memory.get_entries(from, to))
+        );
+        assert_eq!(view(&storage), view(&memory), "call {}", call);
+        if let (Some(path), 0) = (path, call % 50) {
+            drop(storage);
+            storage = B::open(Some(path), &options()).unwrap().0;
+            assert_eq!(view(&storage), view(&memory), "reopened at call {}", call);
+        }
+    }
+}
+
+//Test 1: The memory storage passes the suite.
+#[test]
+fn memory_storage() {
+    conformance::<Memory>(None);
+    agrees_with_memory::<Memory>(None, 0x2545_F491_4F6C_DD1D);
+}
+
+//Test 2: The write-ahead log passes the suite, and keeps what it answers.
+#[test]
+fn file_storage() {
+    let dir = data_dir("storage-file");
+    conformance::<WalStorage>(Some(&dir));
+    let _ = fs::remove_dir_all(&dir);
+    agrees_with_memory::<WalStorage>(Some(&dir), 0x2545_F491_4F6C_DD1D);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 3: The lsm storage passes the suite, and keeps what it answers.
+#[test]
+fn lsm_storage() {
+    let dir = data_dir("storage-lsm");
+    conformance::<LsmStorage>(Some(&dir));
+    let _ = fs::remove_dir_all(&dir);
+    agrees_with_memory::<LsmStorage>(Some(&dir), 0x2545_F491_4F6C_DD1D);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 4: The lsm storage moves its entries from the write-ahead log to a bounded number
+//of runs, and a compaction merges them into one run without the trimmed entries.
+#[test]
+fn lsm_flushes_and_merges() {
+    let dir = data_dir("storage-lsm-runs");
+    let (mut storage, _) = LsmStorage::open(&dir, &options()).unwrap();
+    for id in 0..100 {
+        storage.append_entry(entry(id));
+        assert!(storage.runs().len() <= options().max_runs);
+    }
+    assert!(segments(&dir.join("wal")).unwrap().len() <= 2);
+    assert!(storage.runs()[0] > 1, "the runs were never merged");
+
+    storage.set_decided_idx(100);
+    storage.set_snapshot(snapshot(90));
+    storage.trim(90);
+    storage.set_compacted_idx(90);
+    assert_eq!(runs(&dir).unwrap().len(), 1);
+    assert_eq!(ids(storage.get_suffix(0)), (90..100).collect::<Vec<_>>());
+    let expected = view(&storage);
+    drop(storage);
+
+    let (storage, recovery) = LsmStorage::open(&dir, &options()).unwrap();
+    assert_eq!((recovery.decided, recovery.compacted), (100, 90));
+    assert_eq!(recovery.runs, 1);
+    assert!(recovery.snapshot);
+    assert_eq!(view(&storage), expected);
+    assert_eq!(storage.meta().offset, 90);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 5: The durable engines refuse to start without a place to keep the storage.
+#[test]
+fn durable_engines_need_a_path() {
+    let error = <WalStorage as Backend>::open(None, &options())
+        .err()
+        .unwrap();
+    assert_eq!(error.to_string(), "the file storage needs a data directory");
+    let error = <LsmStorage as Backend>::open(None, &options())
+        .err()
+        .unwrap();
+    assert_eq!(error.to_string(), "the lsm storage needs a data directory");
+}
+
+//Test 6: A corrupted entry of a run cuts the log at its index and a corrupted header of
+//a run cuts the run and the runs after it; the promise is kept across a cut and the
+//accepted round is reset, and the runs are written again, so the repair is done once.
+#[test]
+fn lsm_repairs_corrupted_runs() {
+    let dir = data_dir("storage-lsm-corrupt");
+    let options = StorageOptions {
+        max_runs: 4,
+        ..options()
+    };
+    let (mut storage, _) = LsmStorage::open(&dir, &options).unwrap();
+    storage.set_promise(ballot(5));
+    storage.set_accepted_round(ballot(4));
+    for id in 0..20 {
+        storage.append_entry(entry(id));
+    }
+    storage.set_decided_idx(20);
+    drop(storage);
+    let files = runs(&dir).unwrap();
+    assert_eq!(files.len(), 1);
+    let (run, path) = &files[0];
+
+    //the run holds the entries from index 0 on, frame 3 is the entry at index 2
+    corrupt_frame(path, 3);
+    let (mut storage, recovery) = LsmStorage::open(&dir, &options).unwrap();
+    let repair = Repair::Entry {
+        run: *run,
+        index: 2,
+    };
+    assert_eq!(recovery.repairs, vec![repair]);
+    assert_eq!((recovery.end, recovery.decided), (2, 2));
+    assert_eq!(ids(storage.get_suffix(0)), vec![0, 1]);
+    assert_eq!(storage.get_promise(), ballot(5));
+    //a run of the 6 entries from index 2 on and a run of the 4 from index 8 on
+    assert_eq!(storage.append_entries((30..36).map(entry).collect()), 8);
+    storage.set_promise(ballot(7));
+    assert_eq!(storage.append_entries((36..40).map(entry).collect()), 12);
+    let expected = view(&storage);
+    drop(storage);
+    let (storage, recovery) = LsmStorage::open(&dir, &options).unwrap();
+    assert!(recovery.repairs.is_empty());
+    assert_eq!(view(&storage), expected);
+    drop(storage);
+
+    let files = runs(&dir).unwrap();
+    assert_eq!(files.len(), 3);
+    let (run, path) = &files[1];
+    corrupt_frame(path, 0);
+    let (storage, recovery) = LsmStorage::open(&dir, &options).unwrap();
+    assert_eq!(recovery.repairs, vec![Repair::Run { run: *run }]);
+    assert_eq!(ids(storage.get_suffix(0)), vec![0, 1]);
+    assert_eq!(recovery.end, 2);
+    assert_eq!(storage.get_promise(), ballot(7));
+    assert_eq!(storage.get_accepted_round(), Ballot::default());
+    let expected = view(&storage);
+    drop(storage);
+    let (storage, recovery) = LsmStorage::open(&dir, &options).unwrap();
+    assert!(recovery.repairs.is_empty());
+    assert_eq!(view(&storage), expected);
+    drop(storage);
+
+    //without a run left, the ballots are in the write-ahead log after it
+    let (_, path) = runs(&dir).unwrap().pop().unwrap();
+    corrupt_frame(&path, 0);
+    let (storage, recovery) = LsmStorage::open(&dir, &options).unwrap();
+    assert_eq!(recovery.end, 0);
+    assert_eq!(storage.get_log_len(), 0);
+    assert_eq!(storage.get_promise(), ballot(7));
+    assert_eq!(storage.get_accepted_round(), Ballot::default());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 7: A store written with runs of version 1 does not open and is left as it is, it
+//is not repaired as corrupted.
+#[test]
+fn lsm_refuses_runs_of_version_1() {
+    let dir = data_dir("storage-lsm-v1");
+    fs::create_dir_all(&dir).unwrap();
+    let meta = Meta {
+        len: 2,
+        promise: ballot(3),
+        ..Meta::default()
+    };
+    let entries: BTreeMap<u64, LogEntry> = (0..2).map(|i| (i, entry(i))).collect();
+    let body = serde_json::to_vec(&(meta, entries)).unwrap();
+    let header = format!("KVRUN 1 1 {} {:08x}\n", body.len(), crc32(&body));
+    let bytes = [header.into_bytes(), body].concat();
+    let path = dir.join("00000001.run");
+    fs::write(&path, &bytes).unwrap();
+
+    let error = LsmStorage::open(&dir, &options()).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "run 1 has the unknown version 1");
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+    assert_eq!(runs(&dir).unwrap().len(), 1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 8: A corrupted record of a write-ahead log cuts the log before it, the promise of
+//the whole segments after it is kept and the accepted round is reset.
+#[test]
+fn wal_keeps_the_promise_across_a_cut() {
+    let dir = data_dir("storage-wal-corrupt");
+    let (mut storage, _) = WalStorage::open(&dir, 512).unwrap();
+    storage.set_promise(ballot(2));
+    storage.append_entries((0..4).map(entry).collect());
+    for n in 3..40 {
+        storage.set_promise(ballot(n));
+        storage.set_accepted_round(ballot(n));
+        storage.wal().sync().unwrap();
+    }
+    drop(storage);
+    let files = segments(&dir).unwrap();
+    assert!(files.len() > 2);
+    corrupt_frame(&files[0].1, 1);
+
+    let (storage, replay) = WalStorage::open(&dir, 512).unwrap();
+    assert_eq!(replay.corrupt.map(|(segment, _)| segment), Some(files[0].0));
+    assert_eq!(storage.get_log_len(), 0);
+    assert_eq!(storage.get_promise(), ballot(39));
+    assert_eq!(storage.get_accepted_round(), Ballot::default());
+    drop(storage);
+    let (storage, replay) = WalStorage::open(&dir, 512).unwrap();
+    assert!(replay.corrupt.is_none());
+    assert_eq!(storage.get_promise(), ballot(39));
+    assert_eq!(storage.get_accepted_round(), Ballot::default());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//the calls of the records test, the entries stay in the memtable of the lsm storage
+fn write<B: Backend>(storage: &mut B) {
+    storage.set_promise(ballot(2));
+    storage.append_entries((0..3).map(entry).collect());
+    storage.set_accepted_round(ballot(2));
+    storage.set_decided_idx(2);
+}
+
+//Test 9: The engines count the records of the write-ahead log they read back alike, and
+//the lsm storage counts its runs apart from them.
+#[test]
+fn recovered_records_agree() {
+    let options = StorageOptions {
+        memtable_entries: 100,
+        ..options()
+    };
+    let wal = data_dir("storage-records-wal");
+    let lsm = data_dir("storage-records-lsm");
+    write(&mut WalStorage::open(&wal, options.segment_bytes).unwrap().0);
+    write(&mut LsmStorage::open(&lsm, &options).unwrap().0);
+    let (_, from_wal) = <WalStorage as Backend>::open(Some(&wal), &options).unwrap();
+    let (mut storage, from_lsm) = LsmStorage::open(&lsm, &options).unwrap();
+    assert!(from_wal.records > 0);
+    assert_eq!(from_lsm.records, from_wal.records);
+    assert_eq!((from_wal.runs, from_lsm.runs), (0, 0));
+
+    //once flushed, the entries are in a run and the write-ahead log starts over
+    storage.append_entries((3..100).map(entry).collect());
+    drop(storage);
+    let (_, recovery) = LsmStorage::open(&lsm, &options).unwrap();
+    assert!(recovery.runs > 0);
+    assert!(!recovery.is_new());
+    let _ = fs::remove_dir_all(&wal);
+    let _ = fs::remove_dir_all(&lsm);
+}
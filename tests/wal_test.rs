@@ -0,0 +1,319 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use omnipaxos_core::ballot_leader_election::Ballot;
+use omnipaxos_core::storage::Storage;
+
+#[allow(dead_code)]
+mod common;
+use common::kv::{Command, KVSnapshot, KeyValue, LogEntry, Store};
+use common::wal::{segments, LogState, Wal, WalRecord, WalStorage};
+
+fn entry(id: u64) -> LogEntry {
+    LogEntry {
+        id,
+        command: Command::Put(KeyValue {
+            key: format!("key{}", id % 7),
+            value: id,
+        }),
+    }
+}
+
+fn ballot(n: u32) -> Ballot {
+    Ballot {
+        n,
+        priority: 0,
+        pid: 1,
+    }
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+//the states compare by their json, the entries have no equality
+fn json(state: &LogState) -> String {
+    serde_json::to_string(state).unwrap()
+}
+
+fn wal_len(dir: &Path) -> u64 {
+    segments(dir)
+        .unwrap()
+        .iter()
+        .map(|(_, path)| fs::metadata(path).unwrap().len())
+        .sum()
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+//Test 1: Every change of the storage is replayed when the log is opened again.
+#[test]
+fn replay_after_reopen() {
+    let dir = data_dir("wal-replay");
+    let (mut storage, replay) = WalStorage::open(&dir, 1 << 20).unwrap();
+    assert_eq!(replay.records, 0);
+    storage.set_promise(ballot(2));
+    storage.append_entries((0..5).map(entry).collect());
+    storage.set_accepted_round(ballot(2));
+    storage.append_on_prefix(3, vec![entry(10)]);
+    storage.set_decided_idx(3);
+    let expected = json(storage.state());
+    drop(storage);
+
+    let (storage, replay) = WalStorage::open(&dir, 1 << 20).unwrap();
+    assert_eq!(replay.records, 5);
+    assert_eq!(json(storage.state()), expected);
+    assert_eq!(storage.get_log_len(), 4);
+    assert_eq!(storage.get_entries(3, 4)[0].id, 10);
+    assert_eq!(
+        (storage.get_promise(), storage.get_decided_idx()),
+        (ballot(2), 3)
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 2: Full segments are closed, and the segments covered by a compaction are deleted.
+#[test]
+fn rotation_and_compaction() {
+    let dir = data_dir("wal-rotation");
+    let (mut storage, _) = WalStorage::open(&dir, 256).unwrap();
+    for id in 0..40 {
+        storage.append_entry(entry(id));
+        storage.wal().sync().unwrap();
+    }
+    storage.set_decided_idx(40);
+    assert!(segments(&dir).unwrap().len() > 5);
+
+    let snapshot = KVSnapshot {
+        snapshotted: Store::default(),
+        delta: storage
+            .get_entries(0, 30)
+            .into_iter()
+            .map(|e| e.command)
+            .collect(),
+        len: 30,
+        ids: Default::default(),
+    };
+    storage.set_snapshot(snapshot);
+    storage.trim(30);
+    storage.set_compacted_idx(30);
+    assert_eq!(segments(&dir).unwrap().len(), 1);
+    storage.append_entry(entry(40));
+    let expected = json(storage.state());
+    drop(storage);
+
+    let (storage, replay) = WalStorage::open(&dir, 256).unwrap();
+    assert_eq!(replay.records, 2);
+    assert_eq!(json(storage.state()), expected);
+    assert_eq!(
+        (storage.get_compacted_idx(), storage.get_log_len()),
+        (30, 11)
+    );
+    assert_eq!(storage.get_snapshot().unwrap().len, 30);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 3: Concurrent appends share fsyncs and all of them are on disk once they return,
+//and the changes of the storage are queued and written with one fsync per batch.
+#[test]
+fn group_commit() {
+    let dir = data_dir("wal-group");
+    let (wal, _) = Wal::open(&dir, 1 << 20).unwrap();
+    let wal = Arc::new(wal);
+    let writers: Vec<_> = (0..8)
+        .map(|_| {
+            let wal = wal.clone();
+            thread::spawn(move || {
+                for index in 0..100 {
+                    wal.append(&WalRecord::Decided(index)).unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let syncs = wal.syncs();
+    assert!(syncs < 800, "{} fsyncs for 800 appends", syncs);
+    drop(wal);
+
+    let (_, replay) = Wal::open(&dir, 1 << 20).unwrap();
+    assert_eq!(replay.records, 800);
+    let _ = fs::remove_dir_all(&dir);
+
+    //the node syncs the storage once before it sends the messages of a batch
+    let (mut storage, _) = WalStorage::open(&dir, 1 << 20).unwrap();
+    for batch in 0..10 {
+        for index in 0..10 {
+            storage.set_decided_idx(batch * 10 + index);
+        }
+        assert_eq!(storage.wal().syncs(), batch);
+        storage.wal().sync().unwrap();
+    }
+    let syncs = storage.wal().syncs();
+    assert!(syncs < 100, "{} fsyncs for 100 changes", syncs);
+    assert_eq!(syncs, 10);
+    drop(storage);
+
+    let (storage, replay) = WalStorage::open(&dir, 1 << 20).unwrap();
+    assert_eq!(replay.records, 100);
+    assert_eq!(storage.get_decided_idx(), 99);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 4: A crash can cut the log at any offset, with or without garbage after the cut.
+//The log is then replayed up to the last whole record, and it takes new records after it.
+#[test]
+fn crash_at_random_offsets() {
+    let dir = data_dir("wal-crash");
+    let (mut storage, _) = WalStorage::open(&dir, 512).unwrap();
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    //the state after every change and the length of the log then
+    let mut history = vec![(json(storage.state()), 0)];
+    let mut id = 0;
+    for _ in 0..200 {
+        let len = storage.get_log_len();
+        match rng.next(5) {
+            0 => storage.set_promise(ballot(rng.next(10) as u32)),
+            1 => storage.set_accepted_round(ballot(rng.next(10) as u32)),
+            2 => storage.set_decided_idx(rng.next(len + 1)),
+            3 => {
+                let from = len - rng.next(len.min(3) + 1);
+                storage.append_on_prefix(from, vec![entry(id)]);
+            }
+            _ => {
+                let count = rng.next(4) + 1;
+                storage.append_entries((id..id + count).map(entry).collect());
+            }
+        }
+        id += 5;
+        storage.wal().sync().unwrap();
+        history.push((json(storage.state()), wal_len(&dir)));
+    }
+    drop(storage);
+    let total = wal_len(&dir);
+
+    let crashed = data_dir("wal-crashed");
+    for round in 0..100 {
+        let cut = rng.next(total + 1);
+        let _ = fs::remove_dir_all(&crashed);
+        fs::create_dir_all(&crashed).unwrap();
+        let mut start = 0;
+        for (_, path) in segments(&dir).unwrap() {
+            if start > cut {
+                break;
+            }
+            let mut bytes = fs::read(&path).unwrap();
+            let end = bytes.len() as u64;
+            if start + end > cut {
+                bytes.truncate((cut - start) as usize);
+                //a torn write may leave garbage behind the last whole record
+                if round % 2 == 1 {
+                    bytes.extend((0..rng.next(32) + 1).map(|_| rng.next(256) as u8));
+                }
+            }
+            fs::write(crashed.join(path.file_name().unwrap()), bytes).unwrap();
+            start += end;
+        }
+
+        let (mut storage, _) = WalStorage::open(&crashed, 512).unwrap();
+        let (expected, _) = history.iter().rev().find(|(_, len)| *len <= cut).unwrap();
+        assert_eq!(&json(storage.state()), expected, "cut at {}", cut);
+        storage.append_entry(entry(1000));
+        let expected = json(storage.state());
+        drop(storage);
+        let (storage, replay) = WalStorage::open(&crashed, 512).unwrap();
+        assert_eq!(json(storage.state()), expected, "cut at {}", cut);
+        assert!(replay.torn.is_none());
+    }
+    let _ = fs::remove_dir_all(&dir);
+    let _ = fs::remove_dir_all(&crashed);
+}
+
+//Test 5: A record corrupted in a segment before the last one cuts the log before it, the
+//segments after it are deleted and the log takes new records after the cut.
+#[test]
+fn corrupted_segment_is_cut() {
+    let dir = data_dir("wal-corrupt");
+    let (mut storage, _) = WalStorage::open(&dir, 256).unwrap();
+    let mut history = vec![(json(storage.state()), 0)];
+    for id in 0..40 {
+        storage.append_entry(entry(id));
+        storage.wal().sync().unwrap();
+        history.push((json(storage.state()), wal_len(&dir)));
+    }
+    drop(storage);
+    let files = segments(&dir).unwrap();
+    assert!(files.len() > 3);
+
+    let (second, path) = &files[1];
+    let mut bytes = fs::read(path).unwrap();
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 0x20;
+    fs::write(path, bytes).unwrap();
+    let before = fs::metadata(&files[0].1).unwrap().len();
+
+    let (mut storage, replay) = WalStorage::open(&dir, 256).unwrap();
+    let (segment, offset) = replay.corrupt.unwrap();
+    assert_eq!(segment, *second);
+    assert!(offset <= middle as u64);
+    let (expected, _) = history
+        .iter()
+        .find(|(_, len)| *len == before + offset)
+        .unwrap();
+    assert_eq!(&json(storage.state()), expected);
+    assert_eq!(segments(&dir).unwrap().len(), 2);
+
+    storage.append_entry(entry(100));
+    let expected = json(storage.state());
+    drop(storage);
+    let (storage, replay) = WalStorage::open(&dir, 256).unwrap();
+    assert!(replay.corrupt.is_none());
+    assert_eq!(json(storage.state()), expected);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+//Test 6: A record corrupted in the last segment with whole records after it is not torn
+//by a crash: the log is cut before it and the promise after it is kept.
+#[test]
+fn corrupted_last_segment_is_not_torn() {
+    let dir = data_dir("wal-corrupt-last");
+    let (mut storage, _) = WalStorage::open(&dir, 1 << 20).unwrap();
+    storage.append_entries((0..5).map(entry).collect());
+    storage.set_accepted_round(ballot(4));
+    storage.set_promise(ballot(9));
+    storage.wal().sync().unwrap();
+    drop(storage);
+    let files = segments(&dir).unwrap();
+    assert_eq!(files.len(), 1);
+
+    let (number, path) = &files[0];
+    let mut bytes = fs::read(path).unwrap();
+    bytes[10] ^= 0x20;
+    fs::write(path, bytes).unwrap();
+
+    let (storage, replay) = WalStorage::open(&dir, 1 << 20).unwrap();
+    assert!(replay.torn.is_none());
+    assert_eq!(replay.corrupt, Some((*number, 0)));
+    assert_eq!(storage.get_log_len(), 0);
+    assert_eq!(storage.get_promise(), ballot(9));
+    assert_eq!(storage.get_accepted_round(), Ballot::default());
+    drop(storage);
+    let (storage, replay) = WalStorage::open(&dir, 1 << 20).unwrap();
+    assert!(replay.corrupt.is_none() && replay.torn.is_none());
+    assert_eq!(storage.get_promise(), ballot(9));
+    let _ = fs::remove_dir_all(&dir);
+}